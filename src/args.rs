@@ -1,7 +1,11 @@
-use clap::{arg, ArgGroup, Parser};
+use crate::format::Palette;
+use crate::memory::Address;
+use crate::project::OperandDisplay;
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use std::io::IsTerminal;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_negates_reqs = true)]
 #[clap(group(
     ArgGroup::new("mode")
         .required(true)
@@ -16,7 +20,293 @@ pub(crate) struct Args {
     #[arg(short = 's', long = "simulate")]
     pub simulate: bool,
 
+    /// Follow control flow from the entry points instead of decoding linearly
+    #[arg(short = 'r', long = "recursive", requires = "disassemble")]
+    pub recursive: bool,
+
+    /// Hexadecimal address to start recursive disassembly from (defaults to 0)
+    #[arg(long = "entry", value_name = "ADDRESS", value_parser = parse_address)]
+    pub entry: Vec<Address>,
+
+    /// Print the control-flow graph of the traced code instead of a listing
+    #[arg(long = "cfg", value_name = "FORMAT", requires = "disassemble")]
+    pub cfg: Option<CfgFormat>,
+
+    /// Print the cross-reference report of the traced code instead of a listing
+    #[arg(long = "xrefs", requires = "disassemble")]
+    pub xrefs: bool,
+
+    /// Print each instruction with its bytes split into the fields of its encoding
+    #[arg(long = "explain", requires = "disassemble")]
+    pub explain: bool,
+
+    /// Write a NASM source tree that rebuilds the traced image to DIR, and verify it
+    #[arg(long = "export", value_name = "DIR", requires = "disassemble")]
+    pub export: Option<String>,
+
+    /// Fail the export if nasm is not installed to verify it with
+    #[arg(long = "verify", requires = "export")]
+    pub verify: bool,
+
+    /// Symbol file to take names and comments from, either a linker `.MAP` file or
+    /// lines of `address name [comment]`
+    #[arg(long = "symbols", value_name = "FILE")]
+    pub symbols: Vec<String>,
+
+    /// NASM `-l` or MASM `.LST` listing whose source lines are shown with the code they
+    /// assembled to
+    #[arg(long = "listing", value_name = "FILE")]
+    pub listings: Vec<String>,
+
+    /// How to load FILE: `auto` loads files starting with `MZ` as executables, picks COM,
+    /// Intel HEX, S-record, OMF object, library, disk image, ROM or memory map loading by the
+    /// extension and loads anything else as a flat binary
+    #[arg(long = "image", value_name = "KIND", default_value = "auto")]
+    pub image: ImageKind,
+
+    /// Disassemble only the library module with this name, or the one defining this public
+    #[arg(long = "module", value_name = "NAME", requires = "disassemble")]
+    pub module: Option<String>,
+
+    /// List the library's modules and their public names instead of disassembling them
+    #[arg(long = "list-modules", requires = "disassemble")]
+    pub list_modules: bool,
+
+    /// Hexadecimal BIOS number of the drive a boot sector is loaded from (defaults to 00 for
+    /// floppy images and 80 for hard disk images)
+    #[arg(long = "drive", value_name = "DRIVE", value_parser = parse_drive)]
+    pub drive: Option<u8>,
+
+    /// Hexadecimal segment a ROM is placed at (defaults to C000 for option ROMs and to the
+    /// segment that makes a system ROM end at the top of memory)
+    #[arg(long = "rom-segment", value_name = "SEGMENT", value_parser = parse_segment)]
+    pub rom_segment: Option<u16>,
+
+    /// Command line passed to the simulated program in its PSP
+    #[arg(
+        long = "args",
+        value_name = "ARGUMENTS",
+        default_value = "",
+        requires = "simulate"
+    )]
+    pub arguments: String,
+
+    /// Hexadecimal segment an executable or COM program is loaded at for simulation
+    #[arg(long = "load-segment", value_name = "SEGMENT", value_parser = parse_segment, default_value = "1000")]
+    pub load_segment: u16,
+
+    /// When to colour the listing
+    #[arg(long = "color", value_name = "WHEN", default_value = "auto")]
+    pub color: ColorChoice,
+
+    /// Output format of the disassembly
+    #[arg(long = "format", value_name = "FORMAT", default_value = "text")]
+    pub format: OutputFormat,
+
     /// Input file for disassembly or simulation
+    #[arg(value_name = "FILE", required = true)]
+    pub file: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub(crate) enum Command {
+    /// Add entries to the project file kept next to the binary, which disassembly honours
+    Annotate(Annotate),
+    /// Compare the traced code of two binaries function by function and block by block
+    Diff(Diff),
+}
+
+#[derive(clap::Args)]
+pub(crate) struct Annotate {
+    /// Name an address
+    #[arg(long = "name", value_name = "ADDRESS=NAME", value_parser = parse_assignment)]
+    pub names: Vec<(Address, String)>,
+
+    /// Comment an address
+    #[arg(long = "comment", value_name = "ADDRESS=TEXT", value_parser = parse_assignment)]
+    pub comments: Vec<(Address, String)>,
+
+    /// Decode a range as code, given as hexadecimal START-END with END excluded
+    #[arg(long = "code", value_name = "RANGE", value_parser = parse_range)]
+    pub code: Vec<(Address, Address)>,
+
+    /// Keep a range as data, given as hexadecimal START-END with END excluded
+    #[arg(long = "data", value_name = "RANGE", value_parser = parse_range)]
+    pub data: Vec<(Address, Address)>,
+
+    /// Add an entry point
+    #[arg(long = "entry", value_name = "ADDRESS", value_parser = parse_address)]
+    pub entry: Vec<Address>,
+
+    /// Set how the immediate of the instruction at ADDRESS is printed
+    #[arg(long = "operand", value_name = "ADDRESS=DISPLAY", value_parser = parse_operand_display)]
+    pub operands: Vec<(Address, OperandDisplay)>,
+
+    /// Binary whose project file is updated
     #[arg(value_name = "FILE")]
     pub file: String,
 }
+
+#[derive(clap::Args)]
+pub(crate) struct Diff {
+    /// Hexadecimal address to trace both binaries from (defaults to 0)
+    #[arg(long = "entry", value_name = "ADDRESS", value_parser = parse_address)]
+    pub entry: Vec<Address>,
+
+    /// Binary before the change
+    #[arg(value_name = "OLD")]
+    pub old: String,
+
+    /// Binary after the change
+    #[arg(value_name = "NEW")]
+    pub new: String,
+}
+
+impl Args {
+    /// Whether disassembly needs control flow traced rather than a linear decode.
+    pub fn traces(&self) -> bool {
+        self.recursive
+            || self.cfg.is_some()
+            || self.xrefs
+            || self.export.is_some()
+            || !self.symbols.is_empty()
+            || self.format == OutputFormat::Html
+    }
+
+    /// Kind of image FILE holds, looking at its contents and name for `--image auto`.
+    pub fn image_kind(&self, path: &str, bytes: &[u8]) -> ImageKind {
+        match self.image {
+            ImageKind::Auto => ImageKind::detect(path, bytes),
+            kind => kind,
+        }
+    }
+
+    /// Colours are used with `--color=auto` only when stdout is a terminal.
+    pub fn palette(&self) -> Palette {
+        let colored = match self.color {
+            ColorChoice::Auto => std::io::stdout().is_terminal(),
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        };
+        if colored {
+            Palette::Ansi
+        } else {
+            Palette::Plain
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    Text,
+    Json,
+    /// Self-contained HTML report of the traced code
+    Html,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ImageKind {
+    Auto,
+    /// Raw bytes loaded at address 0
+    Flat,
+    /// DOS MZ executable
+    Mz,
+    /// DOS COM program, loaded at offset 0x100 after a PSP
+    Com,
+    /// Intel HEX records
+    Hex,
+    /// Motorola S-records
+    Srec,
+    /// OMF object module, disassembled segment by segment
+    Obj,
+    /// Library of OMF object modules
+    Lib,
+    /// Boot sector, or the first sector of a floppy or hard disk image, loaded at 0000:7C00
+    Boot,
+    /// System ROM started at the FFFF:0000 reset vector, or option ROM started after its
+    /// 55AA header
+    Rom,
+    /// JSON memory map placing several files at their own addresses
+    MemoryMap,
+}
+
+impl ImageKind {
+    /// Executables by their `MZ` signature, everything else by the file extension.
+    pub fn detect(path: &str, bytes: &[u8]) -> ImageKind {
+        if bytes.starts_with(b"MZ") || bytes.starts_with(b"ZM") {
+            return ImageKind::Mz;
+        }
+        let extension = path
+            .rsplit_once('.')
+            .map(|(_, extension)| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("com") => ImageKind::Com,
+            Some("hex" | "ihx") => ImageKind::Hex,
+            Some("s19" | "s28" | "s37" | "srec" | "mot") => ImageKind::Srec,
+            Some("obj") => ImageKind::Obj,
+            Some("lib") => ImageKind::Lib,
+            Some("rom") => ImageKind::Rom,
+            Some("json") => ImageKind::MemoryMap,
+            Some("img" | "ima" | "vfd" | "bin" | "mbr") if is_boot_sector(bytes) => ImageKind::Boot,
+            _ => ImageKind::Flat,
+        }
+    }
+}
+
+/// Whether the first sector ends in the boot signature.
+fn is_boot_sector(bytes: &[u8]) -> bool {
+    crate::boot::boot_sector(bytes).is_ok()
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub(crate) enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub(crate) enum CfgFormat {
+    Dot,
+    Json,
+}
+
+fn parse_address(s: &str) -> Result<Address, std::num::ParseIntError> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map(Address)
+}
+
+fn parse_drive(s: &str) -> Result<u8, std::num::ParseIntError> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u8::from_str_radix(digits, 16)
+}
+
+fn parse_segment(s: &str) -> Result<u16, std::num::ParseIntError> {
+    parse_address(s).map(|address| address.0)
+}
+
+fn parse_assignment(s: &str) -> Result<(Address, String), String> {
+    let (address, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected ADDRESS=VALUE, got {s:?}"))?;
+    let address = parse_address(address).map_err(|error| error.to_string())?;
+    Ok((address, value.to_string()))
+}
+
+fn parse_range(s: &str) -> Result<(Address, Address), String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("expected START-END, got {s:?}"))?;
+    let start = parse_address(start).map_err(|error| error.to_string())?;
+    let end = parse_address(end).map_err(|error| error.to_string())?;
+    Ok((start, end))
+}
+
+fn parse_operand_display(s: &str) -> Result<(Address, OperandDisplay), String> {
+    let (address, display) = parse_assignment(s)?;
+    let display = OperandDisplay::from_str(&display, true)?;
+    Ok((address, display))
+}
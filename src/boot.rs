@@ -0,0 +1,246 @@
+use crate::error::Error;
+use crate::memory::Address;
+use std::fmt::Write;
+
+/// Where the BIOS loads the boot sector, as an offset in segment 0.
+pub const LOAD_ADDRESS: u16 = 0x7C00;
+pub const SECTOR_SIZE: usize = 512;
+
+/// Last two bytes of a bootable sector.
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Field of the BIOS Parameter Block, at its offset in the sector.
+pub struct Field {
+    pub offset: u16,
+    pub size: u8,
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+const fn field(offset: u16, size: u8, name: &'static str, description: &'static str) -> Field {
+    Field {
+        offset,
+        size,
+        name,
+        description,
+    }
+}
+
+/// The BPB shared by FAT12, FAT16 and FAT32, after the jump at the start of the sector.
+const COMMON: &[Field] = &[
+    field(0x03, 8, "bpb_oem_name", "OEM name"),
+    field(0x0B, 2, "bpb_bytes_per_sector", "bytes per sector"),
+    field(0x0D, 1, "bpb_sectors_per_cluster", "sectors per cluster"),
+    field(0x0E, 2, "bpb_reserved_sectors", "reserved sectors"),
+    field(0x10, 1, "bpb_fat_count", "number of FATs"),
+    field(0x11, 2, "bpb_root_entries", "root directory entries"),
+    field(0x13, 2, "bpb_total_sectors", "total sectors if below 65536"),
+    field(0x15, 1, "bpb_media", "media descriptor"),
+    field(0x16, 2, "bpb_sectors_per_fat", "sectors per FAT"),
+    field(0x18, 2, "bpb_sectors_per_track", "sectors per track"),
+    field(0x1A, 2, "bpb_heads", "number of heads"),
+    field(
+        0x1C,
+        4,
+        "bpb_hidden_sectors",
+        "sectors before the partition",
+    ),
+    field(0x20, 4, "bpb_total_sectors_32", "total sectors"),
+];
+
+/// Extended BPB of FAT12 and FAT16.
+const EXTENDED: &[Field] = &[
+    field(0x24, 1, "bpb_drive_number", "BIOS drive number"),
+    field(0x25, 1, "bpb_reserved", "reserved"),
+    field(0x26, 1, "bpb_signature", "extended boot signature"),
+    field(0x27, 4, "bpb_volume_id", "volume serial number"),
+    field(0x2B, 11, "bpb_volume_label", "volume label"),
+    field(0x36, 8, "bpb_file_system", "file system type"),
+];
+
+/// Extended BPB of FAT32.
+const FAT32: &[Field] = &[
+    field(0x24, 4, "bpb_sectors_per_fat_32", "sectors per FAT"),
+    field(0x28, 2, "bpb_fat_flags", "FAT mirroring flags"),
+    field(0x2A, 2, "bpb_version", "file system version"),
+    field(
+        0x2C,
+        4,
+        "bpb_root_cluster",
+        "first cluster of the root directory",
+    ),
+    field(0x30, 2, "bpb_fs_info", "sector of the FSInfo structure"),
+    field(
+        0x32,
+        2,
+        "bpb_backup_boot",
+        "sector of the backup boot sector",
+    ),
+    field(0x34, 12, "bpb_reserved", "reserved"),
+    field(0x40, 1, "bpb_drive_number", "BIOS drive number"),
+    field(0x41, 1, "bpb_reserved_nt", "reserved"),
+    field(0x42, 1, "bpb_signature", "extended boot signature"),
+    field(0x43, 4, "bpb_volume_id", "volume serial number"),
+    field(0x47, 11, "bpb_volume_label", "volume label"),
+    field(0x52, 8, "bpb_file_system", "file system type"),
+];
+
+/// The boot signature itself, kept as data at the end of the sector.
+const BOOT_SIGNATURE: Field = field(0x1FE, 2, "boot_signature", "boot signature");
+
+/// The first sector of a disk image, which must end in the `55AA` boot signature.
+pub fn boot_sector(disk: &[u8]) -> Result<&[u8], Error> {
+    let sector = disk
+        .get(..SECTOR_SIZE)
+        .ok_or(Error::InvalidBootSector("shorter than a sector"))?;
+    if sector[SECTOR_SIZE - 2..] != SIGNATURE {
+        return Err(Error::InvalidBootSector("missing 55AA signature"));
+    }
+    Ok(sector)
+}
+
+/// Fields of the sector's BIOS Parameter Block and its boot signature, at the addresses the
+/// sector is loaded to.
+///
+/// A BPB is only assumed when the sector starts with a jump over it and gives a plausible
+/// sector size. FAT32 is told apart by its 16-bit FAT size and root directory size being
+/// zero.
+pub fn data_fields(sector: &[u8]) -> Vec<(Address, &'static Field)> {
+    let word = |offset: usize| u16::from_le_bytes([sector[offset], sector[offset + 1]]);
+    let jumps = matches!(sector[0], 0xEB | 0xE9);
+    let bytes_per_sector = word(0x0B);
+    let has_bpb = jumps && bytes_per_sector.is_power_of_two() && bytes_per_sector >= 128;
+
+    let mut fields: Vec<&Field> = Vec::new();
+    if has_bpb {
+        fields.extend(COMMON);
+        if word(0x16) == 0 && word(0x11) == 0 {
+            fields.extend(FAT32);
+        } else if matches!(sector[0x26], 0x28 | 0x29) {
+            fields.extend(EXTENDED);
+        }
+    }
+    fields.push(&BOOT_SIGNATURE);
+
+    fields
+        .into_iter()
+        .map(|field| (Address(LOAD_ADDRESS + field.offset), field))
+        .collect()
+}
+
+/// Layout the BIOS addresses the disk with through `int 13h`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Geometry {
+    pub cylinders: usize,
+    pub heads: usize,
+    pub sectors: usize,
+}
+
+impl Geometry {
+    /// Geometry of the BPB if the boot sector has one, otherwise that of the standard floppy
+    /// of the image's size, otherwise the 16 heads and 63 sectors of a hard disk.
+    pub fn of(disk: &[u8]) -> Geometry {
+        let sectors_total = disk.len().div_ceil(SECTOR_SIZE).max(1);
+        let (heads, sectors) = match bpb_geometry(disk) {
+            Some(geometry) => geometry,
+            None => match disk.len() / 1024 {
+                160 | 180 => (1, disk.len() / 1024 / 20),
+                320 | 360 => (2, disk.len() / 1024 / 40),
+                720 => (2, 9),
+                1200 => (2, 15),
+                1440 => (2, 18),
+                2880 => (2, 36),
+                _ => (16, 63),
+            },
+        };
+        Geometry {
+            cylinders: sectors_total.div_ceil(heads * sectors),
+            heads,
+            sectors,
+        }
+    }
+
+    /// Floppies are the disks with at most two heads, which the BIOS numbers from 0.
+    pub fn is_floppy(&self) -> bool {
+        self.heads <= 2
+    }
+
+    /// Logical block address of a cylinder, head and sector numbered from 1.
+    pub fn lba(&self, cylinder: usize, head: usize, sector: usize) -> Option<usize> {
+        if sector == 0 || sector > self.sectors || head >= self.heads {
+            return None;
+        }
+        Some((cylinder * self.heads + head) * self.sectors + sector - 1)
+    }
+}
+
+fn bpb_geometry(disk: &[u8]) -> Option<(usize, usize)> {
+    let sector = disk.get(..SECTOR_SIZE)?;
+    if !data_fields(sector)
+        .iter()
+        .any(|(_, field)| field.offset == 0x18)
+    {
+        return None;
+    }
+    let sectors = u16::from_le_bytes([sector[0x18], sector[0x19]]) as usize;
+    let heads = u16::from_le_bytes([sector[0x1A], sector[0x1B]]) as usize;
+    (sectors > 0 && sectors < 64 && heads > 0 && heads <= 256).then_some((heads, sectors))
+}
+
+/// Load address, drive and disk geometry as assembly comments, for the top of a listing.
+pub fn describe(disk: &[u8], drive: u8) -> String {
+    let mut text = String::new();
+    let _ = writeln!(
+        text,
+        "; boot sector loaded at 0000:{LOAD_ADDRESS:04x} from drive {drive:#04x}"
+    );
+    let geometry = Geometry::of(disk);
+    let _ = writeln!(
+        text,
+        "; disk of {} bytes: {} cylinders, {} heads, {} sectors per track",
+        disk.len(),
+        geometry.cylinders,
+        geometry.heads,
+        geometry.sectors
+    );
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::boot::{boot_sector, data_fields, Geometry};
+    use crate::memory::Address;
+
+    #[test]
+    fn test_boot_sector() -> anyhow::Result<()> {
+        let mut disk = vec![0; 1440 * 1024];
+        disk[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+        disk[0x0B..0x0D].copy_from_slice(&512u16.to_le_bytes());
+        disk[0x16] = 9;
+        disk[0x18] = 18;
+        disk[0x1A] = 2;
+        disk[0x26] = 0x29;
+        assert!(boot_sector(&disk).is_err());
+        disk[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        let fields = data_fields(boot_sector(&disk)?);
+        assert_eq!(fields.len(), 20);
+        assert_eq!(fields[0].0, Address(0x7C03));
+        assert_eq!(fields[18].1.name, "bpb_file_system");
+        assert_eq!(fields[19].0, Address(0x7DFE));
+
+        let geometry = Geometry::of(&disk);
+        assert_eq!(
+            geometry,
+            Geometry {
+                cylinders: 80,
+                heads: 2,
+                sectors: 18
+            }
+        );
+        assert_eq!(geometry.lba(1, 0, 1), Some(36));
+        assert_eq!(geometry.lba(0, 0, 0), None);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,231 @@
+use crate::flow::Program;
+use crate::format::Palette;
+use crate::listing::format_instruction;
+use crate::memory::Address;
+use crate::symbols::Symbols;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+#[derive(Serialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeKind {
+    FallThrough,
+    Taken,
+    Loop,
+    Call,
+}
+
+#[derive(Serialize)]
+pub struct Edge {
+    pub from: Address,
+    pub to: Address,
+    pub kind: EdgeKind,
+}
+
+#[derive(Serialize)]
+pub struct BlockInstruction {
+    pub address: Address,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct BasicBlock {
+    pub start: Address,
+    pub label: Option<String>,
+    pub instructions: Vec<BlockInstruction>,
+}
+
+#[derive(Serialize)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<Edge>,
+}
+
+impl ControlFlowGraph {
+    /// Splits the traced instructions into basic blocks.
+    ///
+    /// A block starts at a branch target, after a branch or return, or where code resumes
+    /// after data, and ends where the next block starts.
+    pub fn build(program: &Program, symbols: &Symbols) -> ControlFlowGraph {
+        let mut leaders = BTreeSet::new();
+        let mut next_address = None;
+        for (address, instruction) in program.instructions() {
+            if next_address != Some(address) {
+                leaders.insert(address);
+            }
+            let next = Address(address.0.wrapping_add(instruction.get_size() as u16));
+            if instruction.to_jump().is_some() {
+                leaders.extend(program.branch_target(address, instruction));
+                leaders.insert(next);
+            }
+            if !instruction.falls_through() {
+                leaders.insert(next);
+            }
+            next_address = Some(next);
+        }
+
+        let mut blocks: Vec<BasicBlock> = Vec::new();
+        let mut edges = Vec::new();
+
+        let mut instructions = program.instructions().peekable();
+        while let Some((address, instruction)) = instructions.next() {
+            if leaders.contains(&address) {
+                blocks.push(BasicBlock {
+                    start: address,
+                    label: symbols.label(address).map(str::to_string),
+                    instructions: Vec::new(),
+                });
+            }
+            let block = blocks
+                .last_mut()
+                .expect("The first instruction is a leader");
+            block.instructions.push(BlockInstruction {
+                address,
+                text: format_instruction(address, instruction, symbols, Palette::Plain),
+            });
+
+            let next = Address(address.0.wrapping_add(instruction.get_size() as u16));
+            let next_is_instruction = instructions.peek().map(|(a, _)| *a) == Some(next);
+            let ends_block = !next_is_instruction || leaders.contains(&next);
+            if !ends_block {
+                continue;
+            }
+
+            let from = block.start;
+            if let (Some(jmp), Some(target)) = (
+                instruction.to_jump(),
+                program.branch_target(address, instruction),
+            ) {
+                let kind = if jmp.is_call() {
+                    EdgeKind::Call
+                } else if jmp.is_loop() {
+                    EdgeKind::Loop
+                } else {
+                    EdgeKind::Taken
+                };
+                if program.items.contains_key(&target) {
+                    edges.push(Edge {
+                        from,
+                        to: target,
+                        kind,
+                    });
+                }
+            }
+            if instruction.falls_through() && next_is_instruction {
+                edges.push(Edge {
+                    from,
+                    to: next,
+                    kind: EdgeKind::FallThrough,
+                });
+            }
+        }
+
+        ControlFlowGraph { blocks, edges }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph cfg {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in &self.blocks {
+            let mut text = String::new();
+            if let Some(label) = &block.label {
+                let _ = write!(text, "{label}:\\l");
+            }
+            for instruction in &block.instructions {
+                let _ = write!(
+                    text,
+                    "{:04x}: {}\\l",
+                    instruction.address.0,
+                    escape_dot(&instruction.text)
+                );
+            }
+            let _ = writeln!(dot, "    \"{:04x}\" [label=\"{text}\"];", block.start.0);
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::FallThrough => "label=\"fall through\", style=dashed",
+                EdgeKind::Taken => "label=\"taken\", color=darkgreen",
+                EdgeKind::Loop => "label=\"loop\", color=blue",
+                EdgeKind::Call => "label=\"call\", color=red",
+            };
+            let _ = writeln!(
+                dot,
+                "    \"{:04x}\" -> \"{:04x}\" [{style}];",
+                edge.from.0, edge.to.0
+            );
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn escape_dot(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cfg::{ControlFlowGraph, EdgeKind};
+    use crate::flow::{trace, Layout};
+    use crate::functions::discover;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn test_build() {
+        let bin = [
+            0xB9, 0x03, 0x00, // mov cx, 3
+            0xE8, 0x05, 0x00, // call 000B
+            0xE2, 0xFB, // loop 0003
+            0x74, 0x01, // je 000B
+            0xC3, // ret
+            0xB8, 0x01, 0x00, // mov ax, 1
+            0xC3, // ret
+        ];
+        let program = trace(&Image::flat(&bin), &[Address(0)], &Layout::default());
+        let symbols = Symbols::new(&program, &discover(&program));
+        let cfg = ControlFlowGraph::build(&program, &symbols);
+
+        let blocks: Vec<(u16, usize)> = cfg
+            .blocks
+            .iter()
+            .map(|block| (block.start.0, block.instructions.len()))
+            .collect();
+        assert_eq!(
+            blocks,
+            vec![(0, 1), (3, 1), (6, 1), (8, 1), (0xA, 1), (0xB, 2)]
+        );
+        assert_eq!(cfg.blocks[5].label.as_deref(), Some("sub_000B"));
+
+        let edges: Vec<(u16, u16, EdgeKind)> = cfg
+            .edges
+            .iter()
+            .map(|edge| (edge.from.0, edge.to.0, edge.kind))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![
+                (0, 3, EdgeKind::FallThrough),
+                (3, 0xB, EdgeKind::Call),
+                (3, 6, EdgeKind::FallThrough),
+                (6, 3, EdgeKind::Loop),
+                (6, 8, EdgeKind::FallThrough),
+                (8, 0xB, EdgeKind::Taken),
+                (8, 0xA, EdgeKind::FallThrough),
+            ]
+        );
+
+        let dot = cfg.to_dot();
+        assert!(dot.contains("    \"0006\" -> \"0003\" [label=\"loop\", color=blue];\n"));
+    }
+}
@@ -1,11 +1,40 @@
 use std::fmt;
 
+use crate::boot::{self, Geometry, SECTOR_SIZE};
 use crate::decode::{decode_instruction, CountingPeekable};
+use crate::image::Image;
 use crate::instruction::Instruction;
-use crate::memory::Address;
+use crate::memory::{Address, Displacement, Memory};
+use crate::memory_map::MemoryMap;
+use crate::mz::Executable;
 use crate::operand::Operand;
 use crate::register::Register;
+use crate::rom::Rom;
+use crate::symbols::Symbols;
 use anyhow::Result;
+use std::ops::Range;
+
+/// Size of the 8086 address space.
+const MEMORY_SIZE: usize = 0x10_0000;
+
+/// Indices into the segment registers, in the order the `sreg` field numbers them.
+const ES: usize = 0;
+const CS: usize = 1;
+const SS: usize = 2;
+const DS: usize = 3;
+
+/// Paragraphs of the program segment prefix DOS puts in front of a loaded program.
+const PSP_PARAGRAPHS: u16 = 0x10;
+
+/// Segment just past conventional memory, given to the program as the end of its memory.
+const TOP_OF_MEMORY: u16 = 0xA000;
+
+/// Offset of the command tail in the PSP: a length byte, then the text ending in `\r`.
+const COMMAND_TAIL: usize = 0x80;
+const MAX_COMMAND_TAIL: usize = 126;
+
+/// Where a COM program starts in its segment, past the PSP.
+const COM_ORIGIN: u16 = 0x100;
 
 #[derive(Clone, PartialEq, Eq)]
 pub struct CpuStateFlags {
@@ -15,6 +44,11 @@ pub struct CpuStateFlags {
 impl CpuStateFlags {
     const ZERO_FLAG_MASK: u8 = 0b0000_0001;
     const SIGN_FLAG_MASK: u8 = 0b0000_0010;
+    const CARRY_FLAG_MASK: u8 = 0b0000_0100;
+    const OVERFLOW_FLAG_MASK: u8 = 0b0000_1000;
+    const DIRECTION_FLAG_MASK: u8 = 0b0001_0000;
+    const INTERRUPT_FLAG_MASK: u8 = 0b0010_0000;
+    const PARITY_FLAG_MASK: u8 = 0b0100_0000;
 
     pub fn new() -> CpuStateFlags {
         CpuStateFlags { flags: 0 }
@@ -43,36 +77,348 @@ impl CpuStateFlags {
     pub fn get_sign_flag(&self) -> bool {
         (self.flags & Self::SIGN_FLAG_MASK) != 0
     }
+
+    pub fn set_carry_flag(&mut self, v: bool) {
+        if v {
+            self.flags |= Self::CARRY_FLAG_MASK;
+        } else {
+            self.flags &= !Self::CARRY_FLAG_MASK;
+        }
+    }
+
+    pub fn get_carry_flag(&self) -> bool {
+        (self.flags & Self::CARRY_FLAG_MASK) != 0
+    }
+
+    pub fn set_overflow_flag(&mut self, v: bool) {
+        if v {
+            self.flags |= Self::OVERFLOW_FLAG_MASK;
+        } else {
+            self.flags &= !Self::OVERFLOW_FLAG_MASK;
+        }
+    }
+
+    pub fn get_overflow_flag(&self) -> bool {
+        (self.flags & Self::OVERFLOW_FLAG_MASK) != 0
+    }
+
+    pub fn set_parity_flag(&mut self, v: bool) {
+        if v {
+            self.flags |= Self::PARITY_FLAG_MASK;
+        } else {
+            self.flags &= !Self::PARITY_FLAG_MASK;
+        }
+    }
+
+    pub fn get_parity_flag(&self) -> bool {
+        (self.flags & Self::PARITY_FLAG_MASK) != 0
+    }
+
+    pub fn set_direction_flag(&mut self, v: bool) {
+        if v {
+            self.flags |= Self::DIRECTION_FLAG_MASK;
+        } else {
+            self.flags &= !Self::DIRECTION_FLAG_MASK;
+        }
+    }
+
+    pub fn get_direction_flag(&self) -> bool {
+        (self.flags & Self::DIRECTION_FLAG_MASK) != 0
+    }
+
+    pub fn set_interrupt_flag(&mut self, v: bool) {
+        if v {
+            self.flags |= Self::INTERRUPT_FLAG_MASK;
+        } else {
+            self.flags &= !Self::INTERRUPT_FLAG_MASK;
+        }
+    }
+
+    pub fn get_interrupt_flag(&self) -> bool {
+        (self.flags & Self::INTERRUPT_FLAG_MASK) != 0
+    }
+
+    /// Flags from the FLAGS register as `iret` pops it.
+    pub fn from_word(word: u16) -> CpuStateFlags {
+        let mut flags = CpuStateFlags::new();
+        flags.set_overflow_flag(word & 0x0800 != 0);
+        flags.set_carry_flag(word & 0x0001 != 0);
+        flags.set_zero_flag(word & 0x0040 != 0);
+        flags.set_sign_flag(word & 0x0080 != 0);
+        flags.set_parity_flag(word & 0x0004 != 0);
+        flags.set_direction_flag(word & 0x0400 != 0);
+        flags.set_interrupt_flag(word & 0x0200 != 0);
+        flags
+    }
 }
 
 impl fmt::Display for CpuStateFlags {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut flags_str = String::new();
-        if self.flags & Self::ZERO_FLAG_MASK != 0 {
+        if self.get_zero_flag() {
             flags_str.push('Z');
         }
-        if self.flags & Self::SIGN_FLAG_MASK != 0 {
+        if self.get_sign_flag() {
             flags_str.push('S');
         }
+        if self.get_carry_flag() {
+            flags_str.push('C');
+        }
+        if self.get_overflow_flag() {
+            flags_str.push('O');
+        }
+        if self.get_parity_flag() {
+            flags_str.push('P');
+        }
+        if self.get_direction_flag() {
+            flags_str.push('D');
+        }
+        if self.get_interrupt_flag() {
+            flags_str.push('I');
+        }
         write!(f, "{}", flags_str)
     }
 }
 
+/// Whether a result sets the parity flag, which only looks at its low byte and is set when
+/// an even number of its bits are.
+fn even_parity(result: u8) -> bool {
+    result.count_ones().is_multiple_of(2)
+}
+
+/// Two-operand arithmetic the ALU performs, setting the flags from the result.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Alu {
+    Add,
+    /// Adds the carry flag too.
+    Adc,
+    Sub,
+    /// Subtracts the carry flag too.
+    Sbb,
+    /// Subtracts only to set the flags.
+    Cmp,
+    Or,
+    Xor,
+}
+
 pub struct CpuState<'a> {
-    instructions: &'a [u8],
+    memory: Vec<u8>,
+    /// Linear addresses the program was loaded to, with the PSP of a DOS program. Execution
+    /// stops when it leaves them.
+    image: Vec<Range<usize>>,
+    /// Linear address the listing counts from.
+    origin: usize,
     instruction_pointer: u16,
     registers: [u16; 8],
+    segments: [u16; 4],
     flags: CpuStateFlags,
+    /// Linear addresses that ignore writes, and those of devices, which also read as `FF`.
+    read_only: Vec<Range<usize>>,
+    mmio: Vec<Range<usize>>,
+    /// Disk image `int 13h` reads from, with the BIOS number of its drive.
+    disk: Vec<u8>,
+    drive: u8,
+    /// Set once the program ends through DOS, which stops execution.
+    exit_code: Option<u8>,
+    symbols: &'a Symbols,
 }
 
 impl<'a> CpuState<'a> {
-    pub fn new(instructions: &'a [u8]) -> Self {
-        CpuState {
-            instructions,
-            instruction_pointer: 0,
+    /// Places every region of the image at its linear address, with every segment register
+    /// set to the segment the image starts in and `ip` at the image's start address, or at
+    /// its first byte when it has none.
+    pub fn new(image: &Image, symbols: &'a Symbols) -> Result<Self> {
+        if image.end() as usize > MEMORY_SIZE {
+            return Err(crate::error::Error::ImageTooLarge(image.end() as usize).into());
+        }
+
+        let mut memory = vec![0; MEMORY_SIZE];
+        let mut loaded = Vec::new();
+        for (start, data) in image.regions() {
+            let start = start as usize;
+            memory[start..start + data.len()].copy_from_slice(data);
+            loaded.push(start..start + data.len());
+        }
+
+        let segment = image.segment();
+        let origin = segment as usize * 16;
+        let entry = image.entry.unwrap_or(image.start()) as usize;
+
+        Ok(CpuState {
+            memory,
+            image: loaded,
+            origin,
+            instruction_pointer: entry.wrapping_sub(origin) as u16,
             registers: [0; 8],
+            segments: [segment; 4],
+            flags: CpuStateFlags::new(),
+            read_only: Vec::new(),
+            mmio: Vec::new(),
+            disk: Vec::new(),
+            drive: 0,
+            exit_code: None,
+            symbols,
+        })
+    }
+
+    /// Loads the executable's image at `load_segment` with its relocations applied, the
+    /// way DOS does after a program segment prefix: `ds` and `es` point at the prefix and
+    /// `cs:ip` and `ss:sp` come from the header. The prefix needs `load_segment` to be at
+    /// least `0010`.
+    pub fn load_executable(
+        executable: &Executable,
+        load_segment: u16,
+        arguments: &str,
+        symbols: &'a Symbols,
+    ) -> Result<Self> {
+        if load_segment < PSP_PARAGRAPHS {
+            return Err(crate::error::Error::LoadSegmentTooLow(load_segment).into());
+        }
+        let image = executable.relocated(load_segment);
+        let start = load_segment as usize * 16;
+        let end = start + image.len();
+        if end > MEMORY_SIZE {
+            return Err(crate::error::Error::ImageTooLarge(end).into());
+        }
+
+        let header = &executable.header;
+        let psp = load_segment - PSP_PARAGRAPHS;
+
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[start..end].copy_from_slice(&image);
+        let psp_start = psp as usize * 16;
+        memory[psp_start..psp_start + 0x100].copy_from_slice(&program_segment_prefix(arguments)?);
+        let mut registers = [0; 8];
+        registers[4] = header.sp;
+
+        let mut segments = [0; 4];
+        segments[ES] = psp;
+        segments[CS] = load_segment.wrapping_add(header.cs);
+        segments[SS] = load_segment.wrapping_add(header.ss);
+        segments[DS] = psp;
+
+        Ok(CpuState {
+            memory,
+            image: vec![psp_start..psp_start + 0x100, start..end],
+            origin: start,
+            instruction_pointer: header.ip,
+            registers,
+            segments,
             flags: CpuStateFlags::new(),
+            read_only: Vec::new(),
+            mmio: Vec::new(),
+            disk: Vec::new(),
+            drive: 0,
+            exit_code: None,
+            symbols,
+        })
+    }
+
+    /// Loads a COM program the way DOS does: the PSP at `segment:0000` and the program right
+    /// after it at `segment:0100`, every segment register set to `segment`, and a zero word
+    /// pushed at `FFFE` so that a final `ret` reaches the `int 20h` at the start of the PSP.
+    pub fn load_com(
+        program: &[u8],
+        segment: u16,
+        arguments: &str,
+        symbols: &'a Symbols,
+    ) -> Result<Self> {
+        let psp_start = segment as usize * 16;
+        let start = psp_start + COM_ORIGIN as usize;
+        let end = start + program.len();
+        if program.len() > 0x10000 - COM_ORIGIN as usize - 2 {
+            return Err(crate::error::Error::ImageTooLarge(program.len()).into());
+        }
+        if psp_start + 0x10000 > MEMORY_SIZE {
+            let available = MEMORY_SIZE - psp_start;
+            return Err(crate::error::Error::LoadSegmentTooHigh(segment, available).into());
         }
+
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[psp_start..start].copy_from_slice(&program_segment_prefix(arguments)?);
+        memory[start..end].copy_from_slice(program);
+
+        let mut registers = [0; 8];
+        registers[4] = 0xFFFE;
+
+        Ok(CpuState {
+            memory,
+            image: vec![psp_start..start, start..end],
+            origin: start,
+            instruction_pointer: COM_ORIGIN,
+            registers,
+            segments: [segment; 4],
+            flags: CpuStateFlags::new(),
+            read_only: Vec::new(),
+            mmio: Vec::new(),
+            disk: Vec::new(),
+            drive: 0,
+            exit_code: None,
+            symbols,
+        })
+    }
+
+    /// Loads the first sector of `disk` at `0000:7C00` the way the BIOS boots from it, with
+    /// `dl` holding `drive` and the stack just below the sector. `int 13h` reads the rest of
+    /// the disk from the image. Other BIOS services are only noted in the trace.
+    pub fn load_boot_sector(disk: &[u8], drive: u8, symbols: &'a Symbols) -> Result<Self> {
+        let sector = boot::boot_sector(disk)?;
+        let start = boot::LOAD_ADDRESS as usize;
+        let end = start + SECTOR_SIZE;
+
+        let mut memory = vec![0; MEMORY_SIZE];
+        memory[start..end].copy_from_slice(sector);
+        let mut registers = [0; 8];
+        registers[3] = drive as u16;
+        registers[4] = boot::LOAD_ADDRESS;
+
+        Ok(CpuState {
+            memory,
+            image: std::iter::once(start..end).collect(),
+            origin: 0,
+            instruction_pointer: boot::LOAD_ADDRESS,
+            registers,
+            segments: [0; 4],
+            flags: CpuStateFlags::new(),
+            read_only: Vec::new(),
+            mmio: Vec::new(),
+            disk: disk.to_vec(),
+            drive,
+            exit_code: None,
+            symbols,
+        })
+    }
+
+    /// Places the ROM at its segment, where writes are ignored, and starts at its entry point
+    /// with `cs` as the CPU has it after a reset or as the BIOS calls an option ROM.
+    pub fn load_rom(rom: &Rom, symbols: &'a Symbols) -> Result<Self> {
+        let image = rom.image();
+        let mut cpu_state = Self::new(&image, symbols)?;
+        (cpu_state.segments[CS], cpu_state.instruction_pointer) = rom.entry;
+        cpu_state.read_only = image
+            .regions()
+            .map(|(start, data)| start as usize..start as usize + data.len())
+            .collect();
+        Ok(cpu_state)
+    }
+
+    /// Loads every region of the memory map and starts at its entry point, with every
+    /// segment register set to the entry's code segment.
+    pub fn load_memory_map(map: &MemoryMap, symbols: &'a Symbols) -> Result<Self> {
+        let mut cpu_state = Self::new(&map.image, symbols)?;
+        let (segment, offset) = map.entry;
+        cpu_state.segments = [segment; 4];
+        cpu_state.instruction_pointer = offset;
+        cpu_state.origin = segment as usize * 16;
+        let linear = |range: &Range<u32>| range.start as usize..range.end as usize;
+        cpu_state.read_only = map
+            .regions
+            .iter()
+            .filter(|region| region.read_only)
+            .map(|region| linear(&region.range))
+            .collect();
+        cpu_state.mmio = map.mmio.iter().map(linear).collect();
+        Ok(cpu_state)
     }
 
     pub fn print_registers(&self) {
@@ -85,18 +431,139 @@ impl<'a> CpuState<'a> {
         println!("BP: {:04X}", self.registers[5]);
         println!("SI: {:04X}", self.registers[6]);
         println!("DI: {:04X}", self.registers[7]);
+        println!("CS: {:04X}", self.segments[CS]);
+        println!("DS: {:04X}", self.segments[DS]);
+        println!("SS: {:04X}", self.segments[SS]);
+        println!("ES: {:04X}", self.segments[ES]);
+        println!("IP: {:04X}", self.instruction_pointer);
     }
 
     pub fn exec(&mut self) -> Result<()> {
-        while self.instruction_pointer < self.instructions.len() as u16 {
+        while self.exit_code.is_none()
+            && self
+                .image
+                .iter()
+                .any(|range| range.contains(&self.linear(CS, self.instruction_pointer)))
+        {
             self.run_next_instruction()?;
         }
 
         Ok(())
     }
 
+    fn linear(&self, segment: usize, offset: u16) -> usize {
+        (self.segments[segment] as usize * 16 + offset as usize) % MEMORY_SIZE
+    }
+
+    /// Address of the instruction pointer in the listing, which counts from the start of
+    /// the loaded image.
+    fn listing_address(&self) -> Address {
+        Address(
+            self.linear(CS, self.instruction_pointer)
+                .wrapping_sub(self.origin) as u16,
+        )
+    }
+
+    /// Segment and offset a memory operand refers to. Addresses based on `bp` are in the
+    /// stack segment, all others in the data segment.
+    fn effective_address(&self, memory: &Memory) -> (usize, u16) {
+        let mut offset = match memory.displacement {
+            Displacement::Disp8(disp) => disp as i8 as u16,
+            Displacement::Disp16(disp) => disp,
+            Displacement::None => 0,
+        };
+        for register in memory.registers.iter().flatten() {
+            let (index, _) = self.get_register_index_and_mask(register);
+            offset = offset.wrapping_add(self.registers[index]);
+        }
+        let segment = match memory.registers {
+            [Some(Register::Bp), _] => SS,
+            _ => DS,
+        };
+        (segment, offset)
+    }
+
+    fn read_memory(&self, memory: &Memory, width: u8) -> u16 {
+        let (segment, offset) = self.effective_address(memory);
+        self.read(segment, offset, width)
+    }
+
+    fn read(&self, segment: usize, offset: u16, width: u8) -> u16 {
+        let low = self.read_byte(self.linear(segment, offset)) as u16;
+        if width == 1 {
+            return low;
+        }
+        let high = self.read_byte(self.linear(segment, offset.wrapping_add(1))) as u16;
+        (high << 8) | low
+    }
+
+    /// Writes `value` unless it would land in read-only memory or on a device, which is
+    /// reported instead. Returns whether it was written.
+    fn write_memory(&mut self, memory: &Memory, width: u8, value: u16) -> bool {
+        let (segment, offset) = self.effective_address(memory);
+        self.write(segment, offset, width, value)
+    }
+
+    fn write(&mut self, segment: usize, offset: u16, width: u8, value: u16) -> bool {
+        let addresses: Vec<usize> = (0..width as u16)
+            .map(|byte| self.linear(segment, offset.wrapping_add(byte)))
+            .collect();
+        let within = |ranges: &[Range<usize>]| {
+            addresses
+                .iter()
+                .copied()
+                .find(|linear| ranges.iter().any(|range| range.contains(linear)))
+        };
+        if let Some(linear) = within(&self.mmio) {
+            print!("; MMIO write to {linear:#07x} ignored");
+            return false;
+        }
+        if let Some(linear) = within(&self.read_only) {
+            print!("; write to read-only {linear:#07x} ignored");
+            return false;
+        }
+
+        for (byte, linear) in addresses.into_iter().enumerate() {
+            self.memory[linear] = (value >> (8 * byte)) as u8;
+        }
+        true
+    }
+
+    /// Pushes a word at `ss:sp`, which grows down.
+    fn push(&mut self, value: u16) {
+        self.registers[4] = self.registers[4].wrapping_sub(2);
+        self.write(SS, self.registers[4], 2, value);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let value = self.read(SS, self.registers[4], 2);
+        self.registers[4] = self.registers[4].wrapping_add(2);
+        value
+    }
+
+    /// Offset and segment of the far pointer a memory operand refers to.
+    fn far_pointer(&self, target: &Operand) -> Option<(u16, u16)> {
+        let Operand::Memory(memory) = target else {
+            return None;
+        };
+        let (segment, offset) = self.effective_address(memory);
+        Some((
+            self.read(segment, offset, 2),
+            self.read(segment, offset.wrapping_add(2), 2),
+        ))
+    }
+
+    /// Device memory is not simulated and reads as an open bus.
+    fn read_byte(&self, linear: usize) -> u8 {
+        if self.mmio.iter().any(|range| range.contains(&linear)) {
+            return 0xFF;
+        }
+        self.memory[linear]
+    }
+
     fn decode_instruction(&mut self) -> Result<Instruction> {
-        let instruction_iterator = self.instructions[self.instruction_pointer as usize..]
+        let start = self.linear(CS, self.instruction_pointer);
+        let instruction_iterator = self.memory[start..]
             .iter()
             .enumerate()
             .map(|(i, v)| (Address(i as u16), *v));
@@ -106,126 +573,336 @@ impl<'a> CpuState<'a> {
 
     fn run_next_instruction(&mut self) -> Result<()> {
         let instruction = self.decode_instruction()?;
-        print!(
-            "Executing {} at 0x{:x}",
-            instruction, self.instruction_pointer
-        );
+        let text = instruction.format(&self.symbols.at(self.listing_address()));
+        print!("Executing {} at 0x{:x}", text, self.instruction_pointer);
+        let address = self.listing_address();
+        if let Some(location) = self.symbols.locate(address) {
+            print!(" ({location})");
+        }
+        if let Some(comment) = self.symbols.comment(address) {
+            print!("; {comment}");
+        }
+        if let Some(line) = self.symbols.source(address).last() {
+            print!("; {line}");
+        }
 
+        let width = instruction.operand_width();
         let previous_instruction_pointer = self.instruction_pointer;
-        self.instruction_pointer += instruction.get_size() as u16;
+        self.instruction_pointer = self
+            .instruction_pointer
+            .wrapping_add(instruction.get_size() as u16);
 
         match instruction {
             Instruction::Mov { dst, src, .. } => match dst {
                 Operand::Register(reg) => {
-                    let previous_flags = self.flags.clone();
-
-                    let (index, mask) = self.get_register_index_and_mask(&reg);
-                    let previous_register_value = self.registers[index] & mask;
-                    self.registers[index] = self.get_operand_value(&src) & mask;
+                    let previous_register_value = self.read_register(&reg);
+                    let value = self.get_operand_value(&src, reg.width());
+                    self.write_register(&reg, value);
                     print!(
                         "; {}: 0x{:x} -> 0x{:x}",
                         reg,
                         previous_register_value,
-                        self.registers[index] & mask
+                        self.read_register(&reg)
                     );
-                    if previous_flags != self.flags {
-                        print!("; flags:{} -> flags:{}", previous_flags, self.flags);
-                    }
                 }
-                Operand::Memory(_) => todo!(),
-                Operand::Immediate8(_) => unreachable!(),
-                Operand::Immediate16(_) => unreachable!(),
-            },
-            Instruction::Add { dst, src, .. } => match dst {
-                Operand::Register(reg) => {
-                    let previous_flags = self.flags.clone();
-
-                    let (index, mask) = self.get_register_index_and_mask(&reg);
-                    let previous_register_value = self.registers[index] & mask;
-                    let src_value = self.get_operand_value(&src);
-                    let new_register_value = self.add_to_register(&reg, src_value);
-                    self.registers[index] = new_register_value;
-
-                    print!(
-                        "; {}: 0x{:x} -> 0x{:x}",
-                        reg,
-                        previous_register_value,
-                        self.registers[index] & mask
-                    );
-
-                    if previous_flags != self.flags {
-                        print!("; flags:{} -> flags:{}", previous_flags, self.flags);
+                Operand::Memory(memory) => {
+                    let width = src.width().expect("mov to memory has a sized source");
+                    let value = self.get_operand_value(&src, width);
+                    let previous_value = self.read_memory(&memory, width);
+                    if self.write_memory(&memory, width, value) {
+                        print!("; {memory}: 0x{previous_value:x} -> 0x{value:x}");
                     }
                 }
-                Operand::Memory(_) => todo!(),
                 Operand::Immediate8(_) => unreachable!(),
                 Operand::Immediate16(_) => unreachable!(),
             },
-            Instruction::Sub { dst, src, .. } => match dst {
-                Operand::Register(reg) => {
-                    let previous_flags = self.flags.clone();
-
-                    let (index, mask) = self.get_register_index_and_mask(&reg);
-                    let previous_register_value = self.registers[index] & mask;
-                    let src_value = self.get_operand_value(&src);
-                    let new_register_value = self.add_to_register(&reg, src_value.wrapping_neg());
-                    self.registers[index] = new_register_value;
-
-                    print!(
-                        "; {}: 0x{:x} -> 0x{:x}",
-                        reg,
-                        previous_register_value,
-                        self.registers[index] & mask
-                    );
-
-                    if previous_flags != self.flags {
-                        print!("; flags:{} -> flags:{}", previous_flags, self.flags);
+            Instruction::MovSegment {
+                to_segment: true,
+                segment,
+                operand,
+                ..
+            } => {
+                let index = segment as usize;
+                let previous_segment = self.segments[index];
+                self.segments[index] = self.get_operand_value(&operand, 2);
+                print!(
+                    "; {segment}: 0x{previous_segment:x} -> 0x{:x}",
+                    self.segments[index]
+                );
+            }
+            Instruction::MovSegment {
+                to_segment: false,
+                segment,
+                operand,
+                ..
+            } => {
+                let value = self.segments[segment as usize];
+                match operand {
+                    Operand::Register(reg) => {
+                        let previous_register_value = self.read_register(&reg);
+                        self.write_register(&reg, value);
+                        print!("; {reg}: 0x{previous_register_value:x} -> 0x{value:x}");
                     }
-                }
-                Operand::Memory(_) => todo!(),
-                Operand::Immediate8(_) => unreachable!(),
-                Operand::Immediate16(_) => unreachable!(),
-            },
-            Instruction::Cmp { dst, src, .. } => match dst {
-                Operand::Register(reg) => {
-                    let previous_flags = self.flags.clone();
-
-                    let src_value = self.get_operand_value(&src);
-                    self.add_to_register(&reg, src_value.wrapping_neg());
-
-                    if previous_flags != self.flags {
-                        print!("; flags:{} -> flags:{}", previous_flags, self.flags);
+                    Operand::Memory(memory) => {
+                        let previous_value = self.read_memory(&memory, 2);
+                        if self.write_memory(&memory, 2, value) {
+                            print!("; {memory}: 0x{previous_value:x} -> 0x{value:x}");
+                        }
                     }
+                    Operand::Immediate8(_) => unreachable!(),
+                    Operand::Immediate16(_) => unreachable!(),
                 }
-                Operand::Memory(_) => todo!(),
-                Operand::Immediate8(_) => unreachable!(),
-                Operand::Immediate16(_) => unreachable!(),
-            },
-            Instruction::Je { .. } => todo!(),
-            Instruction::Jl { .. } => todo!(),
-            Instruction::Jle { .. } => todo!(),
-            Instruction::Jb { .. } => todo!(),
-            Instruction::Jbe { .. } => todo!(),
-            Instruction::Jp { .. } => todo!(),
-            Instruction::Jo { .. } => todo!(),
-            Instruction::Js { .. } => todo!(),
+            }
+            Instruction::Add { dst, src, .. } => self.arithmetic(Alu::Add, &dst, &src),
+            Instruction::Sub { dst, src, .. } => self.arithmetic(Alu::Sub, &dst, &src),
+            Instruction::Cmp { dst, src, .. } => self.arithmetic(Alu::Cmp, &dst, &src),
+            Instruction::Or { dst, src, .. } => self.arithmetic(Alu::Or, &dst, &src),
+            Instruction::Adc { dst, src, .. } => self.arithmetic(Alu::Adc, &dst, &src),
+            Instruction::Sbb { dst, src, .. } => self.arithmetic(Alu::Sbb, &dst, &src),
+            Instruction::Xor { dst, src, .. } => self.arithmetic(Alu::Xor, &dst, &src),
+            Instruction::Cwd { .. } => {
+                let previous_register_value = self.registers[3];
+                self.registers[3] = if self.registers[0] & 0x8000 != 0 {
+                    0xFFFF
+                } else {
+                    0
+                };
+                print!(
+                    "; dx: 0x{:x} -> 0x{:x}",
+                    previous_register_value, self.registers[3]
+                );
+            }
+            Instruction::Idiv { src, .. } => {
+                if !self.divide(&src, width.unwrap_or(2)) {
+                    println!();
+                    return Err(crate::error::Error::DivideOverflow(text).into());
+                }
+            }
+            Instruction::Shl { dst, count, .. } => {
+                let count = self.get_operand_value(&count, 1);
+                self.shift_left(&dst, width.unwrap_or(2), count);
+            }
+            Instruction::Movs { rep, .. } => self.move_string(width.unwrap_or(2), rep.is_some()),
+            Instruction::Je { ip_increment, .. } => {
+                let taken = self.flags.get_zero_flag();
+                self.jump_if(taken, ip_increment);
+            }
             Instruction::Jne { ip_increment, .. } => {
-                if self.flags.get_zero_flag() == false {
-                    self.instruction_pointer =
-                        self.instruction_pointer.wrapping_add(ip_increment as u16);
+                let taken = !self.flags.get_zero_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jb { ip_increment, .. } => {
+                let taken = self.flags.get_carry_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jnb { ip_increment, .. } => {
+                let taken = !self.flags.get_carry_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jbe { ip_increment, .. } => {
+                let taken = self.flags.get_carry_flag() || self.flags.get_zero_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jnbe { ip_increment, .. } => {
+                let taken = !self.flags.get_carry_flag() && !self.flags.get_zero_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jl { ip_increment, .. } => {
+                let taken = self.flags.get_sign_flag() != self.flags.get_overflow_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jnl { ip_increment, .. } => {
+                let taken = self.flags.get_sign_flag() == self.flags.get_overflow_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jle { ip_increment, .. } => {
+                let taken = self.flags.get_zero_flag()
+                    || self.flags.get_sign_flag() != self.flags.get_overflow_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jnle { ip_increment, .. } => {
+                let taken = !self.flags.get_zero_flag()
+                    && self.flags.get_sign_flag() == self.flags.get_overflow_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jp { ip_increment, .. } => {
+                let taken = self.flags.get_parity_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jnp { ip_increment, .. } => {
+                let taken = !self.flags.get_parity_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jo { ip_increment, .. } => {
+                let taken = self.flags.get_overflow_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jno { ip_increment, .. } => {
+                let taken = !self.flags.get_overflow_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Js { ip_increment, .. } => {
+                let taken = self.flags.get_sign_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Jns { ip_increment, .. } => {
+                let taken = !self.flags.get_sign_flag();
+                self.jump_if(taken, ip_increment);
+            }
+            Instruction::Loop { ip_increment, .. } => {
+                let count = self.decrement_count();
+                self.jump_if(count != 0, ip_increment);
+            }
+            Instruction::Loopz { ip_increment, .. } => {
+                let count = self.decrement_count();
+                self.jump_if(count != 0 && self.flags.get_zero_flag(), ip_increment);
+            }
+            Instruction::Loopnz { ip_increment, .. } => {
+                let count = self.decrement_count();
+                self.jump_if(count != 0 && !self.flags.get_zero_flag(), ip_increment);
+            }
+            Instruction::Jcxz { ip_increment, .. } => {
+                self.jump_if(self.registers[2] == 0, ip_increment);
+            }
+            Instruction::Jmp { ip_increment, .. } => {
+                self.instruction_pointer =
+                    self.instruction_pointer.wrapping_add(ip_increment as u16);
+            }
+            Instruction::JmpShort { ip_increment, .. } => {
+                self.instruction_pointer =
+                    self.instruction_pointer.wrapping_add(ip_increment as u16);
+            }
+            Instruction::JmpFar {
+                segment, offset, ..
+            } => {
+                self.segments[CS] = segment;
+                self.instruction_pointer = offset;
+            }
+            Instruction::JmpIndirect {
+                far: false, target, ..
+            } => {
+                self.instruction_pointer = self.get_operand_value(&target, 2);
+            }
+            Instruction::JmpIndirect {
+                far: true, target, ..
+            } => {
+                let Some((offset, segment)) = self.far_pointer(&target) else {
+                    println!();
+                    return Err(crate::error::Error::Unsupported(text).into());
+                };
+                self.segments[CS] = segment;
+                self.instruction_pointer = offset;
+            }
+            Instruction::Call { ip_increment, .. } => {
+                let previous_stack_pointer = self.registers[4];
+                self.push(self.instruction_pointer);
+                self.instruction_pointer =
+                    self.instruction_pointer.wrapping_add(ip_increment as u16);
+                self.print_stack_pointer(previous_stack_pointer);
+            }
+            Instruction::CallFar {
+                segment, offset, ..
+            } => {
+                let previous_stack_pointer = self.registers[4];
+                self.push(self.segments[CS]);
+                self.push(self.instruction_pointer);
+                self.segments[CS] = segment;
+                self.instruction_pointer = offset;
+                self.print_stack_pointer(previous_stack_pointer);
+            }
+            Instruction::CallIndirect {
+                far: false, target, ..
+            } => {
+                let previous_stack_pointer = self.registers[4];
+                let target = self.get_operand_value(&target, 2);
+                self.push(self.instruction_pointer);
+                self.instruction_pointer = target;
+                self.print_stack_pointer(previous_stack_pointer);
+            }
+            Instruction::CallIndirect {
+                far: true, target, ..
+            } => {
+                let Some((offset, segment)) = self.far_pointer(&target) else {
+                    println!();
+                    return Err(crate::error::Error::Unsupported(text).into());
+                };
+                let previous_stack_pointer = self.registers[4];
+                self.push(self.segments[CS]);
+                self.push(self.instruction_pointer);
+                self.segments[CS] = segment;
+                self.instruction_pointer = offset;
+                self.print_stack_pointer(previous_stack_pointer);
+            }
+            Instruction::Ret { pop, .. } => {
+                let previous_stack_pointer = self.registers[4];
+                self.instruction_pointer = self.pop();
+                self.registers[4] = self.registers[4].wrapping_add(pop.unwrap_or(0));
+                self.print_stack_pointer(previous_stack_pointer);
+            }
+            Instruction::Retf { pop, .. } => {
+                let previous_stack_pointer = self.registers[4];
+                self.instruction_pointer = self.pop();
+                self.segments[CS] = self.pop();
+                self.registers[4] = self.registers[4].wrapping_add(pop.unwrap_or(0));
+                self.print_stack_pointer(previous_stack_pointer);
+            }
+            Instruction::Iret { .. } => {
+                let previous_stack_pointer = self.registers[4];
+                let previous_flags = self.flags.clone();
+                self.instruction_pointer = self.pop();
+                self.segments[CS] = self.pop();
+                self.flags = CpuStateFlags::from_word(self.pop());
+                self.print_stack_pointer(previous_stack_pointer);
+                if previous_flags != self.flags {
+                    print!("; flags:{} -> flags:{}", previous_flags, self.flags);
+                }
+            }
+            Instruction::Push { src, .. } => {
+                let previous_stack_pointer = self.registers[4];
+                let value = self.get_operand_value(&src, 2);
+                self.push(value);
+                self.print_stack_pointer(previous_stack_pointer);
+            }
+            Instruction::Pop { dst, .. } => {
+                let previous_stack_pointer = self.registers[4];
+                let value = self.pop();
+                self.print_stack_pointer(previous_stack_pointer);
+                match dst {
+                    Operand::Register(reg) => {
+                        let (index, _) = self.get_register_index_and_mask(&reg);
+                        let previous_register_value = self.registers[index];
+                        self.registers[index] = value;
+                        print!("; {reg}: 0x{previous_register_value:x} -> 0x{value:x}");
+                    }
+                    Operand::Memory(memory) => {
+                        let previous_value = self.read_memory(&memory, 2);
+                        if self.write_memory(&memory, 2, value) {
+                            print!("; {memory}: 0x{previous_value:x} -> 0x{value:x}");
+                        }
+                    }
+                    Operand::Immediate8(_) => unreachable!(),
+                    Operand::Immediate16(_) => unreachable!(),
+                }
+            }
+            Instruction::Int { vector: 0x13, .. } => {
+                let previous_flags = self.flags.clone();
+                self.disk_service();
+                if previous_flags != self.flags {
+                    print!("; flags:{} -> flags:{}", previous_flags, self.flags);
                 }
             }
-            Instruction::Jnl { .. } => todo!(),
-            Instruction::Jnle { .. } => todo!(),
-            Instruction::Jnb { .. } => todo!(),
-            Instruction::Jnbe { .. } => todo!(),
-            Instruction::Jnp { .. } => todo!(),
-            Instruction::Jno { .. } => todo!(),
-            Instruction::Jns { .. } => todo!(),
-            Instruction::Loop { .. } => todo!(),
-            Instruction::Loopz { .. } => todo!(),
-            Instruction::Loopnz { .. } => todo!(),
-            Instruction::Jcxz { .. } => todo!(),
+            Instruction::Int { vector: 0x20, .. } => self.exit(0),
+            Instruction::Int { vector: 0x21, .. } if self.registers[0] >> 8 == 0x4C => {
+                self.exit(self.registers[0] as u8);
+            }
+            Instruction::Int { vector, .. } => print!("; int {vector:#04x} not simulated"),
+            Instruction::Clc { .. } => self.update_flags(|flags| flags.set_carry_flag(false)),
+            Instruction::Stc { .. } => self.update_flags(|flags| flags.set_carry_flag(true)),
+            Instruction::Cli { .. } => self.update_flags(|flags| flags.set_interrupt_flag(false)),
+            Instruction::Sti { .. } => self.update_flags(|flags| flags.set_interrupt_flag(true)),
+            Instruction::Cld { .. } => self.update_flags(|flags| flags.set_direction_flag(false)),
+            Instruction::Std { .. } => self.update_flags(|flags| flags.set_direction_flag(true)),
+            Instruction::Nop { .. } => {}
         }
 
         println!(
@@ -235,46 +912,327 @@ impl<'a> CpuState<'a> {
         Ok(())
     }
 
-    fn add_to_register(&mut self, dst: &Register, src_value: u16) -> u16 {
-        let (index, mask) = self.get_register_index_and_mask(&dst);
+    /// Ends the program the way `int 20h` and `int 21h` function `4Ch` return to DOS.
+    fn exit(&mut self, code: u8) {
+        self.exit_code = Some(code);
+        print!("; program exited with code {code}");
+    }
+
+    /// Changes the flags the way the flag instructions do, printing the change.
+    fn update_flags(&mut self, update: impl FnOnce(&mut CpuStateFlags)) {
+        let previous_flags = self.flags.clone();
+        update(&mut self.flags);
+        if previous_flags != self.flags {
+            print!("; flags:{} -> flags:{}", previous_flags, self.flags);
+        }
+    }
+
+    fn jump_if(&mut self, taken: bool, ip_increment: i8) {
+        if taken {
+            self.instruction_pointer = self.instruction_pointer.wrapping_add(ip_increment as u16);
+        }
+    }
+
+    /// Decrements `cx` for the `loop` instructions, which leave the flags alone.
+    fn decrement_count(&mut self) -> u16 {
+        let previous = self.registers[2];
+        self.registers[2] = previous.wrapping_sub(1);
+        print!("; cx: 0x{:x} -> 0x{:x}", previous, self.registers[2]);
+        self.registers[2]
+    }
+
+    fn print_stack_pointer(&self, previous: u16) {
+        print!("; sp: 0x{:x} -> 0x{:x}", previous, self.registers[4]);
+    }
+
+    /// BIOS disk services of `int 13h` for the boot drive, served from the disk image:
+    /// reset, CHS and extended LBA reads, drive parameters and the extensions check.
+    fn disk_service(&mut self) {
+        let [al, ah] = self.registers[0].to_le_bytes();
+        let [cl, ch] = self.registers[2].to_le_bytes();
+        let [dl, dh] = self.registers[3].to_le_bytes();
+        let geometry = Geometry::of(&self.disk);
+
+        let status = if dl != self.drive {
+            print!("; int 13h ah={ah:02x}: no drive {dl:#04x}");
+            Err(0x01)
+        } else {
+            match ah {
+                0x00 => {
+                    print!("; int 13h: reset drive {dl:#04x}");
+                    Ok(())
+                }
+                0x02 => {
+                    let cylinder = ch as usize | ((cl as usize & 0xC0) << 2);
+                    let lba = geometry.lba(cylinder, dh as usize, cl as usize & 0x3F);
+                    let offset = self.registers[1];
+                    let status = match lba {
+                        Some(lba) => self.read_sectors(lba, al as usize, ES, offset),
+                        None => Err(0x04),
+                    };
+                    // al returns the number of sectors read.
+                    let read = if status.is_ok() { al } else { 0 };
+                    self.registers[0] = (self.registers[0] & 0xFF00) | read as u16;
+                    status
+                }
+                0x08 => {
+                    let cylinder = geometry.cylinders.clamp(1, 1024) - 1;
+                    let sectors = geometry.sectors as u16 | ((cylinder as u16 >> 2) & 0xC0);
+                    self.registers[2] = ((cylinder as u16 & 0xFF) << 8) | sectors;
+                    self.registers[3] = ((geometry.heads as u16 - 1) << 8) | 1;
+                    self.registers[1] = if geometry.is_floppy() { 0x04 } else { 0 };
+                    print!(
+                        "; int 13h: {} cylinders, {} heads, {} sectors",
+                        geometry.cylinders, geometry.heads, geometry.sectors
+                    );
+                    Ok(())
+                }
+                0x41 if self.registers[1] == 0x55AA => {
+                    self.registers[1] = 0xAA55;
+                    // Only the packet functions of version 1.x.
+                    self.registers[2] = 0x0001;
+                    self.registers[0] = (self.registers[0] & 0x00FF) | 0x2000;
+                    print!("; int 13h: extensions present");
+                    self.flags.set_carry_flag(false);
+                    return;
+                }
+                0x42 => {
+                    let packet = self.registers[6];
+                    let word = |at: u16| self.read(DS, packet.wrapping_add(at), 2);
+                    let count = word(2) as usize;
+                    let (offset, segment) = (word(4), word(6));
+                    let lba = (word(8) as usize) | (word(10) as usize) << 16;
+                    let previous_segment = self.segments[ES];
+                    self.segments[ES] = segment;
+                    let status = self.read_sectors(lba, count, ES, offset);
+                    self.segments[ES] = previous_segment;
+                    // The packet returns the number of blocks read, and al is left alone.
+                    let read = if status.is_ok() { count as u16 } else { 0 };
+                    self.write(DS, packet.wrapping_add(2), 2, read);
+                    status
+                }
+                _ => {
+                    print!("; int 13h ah={ah:02x} not simulated");
+                    Err(0x01)
+                }
+            }
+        };
+
+        let code = status.err().unwrap_or(0);
+        self.registers[0] = (self.registers[0] & 0x00FF) | (code as u16) << 8;
+        self.flags.set_carry_flag(code != 0);
+    }
 
-        if mask == 0xFFFF {
-            let value = (self.registers[index]).wrapping_add(src_value);
+    /// Copies `count` sectors from `lba` on to `segment:offset`, which then counts as loaded
+    /// code. Reads past the end of the disk fail with the BIOS "sector not found" status.
+    fn read_sectors(
+        &mut self,
+        lba: usize,
+        count: usize,
+        segment: usize,
+        offset: u16,
+    ) -> Result<(), u8> {
+        let start = lba * SECTOR_SIZE;
+        let end = start + count * SECTOR_SIZE;
+        let target = self.linear(segment, offset);
+        print!(
+            "; int 13h: read {count} sectors from lba {lba} to {:04x}:{offset:04x}",
+            self.segments[segment]
+        );
+        if end > self.disk.len() || target + (end - start) > MEMORY_SIZE {
+            return Err(0x04);
+        }
 
-            self.flags.set_zero_flag(value == 0);
-            self.flags
-                .set_sign_flag((value & 0b1000_0000_0000_0000) != 0);
+        self.memory[target..target + (end - start)].copy_from_slice(&self.disk[start..end]);
+        self.image.push(target..target + (end - start));
+        Ok(())
+    }
 
-            value
+    /// Applies `alu` to the operands, sets the zero, sign, carry, overflow and parity flags
+    /// from the result and writes it to `dst` unless the operation only compares.
+    fn arithmetic(&mut self, alu: Alu, dst: &Operand, src: &Operand) {
+        let previous_flags = self.flags.clone();
+        let width = dst
+            .width()
+            .or(src.width())
+            .expect("arithmetic has a sized operand");
+        let (mask, sign) = if width == 1 {
+            (0xFF, 0x80)
         } else {
-            let src_value = if mask == 0xFF00 {
-                ((src_value & mask) >> 8) as u8
-            } else {
-                (src_value & mask) as u8
-            };
+            (0xFFFF, 0x8000)
+        };
+        let a = self.get_operand_value(dst, width) as u32 & mask;
+        let b = self.get_operand_value(src, width) as u32 & mask;
 
-            let dst_value = if mask == 0xFF00 {
-                ((self.registers[index] & mask) >> 8) as u8
-            } else {
-                (self.registers[index] & mask) as u8
-            };
+        let carry_in = self.flags.get_carry_flag() as u32;
 
-            let value = dst_value.wrapping_add(src_value);
+        let (result, carry, overflow) = match alu {
+            Alu::Add | Alu::Adc => {
+                let b = if alu == Alu::Adc { b + carry_in } else { b };
+                let result = a + b;
+                (
+                    result,
+                    result > mask,
+                    (a ^ result) & (b ^ result) & sign != 0,
+                )
+            }
+            Alu::Sub | Alu::Sbb | Alu::Cmp => {
+                let b = if alu == Alu::Sbb { b + carry_in } else { b };
+                let result = a.wrapping_sub(b);
+                (result, a < b, (a ^ b) & (a ^ result) & sign != 0)
+            }
+            Alu::Or => (a | b, false, false),
+            Alu::Xor => (a ^ b, false, false),
+        };
+        let result = result & mask;
+        self.flags.set_zero_flag(result == 0);
+        self.flags.set_sign_flag(result & sign != 0);
+        self.flags.set_parity_flag(even_parity(result as u8));
+        self.flags.set_carry_flag(carry);
+        self.flags.set_overflow_flag(overflow);
+
+        if alu != Alu::Cmp {
+            match dst {
+                Operand::Register(reg) => {
+                    self.write_register(reg, result as u16);
+                    print!("; {reg}: 0x{a:x} -> 0x{result:x}");
+                }
+                Operand::Memory(memory) => {
+                    if self.write_memory(memory, width, result as u16) {
+                        print!("; {memory}: 0x{a:x} -> 0x{result:x}");
+                    }
+                }
+                Operand::Immediate8(_) => unreachable!(),
+                Operand::Immediate16(_) => unreachable!(),
+            }
+        }
+        if previous_flags != self.flags {
+            print!("; flags:{} -> flags:{}", previous_flags, self.flags);
+        }
+    }
 
-            self.flags.set_zero_flag(value == 0);
-            self.flags.set_sign_flag((value & 0b1000_0000) != 0);
+    /// Signed division of `ax` by a byte or `dx:ax` by a word, leaving the quotient in `al`
+    /// or `ax` and the remainder in `ah` or `dx`. Returns false on a divide error, when the
+    /// divisor is 0 or the quotient does not fit.
+    fn divide(&mut self, src: &Operand, width: u8) -> bool {
+        let divisor = self.get_operand_value(src, width);
+        let (dividend, divisor, limit) = if width == 1 {
+            (
+                self.registers[0] as i16 as i32,
+                divisor as u8 as i8 as i32,
+                0x80,
+            )
+        } else {
+            let dividend = ((self.registers[3] as u32) << 16) | self.registers[0] as u32;
+            (dividend as i32, divisor as i16 as i32, 0x8000)
+        };
+        if divisor == 0 {
+            return false;
+        }
+        let (quotient, remainder) = (
+            dividend.wrapping_div(divisor),
+            dividend.wrapping_rem(divisor),
+        );
+        if quotient >= limit || quotient < -limit {
+            return false;
+        }
 
-            (value as u16 & mask) & (self.registers[index] & mask.swap_bytes())
+        let previous = (self.registers[0], self.registers[3]);
+        if width == 1 {
+            self.registers[0] = ((remainder as u8 as u16) << 8) | quotient as u8 as u16;
+            print!("; ax: 0x{:x} -> 0x{:x}", previous.0, self.registers[0]);
+        } else {
+            self.registers[0] = quotient as u16;
+            self.registers[3] = remainder as u16;
+            print!(
+                "; ax: 0x{:x} -> 0x{:x}; dx: 0x{:x} -> 0x{:x}",
+                previous.0, self.registers[0], previous.1, self.registers[3]
+            );
         }
+        true
     }
 
-    fn get_operand_value(&self, op: &Operand) -> u16 {
-        match op {
+    /// Shifts `dst` left by `count`, setting the carry flag to the last bit shifted out and,
+    /// for single shifts, the overflow flag to whether the sign changed.
+    fn shift_left(&mut self, dst: &Operand, width: u8, count: u16) {
+        if count == 0 {
+            return;
+        }
+        let previous_flags = self.flags.clone();
+        let bits = width as u32 * 8;
+        let mask = (1u32 << bits) - 1;
+        let value = self.get_operand_value(dst, width) as u32 & mask;
+        let shifted = value.checked_shl(count as u32).unwrap_or(0);
+        let result = shifted & mask;
+        let carry = shifted >> bits & 1 != 0;
+        let sign = result >> (bits - 1) & 1 != 0;
+        self.flags.set_zero_flag(result == 0);
+        self.flags.set_sign_flag(sign);
+        self.flags.set_parity_flag(even_parity(result as u8));
+        self.flags.set_carry_flag(carry);
+        self.flags.set_overflow_flag(count == 1 && sign != carry);
+
+        match dst {
             Operand::Register(reg) => {
-                let (index, mask) = self.get_register_index_and_mask(reg);
-                self.registers[index] & mask
+                self.write_register(reg, result as u16);
+                print!("; {reg}: 0x{value:x} -> 0x{result:x}");
             }
-            Operand::Memory(_) => todo!(),
+            Operand::Memory(memory) => {
+                if self.write_memory(memory, width, result as u16) {
+                    print!("; {memory}: 0x{value:x} -> 0x{result:x}");
+                }
+            }
+            Operand::Immediate8(_) => unreachable!(),
+            Operand::Immediate16(_) => unreachable!(),
+        }
+        if previous_flags != self.flags {
+            print!("; flags:{} -> flags:{}", previous_flags, self.flags);
+        }
+    }
+
+    /// Copies from `ds:si` to `es:di` and moves both forward, or back when the direction
+    /// flag is set. With `rep`, repeats `cx` times and leaves `cx` at 0.
+    fn move_string(&mut self, width: u8, rep: bool) {
+        let count = if rep { self.registers[2] } else { 1 };
+        let step = if self.flags.get_direction_flag() {
+            (width as u16).wrapping_neg()
+        } else {
+            width as u16
+        };
+        for _ in 0..count {
+            let value = self.read(DS, self.registers[6], width);
+            self.write(ES, self.registers[7], width, value);
+            self.registers[6] = self.registers[6].wrapping_add(step);
+            self.registers[7] = self.registers[7].wrapping_add(step);
+        }
+        if rep {
+            self.registers[2] = 0;
+        }
+        print!(
+            "; moved {count} {}; si:0x{:x} di:0x{:x}",
+            if width == 1 { "bytes" } else { "words" },
+            self.registers[6],
+            self.registers[7]
+        );
+    }
+
+    /// Value of a register, with `ah`-`dh` shifted down to a byte.
+    fn read_register(&self, reg: &Register) -> u16 {
+        let (index, mask) = self.get_register_index_and_mask(reg);
+        (self.registers[index] & mask) >> mask.trailing_zeros()
+    }
+
+    /// Writes a register, leaving the other half alone for byte registers.
+    fn write_register(&mut self, reg: &Register, value: u16) {
+        let (index, mask) = self.get_register_index_and_mask(reg);
+        let value = (value << mask.trailing_zeros()) & mask;
+        self.registers[index] = (self.registers[index] & !mask) | value;
+    }
+
+    fn get_operand_value(&self, op: &Operand, width: u8) -> u16 {
+        match op {
+            Operand::Register(reg) => self.read_register(reg),
+            Operand::Memory(memory) => self.read_memory(memory, width),
             Operand::Immediate8(v) => *v as u16,
             Operand::Immediate16(v) => *v,
         }
@@ -301,3 +1259,348 @@ impl<'a> CpuState<'a> {
         }
     }
 }
+
+/// Program segment prefix holding `int 20h` at offset 0, the end of the program's memory at
+/// offset 2 and `arguments` as the command tail.
+fn program_segment_prefix(arguments: &str) -> Result<[u8; 0x100]> {
+    let mut tail = arguments.as_bytes().to_vec();
+    // DOS keeps the separator between the program name and its arguments.
+    if !tail.is_empty() && tail[0] != b' ' {
+        tail.insert(0, b' ');
+    }
+    if tail.len() > MAX_COMMAND_TAIL {
+        return Err(crate::error::Error::CommandTailTooLong(tail.len()).into());
+    }
+
+    let mut psp = [0; 0x100];
+    psp[0..2].copy_from_slice(&[0xCD, 0x20]);
+    psp[2..4].copy_from_slice(&TOP_OF_MEMORY.to_le_bytes());
+    psp[COMMAND_TAIL] = tail.len() as u8;
+    psp[COMMAND_TAIL + 1..COMMAND_TAIL + 1 + tail.len()].copy_from_slice(&tail);
+    psp[COMMAND_TAIL + 1 + tail.len()] = b'\r';
+
+    Ok(psp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cpu_state::{CpuState, CS, ES, SS};
+    use crate::image::Image;
+    use crate::mz::Executable;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn test_load_com() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let cpu_state = CpuState::load_com(&[0xB8, 0x01, 0x00], 0x1000, "/c dir", &symbols)?;
+
+        let psp = &cpu_state.memory[0x10000..0x10100];
+        assert_eq!(psp[..4], [0xCD, 0x20, 0x00, 0xA0]);
+        assert_eq!(psp[0x80..0x89], *b"\x07 /c dir\r");
+        assert_eq!(cpu_state.memory[0x10100..0x10103], [0xB8, 0x01, 0x00]);
+        assert_eq!(cpu_state.segments, [0x1000; 4]);
+        assert_eq!(cpu_state.linear(CS, cpu_state.instruction_pointer), 0x10100);
+        assert_eq!(cpu_state.linear(SS, cpu_state.registers[4]), 0x1FFFE);
+        assert_eq!(cpu_state.memory[0x1FFFE..0x20000], [0, 0]);
+
+        let Err(error) = CpuState::load_com(&[0xC3], 0xF800, "", &symbols) else {
+            panic!("a segment 32 KiB below the end of memory should not load");
+        };
+        assert_eq!(
+            error.to_string(),
+            "Load segment f800 has only 0x8000 bytes of memory, not the 64 KiB a COM program needs"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_segment_leaves_room_for_the_psp() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let mut bytes = vec![0; 0x20];
+        bytes[..2].copy_from_slice(b"MZ");
+        bytes[0x04] = 0x01; // one page
+        bytes[0x08] = 0x02; // 2 paragraph header
+        bytes.extend([0xB8, 0x01, 0x00]);
+        let executable = Executable::parse(&bytes)?.expect("MZ signature");
+
+        assert!(CpuState::load_executable(&executable, 0x000F, "", &symbols).is_err());
+        let cpu_state = CpuState::load_executable(&executable, 0x0010, "", &symbols)?;
+        assert_eq!(cpu_state.memory[..2], [0xCD, 0x20]);
+        assert_eq!(cpu_state.memory[0x100..0x103], [0xB8, 0x01, 0x00]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_com_exits() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+
+        // mov ax, 1; ret, to the int 20h at the start of the PSP
+        let mut cpu_state = CpuState::load_com(&[0xB8, 0x01, 0x00, 0xC3], 0x1000, "", &symbols)?;
+        cpu_state.exec()?;
+        assert_eq!(cpu_state.exit_code, Some(0));
+        assert_eq!(cpu_state.registers[0], 1);
+        assert_eq!(cpu_state.linear(CS, cpu_state.instruction_pointer), 0x10002);
+
+        // mov ax, 0x4c05; int 21h; mov bx, 1
+        let program = [0xB8, 0x05, 0x4C, 0xCD, 0x21, 0xBB, 0x01, 0x00];
+        let mut cpu_state = CpuState::load_com(&program, 0x1000, "", &symbols)?;
+        cpu_state.exec()?;
+        assert_eq!(cpu_state.exit_code, Some(5));
+        assert_eq!(cpu_state.registers[1], 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_sets_carry() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let program = [
+            0xB8, 0x01, 0x00, // mov ax, 1
+            0xBB, 0x02, 0x00, // mov bx, 2
+            0x39, 0xD8, // cmp ax, bx
+            0x72, 0x03, // jb +3
+            0xB9, 0x01, 0x00, // mov cx, 1
+            0xBA, 0x01, 0x00, // mov dx, 1
+        ];
+        let mut cpu_state = CpuState::new(&Image::flat(&program), &symbols)?;
+        cpu_state.exec()?;
+
+        assert_eq!(cpu_state.registers[2], 0);
+        assert_eq!(cpu_state.registers[3], 1);
+        assert!(cpu_state.flags.get_carry_flag() && cpu_state.flags.get_sign_flag());
+        assert!(!cpu_state.flags.get_overflow_flag());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_logic_division_and_strings() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let program = [
+            0xB8, 0xF6, 0xFF, // mov ax, -10
+            0x99, // cwd
+            0xBB, 0x03, 0x00, // mov bx, 3
+            0xF7, 0xFB, // idiv bx
+            0xD1, 0xE0, // shl ax, 1
+            0x31, 0xC9, // xor cx, cx
+            0x83, 0xD1, 0x05, // adc cx, 5
+            0xBE, 0x00, 0x00, // mov si, 0
+            0xBF, 0x00, 0x01, // mov di, 0x100
+            0xF3, 0xA4, // rep movsb
+        ];
+        let mut cpu_state = CpuState::new(&Image::flat(&program), &symbols)?;
+        cpu_state.exec()?;
+
+        assert_eq!(cpu_state.registers[0], 0xFFFA);
+        assert_eq!(cpu_state.registers[3], 0xFFFF);
+        assert_eq!(cpu_state.registers[2], 0);
+        assert_eq!(cpu_state.memory[0x100..0x105], program[..5]);
+        assert_eq!(cpu_state.registers[6..], [0x0005, 0x0105]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_boot_sector() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let mut disk = vec![0; 1024];
+        let first = [
+            0x31, 0xC0, // xor ax, ax
+            0xB9, 0x03, 0x00, // mov cx, 3
+            0x05, 0x02, 0x00, // add ax, 2
+            0xE2, 0xFB, // loop 0x7c05
+            0x50, // push ax
+            0xE8, 0x0B, 0x00, // call 0x7c19
+            0x5E, // pop si
+            0xEA, 0x00, 0x7E, 0x00, 0x00, // jmp 0000:7e00
+            0x00, 0x00, 0x00, 0x00, 0x00, // padding
+            0xB8, 0x01, 0x02, // mov ax, 0x0201
+            0xB9, 0x02, 0x00, // mov cx, 2
+            0xB6, 0x00, // mov dh, 0
+            0xBB, 0x00, 0x7E, // mov bx, 0x7e00
+            0xCD, 0x13, // int 13h
+            0xC3, // ret
+        ];
+        disk[..first.len()].copy_from_slice(&first);
+        disk[510..512].copy_from_slice(&[0x55, 0xAA]);
+        // mov di, 0x1234; jmp 0000:0500
+        disk[512..520].copy_from_slice(&[0xBF, 0x34, 0x12, 0xEA, 0x00, 0x05, 0x00, 0x00]);
+
+        let mut cpu_state = CpuState::load_boot_sector(&disk, 0x80, &symbols)?;
+        cpu_state.exec()?;
+
+        assert_eq!(cpu_state.registers[6], 6);
+        assert_eq!(cpu_state.registers[7], 0x1234);
+        assert_eq!(cpu_state.registers[0], 0x0001);
+        assert!(!cpu_state.flags.get_carry_flag());
+        assert_eq!(cpu_state.registers[4], 0x7C00);
+        assert_eq!(cpu_state.linear(CS, cpu_state.instruction_pointer), 0x500);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_boot_sector_loader() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let mut disk = vec![0; 1024];
+        let first = [
+            0xFA, // cli
+            0x31, 0xC0, // xor ax, ax
+            0x8E, 0xD0, // mov ss, ax
+            0xBC, 0x00, 0x7C, // mov sp, 0x7c00
+            0xFB, // sti
+            0x8E, 0xD8, // mov ds, ax
+            0x8E, 0xC0, // mov es, ax
+            0xFC, // cld
+            0xB4, 0x41, // mov ah, 0x41
+            0xBB, 0xAA, 0x55, // mov bx, 0x55aa
+            0xCD, 0x13, // int 13h
+            0x72, 0x0E, // jb 0x7c25
+            0xBE, 0x40, 0x7C, // mov si, 0x7c40
+            0xB4, 0x42, // mov ah, 0x42
+            0xCD, 0x13, // int 13h
+            0x72, 0x05, // jb 0x7c25
+            0xEA, 0x00, 0x7E, 0x00, 0x00, // jmp 0000:7e00
+            0xEB, 0xFE, // jmp $
+        ];
+        disk[..first.len()].copy_from_slice(&first);
+        // Disk address packet: one sector from lba 1 to 0000:7e00
+        let packet = [0x10, 0x00, 0x01, 0x00, 0x00, 0x7E, 0x00, 0x00, 0x01];
+        disk[0x40..0x40 + packet.len()].copy_from_slice(&packet);
+        disk[510..512].copy_from_slice(&[0x55, 0xAA]);
+        let second = [
+            0xB8, 0x00, 0x10, // mov ax, 0x1000
+            0x8E, 0xC0, // mov es, ax
+            0x8C, 0xC3, // mov bx, es
+            0x31, 0xFF, // xor di, di
+            0xBE, 0x00, 0x7E, // mov si, 0x7e00
+            0xB9, 0x02, 0x00, // mov cx, 2
+            0xF3, 0xA4, // rep movsb
+            0x90, // nop
+            0xF9, // stc
+            0xEA, 0x00, 0x05, 0x00, 0x00, // jmp 0000:0500
+        ];
+        disk[512..512 + second.len()].copy_from_slice(&second);
+
+        let mut cpu_state = CpuState::load_boot_sector(&disk, 0x80, &symbols)?;
+        cpu_state.exec()?;
+
+        assert_eq!(cpu_state.segments[SS], 0);
+        assert_eq!(cpu_state.segments[ES], 0x1000);
+        assert_eq!(cpu_state.registers[1], 0x1000);
+        assert_eq!(cpu_state.registers[4], 0x7C00);
+        assert_eq!(cpu_state.memory[0x10000..0x10002], [0xB8, 0x00]);
+        assert_eq!(
+            (cpu_state.registers[6], cpu_state.registers[7]),
+            (0x7E02, 2)
+        );
+        assert!(cpu_state.flags.get_interrupt_flag());
+        assert!(!cpu_state.flags.get_direction_flag());
+        assert!(cpu_state.flags.get_carry_flag());
+        assert_eq!(cpu_state.linear(CS, cpu_state.instruction_pointer), 0x500);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extended_read_packet() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let mut disk = vec![0; 1024];
+        let first = [
+            0xBE, 0x40, 0x7C, // mov si, 0x7c40
+            0xB8, 0x33, 0x42, // mov ax, 0x4233
+            0xCD, 0x13, // int 13h
+            0x89, 0xC3, // mov bx, ax
+            0x8B, 0x0E, 0x42, 0x7C, // mov cx, [0x7c42]
+            0xC7, 0x06, 0x48, 0x7C, 0x10, 0x00, // mov word [0x7c48], 16
+            0xB8, 0x33, 0x42, // mov ax, 0x4233
+            0xCD, 0x13, // int 13h
+            0xEA, 0x00, 0x05, 0x00, 0x00, // jmp 0000:0500
+        ];
+        disk[..first.len()].copy_from_slice(&first);
+        let packet = [0x10, 0x00, 0x01, 0x00, 0x00, 0x7E, 0x00, 0x00, 0x01];
+        disk[0x40..0x40 + packet.len()].copy_from_slice(&packet);
+        disk[510..512].copy_from_slice(&[0x55, 0xAA]);
+
+        let mut cpu_state = CpuState::load_boot_sector(&disk, 0x80, &symbols)?;
+        cpu_state.exec()?;
+
+        // The read leaves al alone and reports the blocks read in the packet.
+        assert_eq!(cpu_state.registers[1], 0x0033);
+        assert_eq!(cpu_state.registers[2], 1);
+        // Past the end of the disk nothing is read.
+        assert_eq!(cpu_state.registers[0], 0x0433);
+        assert!(cpu_state.flags.get_carry_flag());
+        assert_eq!(cpu_state.memory[0x7C42..0x7C44], [0, 0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parity_jumps() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let program = [
+            0xB0, 0x03, // mov al, 3
+            0x04, 0x00, // add al, 0
+            0x7A, 0x02, // jp 0x8
+            0xB3, 0x01, // mov bl, 1
+            0x04, 0x04, // add al, 4
+            0x7B, 0x02, // jnp 0xe
+            0xB7, 0x01, // mov bh, 1
+        ];
+        let mut cpu_state = CpuState::new(&Image::flat(&program), &symbols)?;
+        cpu_state.exec()?;
+
+        assert_eq!(cpu_state.registers[0], 7);
+        assert_eq!(cpu_state.registers[1], 0);
+        assert!(!cpu_state.flags.get_parity_flag());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_loop_and_signed_jumps() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let program = [
+            0xB9, 0x03, 0x00, // mov cx, 3
+            0x31, 0xC0, // xor ax, ax
+            0x05, 0x02, 0x00, // add ax, 2
+            0xE2, 0xFB, // loop 0x5
+            0x3D, 0x07, 0x00, // cmp ax, 7
+            0x7C, 0x03, // jl 0x12
+            0xBB, 0x01, 0x00, // mov bx, 1
+            0xBA, 0x01, 0x00, // mov dx, 1
+        ];
+        let mut cpu_state = CpuState::new(&Image::flat(&program), &symbols)?;
+        cpu_state.exec()?;
+
+        assert_eq!(cpu_state.registers[0], 6);
+        assert_eq!(cpu_state.registers[1], 0);
+        assert_eq!(cpu_state.registers[2], 0);
+        assert_eq!(cpu_state.registers[3], 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_call_and_return() -> anyhow::Result<()> {
+        let symbols = Symbols::default();
+        let program = [
+            0xB8, 0x34, 0x12, // mov ax, 0x1234
+            0x50, // push ax
+            0xE8, 0x02, 0x00, // call 0x109
+            0x5B, // pop bx
+            0xC3, // ret, to the PSP
+            0xC3, // ret
+        ];
+        let mut cpu_state = CpuState::load_com(&program, 0x1000, "", &symbols)?;
+        cpu_state.exec()?;
+
+        assert_eq!(cpu_state.registers[1], 0x1234);
+        assert_eq!(cpu_state.registers[4], 0x0000);
+
+        Ok(())
+    }
+}
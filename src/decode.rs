@@ -1,81 +1,99 @@
 use anyhow::Result;
 use std::collections::BTreeMap;
 
-use crate::memory::Displacement::{Disp16, Disp8};
+use crate::encoding::{Encoding, Immediate, Location, ModRm, Reg};
+use crate::format::{exact, Palette, Plain, Style, Styled};
+use crate::instruction::{Form, Repeat};
+use crate::memory::Displacement::Disp16;
 use crate::memory::{Displacement, Memory};
 use crate::operand::Operand;
-use crate::register::Register;
+use crate::register::{Register, SegmentRegister};
 use crate::{instruction::Instruction, memory::Address};
 
-pub fn disassemble<T>(bytes: &mut T) -> Result<String>
+pub fn disassemble<T>(bytes: &mut T, palette: Palette) -> Result<String>
 where
     T: Iterator<Item = (Address, u8)>,
 {
-    let mut bytes = CountingPeekable::new(bytes);
-
     let mut disassembly = String::new();
     disassembly.push_str("bits 16\n\n");
 
-    let mut instructions = Vec::new();
+    let instructions = decode_linear(bytes)?;
     let mut label_addresses = BTreeMap::new();
 
-    while let Some((addr, _byte)) = bytes.peek().cloned() {
-        let instruction = decode_instruction(&mut bytes)?;
-
+    for (addr, instruction) in &instructions {
         if let Some(jmp) = instruction.to_jump() {
-            let byte_count = jmp.ip_increment() + jmp.len();
-
-            let label_address = Address(
-                (addr.0 as i32)
-                    .checked_add(byte_count as i32)
-                    .expect("Overflow when adding") as u16,
-            );
+            let label_address = jmp.target(*addr);
 
             if !label_addresses.contains_key(&label_address) {
                 label_addresses.insert(label_address, label_addresses.len() as u16);
             }
         }
-
-        instructions.push((addr, instruction));
     }
 
     for (address, instruction) in instructions {
         if let Some(label_index) = label_addresses.get(&address) {
-            disassembly.push_str(&format!("label{label_index}:\n"));
+            let label = palette.paint(Style::Label, &format!("label{label_index}"));
+            disassembly.push_str(&format!("{label}:\n"));
         }
 
         if let Some(jmp) = instruction.to_jump() {
-            let byte_count = jmp.ip_increment() + jmp.len();
-
-            let target = Address(
-                (address.0 as i32)
-                    .checked_add(byte_count as i32)
-                    .expect("Overflow when adding") as u16,
-            );
+            let target = jmp.target(address);
 
             let label_index = label_addresses
                 .get(&target)
                 .expect("The address of the label should be present in label_addresses");
 
-            disassembly.push_str(&(format!("{jmp} label{label_index}") + "\n"))
+            disassembly.push_str(&format!(
+                "{} {}\n",
+                palette.paint(Style::Branch, &jmp.to_string()),
+                palette.paint(Style::Label, &format!("label{label_index}"))
+            ))
         } else {
-            disassembly.push_str(&(instruction.to_string() + "\n"))
+            let text = instruction.format(&Styled::new(&Plain, palette));
+            disassembly.push_str(&(exact(&instruction, text, palette) + "\n"))
         }
     }
 
     Ok(disassembly)
 }
 
+/// Decodes every instruction in the stream, one after the other.
+pub fn decode_linear<T>(bytes: &mut T) -> Result<Vec<(Address, Instruction)>>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let mut bytes = CountingPeekable::new(bytes);
+    let mut instructions = Vec::new();
+
+    while let Some((address, _byte)) = bytes.peek().cloned() {
+        instructions.push((address, decode_instruction(&mut bytes)?));
+    }
+
+    Ok(instructions)
+}
+
+pub fn decode_at(bytes: &[u8], address: Address) -> Result<Instruction> {
+    let instruction_iterator = bytes[address.0 as usize..]
+        .iter()
+        .enumerate()
+        .map(|(i, v)| (Address(address.0.wrapping_add(i as u16)), *v));
+
+    decode_instruction(&mut CountingPeekable::new(instruction_iterator))
+}
+
 macro_rules! decode_arithmetic_binop_register_to_either {
     ($bytes:expr, $variant:ident) => {{
         let (_, byte1) = $bytes.try_next()?;
         let d = (byte1 & 0b00000010) >> 1;
         let w = byte1 & 0b00000001;
+        $bytes.fields.d = Some(d);
+        $bytes.fields.w = Some(w);
 
         let [dst, src] = Operand::from_mod_reg_rm(d, w, $bytes)?;
 
         Ok(Instruction::$variant {
-            sz: $bytes.get_count() as u8,
+            encoding: $bytes.encoding(),
+            form: Form::RegisterMemory { d: d == 1 },
             dst,
             src,
         })
@@ -84,32 +102,39 @@ macro_rules! decode_arithmetic_binop_register_to_either {
 
 macro_rules! decode_arithmetic_binop_immediate_to_register_memory {
     ($bytes:expr, { $($pattern:expr => $variant:ident),+ $(,)? }) => {{
-        let (_, byte1) = $bytes.try_next()?;
-        let (_, mod_rm) = $bytes.try_next()?;
+        let (address, byte1) = $bytes.try_next()?;
+        let mod_rm = $bytes.next_mod_rm(Reg::Extension)?;
 
         let s = (byte1 & 0b00000010) >> 1;
         let w = byte1 & 0b00000001;
+        $bytes.fields.s = Some(s);
+        $bytes.fields.w = Some(w);
 
         let dst = Operand::from_mod_rm(w, mod_rm, $bytes)?;
 
         let src = if w == 0
         {
             let (_address, data) = $bytes.try_next()?;
+            $bytes.fields.immediate = Some(Immediate::Byte(data));
             Operand::Immediate8(data)
         } else if s == 0 {
             let (_address, data_lo) = $bytes.try_next()?;
             let (_address, data_hi) = $bytes.try_next()?;
-            Operand::Immediate16(((data_hi as u16) << 8) | data_lo as u16)
+            let data = ((data_hi as u16) << 8) | data_lo as u16;
+            $bytes.fields.immediate = Some(Immediate::Word(data));
+            Operand::Immediate16(data)
         } else {
             let (_address, data_lo) = $bytes.try_next()?;
-            Operand::Immediate16(((0u16) << 8) | data_lo as u16)
+            $bytes.fields.immediate = Some(Immediate::SignExtended(data_lo));
+            Operand::Immediate16(data_lo as i8 as u16)
         };
+        let form = Form::ImmediateToRegisterMemory { opcode: byte1 };
 
         match (mod_rm & 0b00111000) {
             $(
-                $pattern => Ok(Instruction::$variant { sz: $bytes.get_count() as u8, dst, src }),
+                $pattern => Ok(Instruction::$variant { encoding: $bytes.encoding(), form, dst, src }),
             )*
-            _ => unreachable!()
+            _ => Err(crate::error::Error::UnknownInstruction(byte1, address).into()),
         }
     }};
 }
@@ -118,6 +143,7 @@ macro_rules! decode_arithmetic_binop_immediate_to_accumulator {
     ($bytes:expr, $variant:ident) => {{
         let (_, byte1) = $bytes.try_next()?;
         let w = byte1 & 0b00000001;
+        $bytes.fields.w = Some(w);
 
         let dst = if w == 0 {
             Operand::Register(Register::Al)
@@ -127,15 +153,19 @@ macro_rules! decode_arithmetic_binop_immediate_to_accumulator {
 
         let src = if w == 0 {
             let (_address, data) = $bytes.try_next()?;
+            $bytes.fields.immediate = Some(Immediate::Byte(data));
             Operand::Immediate8(data)
         } else {
             let (_address, data_lo) = $bytes.try_next()?;
             let (_address, data_hi) = $bytes.try_next()?;
-            Operand::Immediate16(((data_hi as u16) << 8) | data_lo as u16)
+            let data = ((data_hi as u16) << 8) | data_lo as u16;
+            $bytes.fields.immediate = Some(Immediate::Word(data));
+            Operand::Immediate16(data)
         };
 
         Ok(Instruction::$variant {
-            sz: $bytes.get_count() as u8,
+            encoding: $bytes.encoding(),
+            form: Form::Short,
             dst,
             src,
         })
@@ -144,16 +174,86 @@ macro_rules! decode_arithmetic_binop_immediate_to_accumulator {
 
 macro_rules! decode_jump {
     ($bytes:expr, $variant:ident) => {{
-        $bytes.try_next()?;
+        let (_, byte1) = $bytes.try_next()?;
         let (_, ip_increment) = $bytes.try_next()?;
+        // Conditional jumps are `0111cccc`, the loops and `jcxz` have no condition field.
+        if byte1 & 0b1111_0000 == 0b0111_0000 {
+            $bytes.fields.condition = Some(byte1 & 0b0000_1111);
+        }
+        $bytes.fields.location = Some(Location::IpIncrement8(ip_increment));
 
         Ok(Instruction::$variant {
-            sz: $bytes.get_count() as u8,
+            encoding: $bytes.encoding(),
             ip_increment: ip_increment as i8,
         })
     }};
 }
 
+macro_rules! decode_near_jump {
+    ($bytes:expr, $variant:ident) => {{
+        $bytes.try_next()?;
+        let (_address, data_lo) = $bytes.try_next()?;
+        let (_address, data_hi) = $bytes.try_next()?;
+        let ip_increment = ((data_hi as u16) << 8) | data_lo as u16;
+        $bytes.fields.location = Some(Location::IpIncrement16(ip_increment));
+
+        Ok(Instruction::$variant {
+            encoding: $bytes.encoding(),
+            ip_increment: ip_increment as i16,
+        })
+    }};
+}
+
+macro_rules! decode_far_jump {
+    ($bytes:expr, $variant:ident) => {{
+        $bytes.try_next()?;
+        let (_address, offset_lo) = $bytes.try_next()?;
+        let (_address, offset_hi) = $bytes.try_next()?;
+        let (_address, segment_lo) = $bytes.try_next()?;
+        let (_address, segment_hi) = $bytes.try_next()?;
+        let segment = ((segment_hi as u16) << 8) | segment_lo as u16;
+        let offset = ((offset_hi as u16) << 8) | offset_lo as u16;
+        $bytes.fields.location = Some(Location::Far { offset, segment });
+
+        Ok(Instruction::$variant {
+            encoding: $bytes.encoding(),
+            segment,
+            offset,
+        })
+    }};
+}
+
+macro_rules! decode_return {
+    ($bytes:expr, $variant:ident) => {{
+        let (_, byte1) = $bytes.try_next()?;
+
+        let pop = if byte1 & 0b0000_0001 == 0 {
+            let (_address, data_lo) = $bytes.try_next()?;
+            let (_address, data_hi) = $bytes.try_next()?;
+            let data = ((data_hi as u16) << 8) | data_lo as u16;
+            $bytes.fields.immediate = Some(Immediate::Word(data));
+            Some(data)
+        } else {
+            None
+        };
+
+        Ok(Instruction::$variant {
+            encoding: $bytes.encoding(),
+            pop,
+        })
+    }};
+}
+
+/// Decodes an instruction that is nothing but its opcode, like the flag instructions.
+macro_rules! decode_single_byte {
+    ($bytes:expr, $variant:ident) => {{
+        $bytes.try_next()?;
+        Ok(Instruction::$variant {
+            encoding: $bytes.encoding(),
+        })
+    }};
+}
+
 pub fn decode_instruction<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
 where
     T: Iterator<Item = (Address, u8)>,
@@ -171,15 +271,24 @@ where
         0b1100_0110..=0b1100_0111 => decode_mov_immediate_to_reg_mem(bytes),
         0b1010_0000..=0b1010_0001 => decode_mem_to_accumulator(bytes),
         0b1010_0010..=0b1010_0011 => decode_accumulator_to_mem(bytes),
+        0b1000_1100 | 0b1000_1110 => decode_mov_segment(bytes),
 
         0b0000_0000..=0b0000_0011 => decode_arithmetic_binop_register_to_either!(bytes, Add),
         0b0010_1000..=0b0010_1011 => decode_arithmetic_binop_register_to_either!(bytes, Sub),
         0b0011_1000..=0b0011_1011 => decode_arithmetic_binop_register_to_either!(bytes, Cmp),
+        0b0000_1000..=0b0000_1011 => decode_arithmetic_binop_register_to_either!(bytes, Or),
+        0b0001_0000..=0b0001_0011 => decode_arithmetic_binop_register_to_either!(bytes, Adc),
+        0b0001_1000..=0b0001_1011 => decode_arithmetic_binop_register_to_either!(bytes, Sbb),
+        0b0011_0000..=0b0011_0011 => decode_arithmetic_binop_register_to_either!(bytes, Xor),
         0b1000_0000..=0b1000_0011 => decode_arithmetic_binop_immediate_to_register_memory!(
             bytes,
             {
                 0b0000_0000 => Add,
+                0b0000_1000 => Or,
+                0b0001_0000 => Adc,
+                0b0001_1000 => Sbb,
                 0b0010_1000 => Sub,
+                0b0011_0000 => Xor,
                 0b0011_1000 => Cmp,
             }
         ),
@@ -192,6 +301,29 @@ where
         0b0011_1100..=0b0011_1111 => {
             decode_arithmetic_binop_immediate_to_accumulator!(bytes, Cmp)
         }
+        0b0000_1100..=0b0000_1101 => {
+            decode_arithmetic_binop_immediate_to_accumulator!(bytes, Or)
+        }
+        0b0001_0100..=0b0001_0101 => {
+            decode_arithmetic_binop_immediate_to_accumulator!(bytes, Adc)
+        }
+        0b0001_1100..=0b0001_1101 => {
+            decode_arithmetic_binop_immediate_to_accumulator!(bytes, Sbb)
+        }
+        0b0011_0100..=0b0011_0101 => {
+            decode_arithmetic_binop_immediate_to_accumulator!(bytes, Xor)
+        }
+
+        0b1001_1001 => {
+            bytes.try_next()?;
+            Ok(Instruction::Cwd {
+                encoding: bytes.encoding(),
+            })
+        }
+        0b1111_0110..=0b1111_0111 => decode_group_f6(bytes),
+        0b1101_0000..=0b1101_0011 => decode_shift(bytes),
+        0b1010_0100..=0b1010_0101 => decode_movs(bytes),
+        0b1111_0010..=0b1111_0011 => decode_repeat(bytes),
 
         0b0111_0100 => decode_jump!(bytes, Je),
         0b0111_1100 => decode_jump!(bytes, Jl),
@@ -214,20 +346,238 @@ where
         0b1110_0000 => decode_jump!(bytes, Loopnz),
         0b1110_0011 => decode_jump!(bytes, Jcxz),
 
+        0b1110_1000 => decode_near_jump!(bytes, Call),
+        0b1110_1001 => decode_near_jump!(bytes, Jmp),
+        0b1110_1011 => decode_jump!(bytes, JmpShort),
+        0b1001_1010 => decode_far_jump!(bytes, CallFar),
+        0b1110_1010 => decode_far_jump!(bytes, JmpFar),
+        0b1100_0010..=0b1100_0011 => decode_return!(bytes, Ret),
+        0b1100_1010..=0b1100_1011 => decode_return!(bytes, Retf),
+        0b1100_1111 => {
+            bytes.try_next()?;
+            Ok(Instruction::Iret {
+                encoding: bytes.encoding(),
+            })
+        }
+        0b1100_1101 => {
+            bytes.try_next()?;
+            let (_, vector) = bytes.try_next()?;
+            bytes.fields.immediate = Some(Immediate::Byte(vector));
+            Ok(Instruction::Int {
+                encoding: bytes.encoding(),
+                vector,
+            })
+        }
+
+        0b1111_1000 => decode_single_byte!(bytes, Clc),
+        0b1111_1001 => decode_single_byte!(bytes, Stc),
+        0b1111_1010 => decode_single_byte!(bytes, Cli),
+        0b1111_1011 => decode_single_byte!(bytes, Sti),
+        0b1111_1100 => decode_single_byte!(bytes, Cld),
+        0b1111_1101 => decode_single_byte!(bytes, Std),
+        0b1001_0000 => decode_single_byte!(bytes, Nop),
+
+        0b0101_0000..=0b0101_1111 => decode_push_pop_register(bytes),
+        0b1000_1111 => decode_pop_register_memory(bytes),
+        0b1111_1111 => decode_group_ff(bytes),
+
+        _ => Err(crate::error::Error::UnknownInstruction(byte, address).into()),
+    }
+}
+
+/// Decodes `mov` to (`8E`) or from (`8C`) a segment register, whose `reg` field names the
+/// segment register and whose `r/m` operand is always a word.
+fn decode_mov_segment<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (_address1, byte1) = bytes.try_next()?;
+    let mod_rm = bytes.next_mod_rm(Reg::Segment)?;
+    let operand = Operand::from_mod_rm(1, mod_rm, bytes)?;
+
+    Ok(Instruction::MovSegment {
+        encoding: bytes.encoding(),
+        to_segment: byte1 & 0b0000_0010 != 0,
+        segment: SegmentRegister::decode_sreg((mod_rm >> 3) & 0b111),
+        operand,
+    })
+}
+
+fn decode_push_pop_register<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (_address1, byte1) = bytes.try_next()?;
+    bytes.fields.register = Some(byte1 & 0b0000_0111);
+    let operand = Operand::Register(Register::decode_reg(byte1 & 0b0000_0111, 1));
+
+    if byte1 & 0b0000_1000 == 0 {
+        Ok(Instruction::Push {
+            encoding: bytes.encoding(),
+            form: Form::Short,
+            src: operand,
+        })
+    } else {
+        Ok(Instruction::Pop {
+            encoding: bytes.encoding(),
+            form: Form::Short,
+            dst: operand,
+        })
+    }
+}
+
+fn decode_pop_register_memory<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (address, byte1) = bytes.try_next()?;
+    let mod_rm = bytes.next_mod_rm(Reg::Extension)?;
+
+    if mod_rm & 0b0011_1000 != 0 {
+        return Err(crate::error::Error::UnknownInstruction(byte1, address).into());
+    }
+
+    let dst = Operand::from_mod_rm(1, mod_rm, bytes)?;
+    Ok(Instruction::Pop {
+        encoding: bytes.encoding(),
+        form: Form::RegisterMemory { d: false },
+        dst,
+    })
+}
+
+/// Decodes the `FF` group, whose `reg` field selects the operation.
+fn decode_group_ff<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (address, byte1) = bytes.try_next()?;
+    let mod_rm = bytes.next_mod_rm(Reg::Extension)?;
+    let operand = Operand::from_mod_rm(1, mod_rm, bytes)?;
+    let encoding = bytes.encoding();
+
+    match mod_rm & 0b0011_1000 {
+        0b0001_0000 => Ok(Instruction::CallIndirect {
+            encoding,
+            far: false,
+            target: operand,
+        }),
+        0b0001_1000 => Ok(Instruction::CallIndirect {
+            encoding,
+            far: true,
+            target: operand,
+        }),
+        0b0010_0000 => Ok(Instruction::JmpIndirect {
+            encoding,
+            far: false,
+            target: operand,
+        }),
+        0b0010_1000 => Ok(Instruction::JmpIndirect {
+            encoding,
+            far: true,
+            target: operand,
+        }),
+        0b0011_0000 => Ok(Instruction::Push {
+            encoding,
+            form: Form::RegisterMemory { d: false },
+            src: operand,
+        }),
+        _ => Err(crate::error::Error::UnknownInstruction(byte1, address).into()),
+    }
+}
+
+/// Decodes the `F6`/`F7` group, of which only `idiv` is supported.
+fn decode_group_f6<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (address, byte1) = bytes.try_next()?;
+    let mod_rm = bytes.next_mod_rm(Reg::Extension)?;
+    if mod_rm & 0b0011_1000 != 0b0011_1000 {
+        return Err(crate::error::Error::UnknownInstruction(byte1, address).into());
+    }
+
+    bytes.fields.w = Some(byte1 & 0b0000_0001);
+    let src = Operand::from_mod_rm(byte1 & 0b0000_0001, mod_rm, bytes)?;
+    Ok(Instruction::Idiv {
+        encoding: bytes.encoding(),
+        src,
+    })
+}
+
+/// Decodes the `D0`-`D3` shift and rotate group, of which only `shl` is supported.
+fn decode_shift<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (address, byte1) = bytes.try_next()?;
+    let mod_rm = bytes.next_mod_rm(Reg::Extension)?;
+    if mod_rm & 0b0011_1000 != 0b0010_0000 {
+        return Err(crate::error::Error::UnknownInstruction(byte1, address).into());
+    }
+
+    bytes.fields.w = Some(byte1 & 0b0000_0001);
+    let dst = Operand::from_mod_rm(byte1 & 0b0000_0001, mod_rm, bytes)?;
+    let count = match byte1 & 0b0000_0010 {
+        0 => Operand::Immediate8(1),
+        _ => Operand::Register(Register::Cl),
+    };
+    Ok(Instruction::Shl {
+        encoding: bytes.encoding(),
+        dst,
+        count,
+    })
+}
+
+/// Decodes a `rep`/`repne` prefix and the string instruction it repeats, which can only
+/// be `movs` yet.
+fn decode_repeat<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (_address, prefix) = bytes.try_next()?;
+    bytes.fields.prefixes.push(prefix);
+
+    let (address, byte) = bytes
+        .peek()
+        .cloned()
+        .ok_or(crate::error::Error::EndOfInstructionStream())?;
+    match byte {
+        0b1010_0100..=0b1010_0101 => decode_movs(bytes),
         _ => Err(crate::error::Error::UnknownInstruction(byte, address).into()),
     }
 }
 
+/// Decodes `movsb`/`movsw`, repeated when [`decode_repeat`] read a prefix in front of it.
+fn decode_movs<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
+where
+    T: Iterator<Item = (Address, u8)>,
+{
+    let (_address, byte1) = bytes.try_next()?;
+    bytes.fields.w = Some(byte1 & 0b0000_0001);
+
+    let rep = match bytes.fields.prefixes.last() {
+        Some(0b1111_0011) => Some(Repeat::Rep),
+        Some(0b1111_0010) => Some(Repeat::Repne),
+        _ => None,
+    };
+    Ok(Instruction::Movs {
+        encoding: bytes.encoding(),
+        rep,
+    })
+}
+
 fn decode_mov_immediate_to_reg_mem<T>(bytes: &mut CountingPeekable<T>) -> Result<Instruction>
 where
     T: Iterator<Item = (Address, u8)>,
 {
     let (_address1, byte1) = bytes.try_next()?;
     let w = byte1 & 0b00000001;
+    bytes.fields.w = Some(w);
 
     let [dst, src] = Operand::immediate(w, bytes)?;
     Ok(Instruction::Mov {
-        sz: bytes.get_count() as u8,
+        encoding: bytes.encoding(),
+        form: Form::ImmediateToRegisterMemory { opcode: byte1 },
         dst,
         src,
     })
@@ -241,22 +591,29 @@ where
 
     let w = (byte1 & 0b00001000) >> 3;
     let reg = byte1 & 0b00000111;
+    bytes.fields.w = Some(w);
+    bytes.fields.register = Some(reg);
 
     let (_address2, byte2) = bytes.try_next()?;
 
     if w == 0 {
+        bytes.fields.immediate = Some(Immediate::Byte(byte2));
         Ok(Instruction::Mov {
-            sz: bytes.get_count() as u8,
+            encoding: bytes.encoding(),
+            form: Form::Short,
             dst: Operand::Register(Register::decode_reg(reg, w)),
             src: Operand::Immediate8(byte2),
         })
     } else {
         let (_address3, byte3) = bytes.try_next()?;
+        let data = ((byte3 as u16) << 8) | byte2 as u16;
+        bytes.fields.immediate = Some(Immediate::Word(data));
 
         Ok(Instruction::Mov {
-            sz: bytes.get_count() as u8,
+            encoding: bytes.encoding(),
+            form: Form::Short,
             dst: Operand::Register(Register::decode_reg(reg, w)),
-            src: Operand::Immediate16(((byte3 as u16) << 8) | byte2 as u16),
+            src: Operand::Immediate16(data),
         })
     }
 }
@@ -269,11 +626,14 @@ where
 
     let d = (byte1 & 0b00000010) >> 1;
     let w = byte1 & 0b00000001;
+    bytes.fields.d = Some(d);
+    bytes.fields.w = Some(w);
 
     let [dst, src] = Operand::from_mod_reg_rm(d, w, bytes)?;
 
     Ok(Instruction::Mov {
-        sz: bytes.get_count() as u8,
+        encoding: bytes.encoding(),
+        form: Form::RegisterMemory { d: d == 1 },
         dst,
         src,
     })
@@ -286,7 +646,8 @@ where
     let (reg, displacement) = decode_accumulator_and_mem(bytes)?;
 
     Ok(Instruction::Mov {
-        sz: bytes.get_count() as u8,
+        encoding: bytes.encoding(),
+        form: Form::Accumulator,
         dst: Operand::Register(reg),
         src: Operand::Memory(Memory {
             displacement,
@@ -302,7 +663,8 @@ where
     let (reg, displacement) = decode_accumulator_and_mem(bytes)?;
 
     Ok(Instruction::Mov {
-        sz: bytes.get_count() as u8,
+        encoding: bytes.encoding(),
+        form: Form::Accumulator,
         dst: Operand::Memory(Memory {
             displacement,
             registers: [None, None],
@@ -319,17 +681,19 @@ where
 {
     let (_address1, byte1) = bytes.try_next()?;
     let w = byte1 & 0b00000001;
+    bytes.fields.w = Some(w);
+
+    // The address is always 16 bits wide, `w` only selects between al and ax.
+    let (_address, data_lo) = bytes.try_next()?;
+    let (_address, data_hi) = bytes.try_next()?;
+    let address = ((data_hi as u16) << 8) | data_lo as u16;
+    bytes.fields.location = Some(Location::Address(address));
+    let displacement = Disp16(address);
 
     if w == 0 {
-        let (_address, data) = bytes.try_next()?;
-        Ok((Register::Al, Disp8(data)))
+        Ok((Register::Al, displacement))
     } else {
-        let (_address, data_lo) = bytes.try_next()?;
-        let (_address, data_hi) = bytes.try_next()?;
-        Ok((
-            Register::Ax,
-            Disp16(((data_hi as u16) << 8) | data_lo as u16),
-        ))
+        Ok((Register::Ax, displacement))
     }
 }
 
@@ -347,18 +711,19 @@ where
     }
 }
 
+/// Peekable iterator that keeps the bytes consumed since the last reset, which make up
+/// the instruction being decoded, and the encoding fields the decoder split them into.
 pub(crate) struct CountingPeekable<I: std::iter::Iterator> {
     iter: I,
-    counter: usize,
+    pub(crate) fields: Encoding,
     peeked: Option<Option<I::Item>>,
 }
 
-impl<I, IT> Iterator for CountingPeekable<I>
+impl<I> Iterator for CountingPeekable<I>
 where
-    I: std::iter::Iterator<Item = IT>,
-    IT: Clone,
+    I: std::iter::Iterator<Item = (Address, u8)>,
 {
-    type Item = IT;
+    type Item = (Address, u8);
 
     fn next(&mut self) -> Option<I::Item> {
         let item = match self.peeked.take() {
@@ -366,8 +731,8 @@ where
             None => self.iter.next(),
         };
 
-        if item.is_some() {
-            self.counter += 1;
+        if let Some((_, byte)) = item {
+            self.fields.bytes.push(byte);
         }
 
         item
@@ -376,13 +741,12 @@ where
 
 impl<I> CountingPeekable<I>
 where
-    I: std::iter::Iterator,
-    I::Item: Clone,
+    I: std::iter::Iterator<Item = (Address, u8)>,
 {
     pub(crate) fn new(iter: I) -> Self {
         Self {
             iter,
-            counter: 0,
+            fields: Encoding::default(),
             peeked: None,
         }
     }
@@ -391,18 +755,27 @@ where
         self.peeked.get_or_insert_with(|| self.iter.next()).as_ref()
     }
 
-    fn get_count(&self) -> usize {
-        self.counter
+    /// Reads a ModRM byte and records its fields.
+    pub(crate) fn next_mod_rm(&mut self, kind: Reg) -> Result<u8, crate::error::Error> {
+        let (_, byte) = self.try_next()?;
+        self.fields.mod_rm = Some(ModRm::new(byte, kind));
+        Ok(byte)
+    }
+
+    fn encoding(&mut self) -> Encoding {
+        std::mem::take(&mut self.fields).finish()
     }
 
     fn reset_count(&mut self) {
-        self.counter = 0;
+        self.fields = Encoding::default();
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::decode::disassemble;
+    use crate::decode::{decode_linear, disassemble};
+    use crate::error::Error;
+    use crate::format::Palette;
     use crate::memory::Address;
     use std::fs::File;
     use std::io::{self, BufReader, Read, Write};
@@ -419,31 +792,25 @@ mod tests {
                 Ok(it) => it,
                 Err(e) => {
                     eprintln!("{}", e);
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Error disassembling {}", asm_file),
-                    )
-                    .into());
+                    return Err(
+                        io::Error::other(format!("Error disassembling {}", asm_file)).into(),
+                    );
                 }
             };
             let reassembled_bin = match assemble_from_string(&disassembled_output) {
                 Ok(it) => it,
                 Err(e) => {
                     eprintln!("{}", e);
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Error assembling {}", asm_file),
-                    )
-                    .into());
+                    return Err(io::Error::other(format!("Error assembling {}", asm_file)).into());
                 }
             };
 
             if original_bin != reassembled_bin {
                 eprintln!("disassembly:\n{}", disassembled_output);
-                return Err(io::Error::new(
-                    io::ErrorKind::Other,
-                    format!("Binaries do not match for file {}", asm_file),
-                )
+                return Err(io::Error::other(format!(
+                    "Binaries do not match for file {}",
+                    asm_file
+                ))
                 .into());
             }
         }
@@ -451,6 +818,154 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_non_canonical_encodings() -> anyhow::Result<()> {
+        let bin = [
+            0x8B, 0xC3, // mov ax, bx with the d bit set
+            0x82, 0xC3, 0x05, // 82 alias of 80
+            0x81, 0xC3, 0x05, 0x00, // 81 with an immediate that fits in a byte
+            0x83, 0xC3, 0xFF, // 83 sign-extends its immediate
+            0x8B, 0x87, 0x05, 0x00, // disp16 where disp8 would do
+            0x8B, 0x47, 0x00, // disp8 of zero
+            0x8A, 0x06, 0x34, 0x12, // 8A with a direct address instead of A0
+        ];
+
+        let disassembly = disassemble_binary(&bin)?;
+        let lines: Vec<&str> = disassembly.lines().skip(2).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "db 0x8b, 0xc3 ; mov ax, bx",
+                "db 0x82, 0xc3, 0x05 ; add bl, byte 5",
+                "add bx, strict word 5",
+                "add bx, word 65535",
+                "mov ax, [word bx + 5]",
+                "mov ax, [byte bx + 0]",
+                "db 0x8a, 0x06, 0x34, 0x12 ; mov al, [4660]",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_branch_targets_wrap() -> anyhow::Result<()> {
+        // Displacements that reach past the end of the segment wrap around it.
+        let bin = [
+            0xE8, 0xFF, 0x7F, // call 0x1002
+            0xE9, 0xFE, 0x7F, // jmp 0x1004
+        ];
+
+        let instructions = decode_linear(
+            &mut bin
+                .iter()
+                .enumerate()
+                .map(|(i, b)| (Address(0x9000 + i as u16), *b)),
+        )?;
+        let targets: Vec<u16> = instructions
+            .iter()
+            .filter_map(|(address, instruction)| Some(instruction.to_jump()?.target(*address).0))
+            .collect();
+        assert_eq!(targets, vec![0x1002, 0x1004]);
+
+        let disassembly = disassemble_binary(&bin)?;
+        let lines: Vec<&str> = disassembly.lines().skip(2).collect();
+        assert_eq!(lines, vec!["call label0", "jmp near label1"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repeat_prefixes() -> anyhow::Result<()> {
+        let bin = [
+            0xF3, 0xA5, // rep movsw
+            0xF2, 0xA4, // repne movsb
+        ];
+        let disassembly = disassemble_binary(&bin)?;
+        let lines: Vec<&str> = disassembly.lines().skip(2).collect();
+        assert_eq!(lines, vec!["rep movsw", "repne movsb"]);
+
+        // A prefix in front of anything else fails on that byte, not on the prefix.
+        let bin = [0xF3, 0xA5, 0xF3, 0xC3];
+        let Err(error) =
+            decode_linear(&mut bin.iter().enumerate().map(|(i, b)| (Address(i as u16), *b)))
+        else {
+            panic!("a prefix in front of ret should not decode");
+        };
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(Error::UnknownInstruction(0xC3, Address(3)))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_segment_moves_and_flags() -> anyhow::Result<()> {
+        let bin = [
+            0x8E, 0xD0, // mov ss, ax
+            0x8E, 0x1E, 0x34, 0x12, // mov ds, [0x1234]
+            0x8C, 0xC3, // mov bx, es
+            0x8C, 0x4E, 0x02, // mov [bp + 2], cs
+            0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD, 0x90,
+        ];
+        let disassembly = disassemble_binary(&bin)?;
+        let lines: Vec<&str> = disassembly.lines().skip(2).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "mov ss, ax",
+                "mov ds, [4660]",
+                "mov bx, es",
+                "mov [bp + 2], cs",
+                "clc",
+                "stc",
+                "cli",
+                "sti",
+                "cld",
+                "std",
+                "nop",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_accumulator_moves() -> anyhow::Result<()> {
+        // The address is a word even when `w` picks al.
+        let bin = [
+            0xA0, 0x34, 0x12, // mov al, [0x1234]
+            0xA2, 0x78, 0x56, // mov [0x5678], al
+            0xA1, 0x10, 0x00, // mov ax, [0x10]
+            0xA3, 0x20, 0x00, // mov [0x20], ax
+        ];
+
+        let instructions =
+            decode_linear(&mut bin.iter().enumerate().map(|(i, b)| (Address(i as u16), *b)))?;
+        let decoded: Vec<(u16, String, usize)> = instructions
+            .iter()
+            .map(|(address, instruction)| {
+                (
+                    address.0,
+                    instruction.to_string(),
+                    instruction.get_size() as usize,
+                )
+            })
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![
+                (0, "mov al, [4660]".to_string(), 3),
+                (3, "mov [22136], al".to_string(), 3),
+                (6, "mov ax, [16]".to_string(), 3),
+                (9, "mov [32], ax".to_string(), 3),
+            ]
+        );
+
+        Ok(())
+    }
+
     fn get_test_file_paths() -> io::Result<Vec<String>> {
         let mut file_paths = Vec::new();
 
@@ -477,13 +992,10 @@ mod tests {
             fs::remove_file(&output_file)?;
             Ok(data)
         } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Failed to assemble file: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            ))
+            Err(io::Error::other(format!(
+                "Failed to assemble file: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )))
         }
     }
 
@@ -513,13 +1025,10 @@ mod tests {
             fs::remove_file(&out_file_path)?;
             Ok(data)
         } else {
-            Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Failed to assemble from string: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            ))
+            Err(io::Error::other(format!(
+                "Failed to assemble from string: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )))
         }
     }
 
@@ -535,7 +1044,7 @@ mod tests {
             })
             .peekable();
 
-        let disassembly = disassemble(&mut disassemble_bytes)?;
+        let disassembly = disassemble(&mut disassemble_bytes, Palette::Plain)?;
 
         Ok(disassembly)
     }
@@ -0,0 +1,521 @@
+use crate::cfg::ControlFlowGraph;
+use crate::flow::{Item, Program};
+use crate::format::Palette;
+use crate::instruction::Instruction;
+use crate::listing::format_instruction;
+use crate::memory::Address;
+use crate::symbols::Symbols;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Share of matching instructions above which two functions or blocks are taken to be
+/// versions of each other.
+const MIN_SIMILARITY: f64 = 0.5;
+
+/// One traced binary of the pair being compared.
+pub struct Side<'a> {
+    pub program: &'a Program,
+    pub symbols: &'a Symbols,
+}
+
+/// The two sides, and where each paired function and block of the old side starts on the
+/// new one, which is where its branches should now go.
+struct Pairing<'a> {
+    old: &'a Side<'a>,
+    new: &'a Side<'a>,
+    moved: BTreeMap<Address, Address>,
+}
+
+struct Block<'a> {
+    start: Address,
+    instructions: Vec<(Address, &'a Instruction)>,
+    keys: Vec<String>,
+}
+
+struct Function<'a> {
+    start: Address,
+    name: String,
+    /// Whether the name came from a symbol file or the project rather than the address.
+    named: bool,
+    blocks: Vec<Block<'a>>,
+    keys: Vec<String>,
+}
+
+/// Step of an alignment between two sequences.
+#[derive(Copy, Clone)]
+enum Step {
+    Both(usize, usize),
+    Old(usize),
+    New(usize),
+}
+
+#[derive(Default)]
+struct Counts {
+    changed: usize,
+    re_encoded: usize,
+    inserted: usize,
+    deleted: usize,
+}
+
+/// Compares the traced code of two binaries. Functions are paired up first, then the basic
+/// blocks within paired functions and then the instructions within paired blocks, so code
+/// that moved to another address still lines up.
+pub fn diff(old: &Side, new: &Side) -> String {
+    let old_functions = functions(old);
+    let new_functions = functions(new);
+    let mut report = String::new();
+    let mut counts = Counts::default();
+
+    let steps = align(old_functions.len(), new_functions.len(), |i, j| {
+        let (a, b) = (&old_functions[i], &new_functions[j]);
+        if a.named && b.named {
+            a.name == b.name
+        } else {
+            similarity(&a.keys, &b.keys) >= MIN_SIMILARITY
+        }
+    });
+
+    // Blocks are paired in every function before any is compared, so a branch into
+    // another function can be checked too.
+    let mut pairing = Pairing {
+        old,
+        new,
+        moved: BTreeMap::new(),
+    };
+    let mut block_steps = Vec::new();
+    for step in &steps {
+        if let Step::Both(i, j) = *step {
+            let (a, b) = (&old_functions[i], &new_functions[j]);
+            let blocks = align(a.blocks.len(), b.blocks.len(), |i, j| {
+                similarity(&a.blocks[i].keys, &b.blocks[j].keys) >= MIN_SIMILARITY
+            });
+            pairing.moved.insert(a.start, b.start);
+            for block in &blocks {
+                if let Step::Both(i, j) = *block {
+                    pairing.moved.insert(a.blocks[i].start, b.blocks[j].start);
+                }
+            }
+            block_steps.push(blocks);
+        }
+    }
+
+    let mut block_steps = block_steps.into_iter();
+    for step in steps {
+        match step {
+            Step::Both(i, j) => {
+                let (a, b) = (&old_functions[i], &new_functions[j]);
+                let blocks = block_steps.next().unwrap_or_default();
+                let mut section = String::new();
+                diff_function(a, b, blocks, &pairing, &mut section, &mut counts);
+                if !section.is_empty() {
+                    let _ = writeln!(
+                        report,
+                        "{} ({:04X}) {} ({:04X})",
+                        a.name, a.start.0, b.name, b.start.0
+                    );
+                    report.push_str(&section);
+                }
+            }
+            Step::Old(i) => {
+                let function = &old_functions[i];
+                let _ = writeln!(report, "- {} ({:04X})", function.name, function.start.0);
+                for block in &function.blocks {
+                    write_block(block, old, '-', &mut report);
+                    counts.deleted += block.instructions.len();
+                }
+            }
+            Step::New(j) => {
+                let function = &new_functions[j];
+                let _ = writeln!(report, "+ {} ({:04X})", function.name, function.start.0);
+                for block in &function.blocks {
+                    write_block(block, new, '+', &mut report);
+                    counts.inserted += block.instructions.len();
+                }
+            }
+        }
+    }
+
+    if report.is_empty() {
+        return "no differences\n".to_string();
+    }
+    let _ = writeln!(
+        report,
+        "{} changed, {} re-encoded, {} inserted, {} deleted",
+        counts.changed, counts.re_encoded, counts.inserted, counts.deleted
+    );
+    report
+}
+
+fn diff_function(
+    a: &Function,
+    b: &Function,
+    steps: Vec<Step>,
+    pairing: &Pairing,
+    report: &mut String,
+    counts: &mut Counts,
+) {
+    let (old, new) = (pairing.old, pairing.new);
+    for step in steps {
+        match step {
+            Step::Both(i, j) => {
+                let (a, b) = (&a.blocks[i], &b.blocks[j]);
+                let mut section = String::new();
+                diff_block(a, b, pairing, &mut section, counts);
+                if !section.is_empty() {
+                    let _ = writeln!(report, "  block {:04X} {:04X}", a.start.0, b.start.0);
+                    report.push_str(&section);
+                }
+            }
+            Step::Old(i) => {
+                write_block(&a.blocks[i], old, '-', report);
+                counts.deleted += a.blocks[i].instructions.len();
+            }
+            Step::New(j) => {
+                write_block(&b.blocks[j], new, '+', report);
+                counts.inserted += b.blocks[j].instructions.len();
+            }
+        }
+    }
+}
+
+fn diff_block(a: &Block, b: &Block, pairing: &Pairing, report: &mut String, counts: &mut Counts) {
+    let (old, new) = (pairing.old, pairing.new);
+    let steps = align(a.keys.len(), b.keys.len(), |i, j| a.keys[i] == b.keys[j]);
+
+    // Deletions and insertions between two matches pair up as changed instructions.
+    let mut deleted = Vec::new();
+    let mut inserted = Vec::new();
+    for step in steps.into_iter().map(Some).chain([None]) {
+        match step {
+            Some(Step::Old(i)) => deleted.push(a.instructions[i]),
+            Some(Step::New(j)) => inserted.push(b.instructions[j]),
+            _ => {
+                for index in 0..deleted.len().max(inserted.len()) {
+                    match (deleted.get(index), inserted.get(index)) {
+                        (Some(&(from, was)), Some(&(to, is))) => {
+                            let _ = writeln!(
+                                report,
+                                "    {:04X} {:04X}  ! {} => {}",
+                                from.0,
+                                to.0,
+                                text(from, was, old),
+                                text(to, is, new)
+                            );
+                            counts.changed += 1;
+                        }
+                        (Some(&(from, was)), None) => {
+                            let _ = writeln!(
+                                report,
+                                "    {:04X}       - {}",
+                                from.0,
+                                text(from, was, old)
+                            );
+                            counts.deleted += 1;
+                        }
+                        (None, Some(&(to, is))) => {
+                            let _ =
+                                writeln!(report, "         {:04X}  + {}", to.0, text(to, is, new));
+                            counts.inserted += 1;
+                        }
+                        (None, None) => unreachable!(),
+                    }
+                }
+                deleted.clear();
+                inserted.clear();
+            }
+        }
+
+        if let Some(Step::Both(i, j)) = step {
+            let ((from, was), (to, is)) = (a.instructions[i], b.instructions[j]);
+            let (was_bytes, is_bytes) = (&was.encoding().bytes, &is.encoding().bytes);
+            match (was.to_jump(), is.to_jump()) {
+                // Branches share a key whatever their target, which must be the counterpart
+                // of the old one.
+                (Some(was_jump), Some(is_jump)) => {
+                    let target = is_jump.target(to);
+                    if pairing.moved.get(&was_jump.target(from)) != Some(&target) {
+                        let _ = writeln!(
+                            report,
+                            "    {:04X} {:04X}  ! {} => {}",
+                            from.0,
+                            to.0,
+                            text(from, was, old),
+                            text(to, is, new)
+                        );
+                        counts.changed += 1;
+                    }
+                }
+                _ if was_bytes != is_bytes => {
+                    let _ = writeln!(
+                        report,
+                        "    {:04X} {:04X}  ~ {} ; re-encoding only: {} => {}",
+                        from.0,
+                        to.0,
+                        text(to, is, new),
+                        hex(was_bytes),
+                        hex(is_bytes)
+                    );
+                    counts.re_encoded += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Lists every instruction of a block only one side has.
+fn write_block(block: &Block, side: &Side, marker: char, report: &mut String) {
+    let _ = writeln!(report, "  {marker} block {:04X}", block.start.0);
+    for (address, instruction) in &block.instructions {
+        let (from, to) = match marker {
+            '-' => (format!("{:04X}", address.0), "    ".to_string()),
+            _ => ("    ".to_string(), format!("{:04X}", address.0)),
+        };
+        let _ = writeln!(
+            report,
+            "    {from} {to}  {marker} {}",
+            text(*address, instruction, side)
+        );
+    }
+}
+
+/// Splits the traced code into functions and their basic blocks.
+fn functions<'a>(side: &Side<'a>) -> Vec<Function<'a>> {
+    let mut functions: Vec<Function> = side
+        .symbols
+        .functions()
+        .map(|(start, name)| Function {
+            start,
+            name: name.to_string(),
+            named: name != format!("sub_{:04X}", start.0),
+            blocks: Vec::new(),
+            keys: Vec::new(),
+        })
+        .collect();
+
+    for block in ControlFlowGraph::build(side.program, side.symbols).blocks {
+        let instructions = block
+            .instructions
+            .iter()
+            .filter_map(
+                |instruction| match side.program.items.get(&instruction.address) {
+                    Some(Item::Instruction(decoded)) => Some((instruction.address, decoded)),
+                    _ => None,
+                },
+            )
+            .collect::<Vec<_>>();
+        let block = Block {
+            start: block.start,
+            keys: instructions
+                .iter()
+                .map(|(_, instruction)| key(instruction))
+                .collect(),
+            instructions,
+        };
+
+        match functions
+            .iter_mut()
+            .rev()
+            .find(|function| function.start <= block.start)
+        {
+            Some(function) => function.blocks.push(block),
+            None => functions.insert(
+                0,
+                Function {
+                    start: block.start,
+                    name: format!("{:04X}", block.start.0),
+                    named: false,
+                    blocks: vec![block],
+                    keys: Vec::new(),
+                },
+            ),
+        }
+    }
+
+    for function in &mut functions {
+        function.keys = function
+            .blocks
+            .iter()
+            .flat_map(|block| block.keys.iter().cloned())
+            .collect();
+    }
+    functions
+}
+
+/// What an instruction does, leaving out how it is encoded and where branches go, which
+/// is checked against the paired blocks instead.
+fn key(instruction: &Instruction) -> String {
+    if let Some(jmp) = instruction.to_jump() {
+        return jmp.to_string();
+    }
+
+    let operands: Vec<String> = instruction
+        .operands()
+        .iter()
+        .map(|operand| operand.to_string())
+        .collect();
+    match operands.is_empty() {
+        true => instruction.to_string(),
+        false => format!("{} {}", instruction.mnemonic(), operands.join(", ")),
+    }
+}
+
+fn text(address: Address, instruction: &Instruction, side: &Side) -> String {
+    format_instruction(address, instruction, side.symbols, Palette::Plain)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    bytes.join(" ")
+}
+
+/// Share of the two sequences taken up by their longest common subsequence.
+fn similarity(a: &[String], b: &[String]) -> f64 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let common = align(a.len(), b.len(), |i, j| a[i] == b[j])
+        .iter()
+        .filter(|step| matches!(step, Step::Both(..)))
+        .count();
+    2.0 * common as f64 / (a.len() + b.len()) as f64
+}
+
+/// Aligns two sequences along their longest common subsequence under `eq`.
+fn align(old: usize, new: usize, eq: impl Fn(usize, usize) -> bool) -> Vec<Step> {
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..].
+    let mut lengths = vec![vec![0usize; new + 1]; old + 1];
+    for i in (0..old).rev() {
+        for j in (0..new).rev() {
+            lengths[i][j] = if eq(i, j) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut steps = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old || j < new {
+        if i < old && j < new && eq(i, j) && lengths[i][j] == lengths[i + 1][j + 1] + 1 {
+            steps.push(Step::Both(i, j));
+            i += 1;
+            j += 1;
+        } else if j == new || (i < old && lengths[i + 1][j] >= lengths[i][j + 1]) {
+            steps.push(Step::Old(i));
+            i += 1;
+        } else {
+            steps.push(Step::New(j));
+            j += 1;
+        }
+    }
+
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{diff, Side};
+    use crate::flow::{trace, Layout, Program};
+    use crate::functions::discover;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::symbols::Symbols;
+
+    fn traced(bin: &[u8]) -> (Program, Symbols) {
+        let program = trace(&Image::flat(bin), &[Address(0)], &Layout::default());
+        let symbols = Symbols::new(&program, &discover(&program));
+        (program, symbols)
+    }
+
+    fn diff_of(old: &[u8], new: &[u8]) -> String {
+        let (old_program, old_symbols) = traced(old);
+        let (new_program, new_symbols) = traced(new);
+        diff(
+            &Side {
+                program: &old_program,
+                symbols: &old_symbols,
+            },
+            &Side {
+                program: &new_program,
+                symbols: &new_symbols,
+            },
+        )
+    }
+
+    #[test]
+    fn test_diff() {
+        let (old_program, old_symbols) = traced(&[
+            0x81, 0xC3, 0x05, 0x00, // add bx, strict word 5
+            0xB8, 0x01, 0x00, // mov ax, 1
+            0x89, 0xD8, // mov ax, bx
+            0xC3, // ret
+        ]);
+        let (new_program, new_symbols) = traced(&[
+            0x83, 0xC3, 0x05, // add bx, word 5
+            0xB8, 0x02, 0x00, // mov ax, 2
+            0x31, 0xC9, // xor cx, cx
+            0x89, 0xD8, // mov ax, bx
+            0xC3, // ret
+        ]);
+
+        let report = diff(
+            &Side {
+                program: &old_program,
+                symbols: &old_symbols,
+            },
+            &Side {
+                program: &new_program,
+                symbols: &new_symbols,
+            },
+        );
+        assert_eq!(
+            report,
+            "sub_0000 (0000) sub_0000 (0000)\n\
+             \x20 block 0000 0000\n\
+             \x20   0000 0000  ~ add bx, word 5 ; re-encoding only: 81 c3 05 00 => 83 c3 05\n\
+             \x20   0004 0003  ! mov ax, word 1 => mov ax, word 2\n\
+             \x20        0006  + xor cx, cx\n\
+             1 changed, 1 re-encoded, 1 inserted, 0 deleted\n"
+        );
+    }
+
+    #[test]
+    fn test_diff_branch_targets() {
+        let old = [
+            0x74, 0x04, // je 0006
+            0xB8, 0x01, 0x00, // mov ax, 1
+            0xC3, // ret
+            0xB8, 0x02, 0x00, // mov ax, 2
+            0xC3, // ret
+        ];
+
+        // The target moved along with the code after it.
+        let moved = [
+            0x74, 0x06, // je 0008
+            0x31, 0xC9, // xor cx, cx
+            0xB8, 0x01, 0x00, // mov ax, 1
+            0xC3, // ret
+            0xB8, 0x02, 0x00, // mov ax, 2
+            0xC3, // ret
+        ];
+        assert_eq!(
+            diff_of(&old, &moved),
+            "sub_0000 (0000) sub_0000 (0000)\n\
+             \x20 block 0002 0002\n\
+             \x20        0002  + xor cx, cx\n\
+             0 changed, 0 re-encoded, 1 inserted, 0 deleted\n"
+        );
+
+        // Same layout, but the branch now goes to the instruction after it.
+        let retargeted = [0x74, 0x00, 0xB8, 0x01, 0x00, 0xC3, 0xB8, 0x02, 0x00, 0xC3];
+        assert_eq!(
+            diff_of(&old, &retargeted),
+            "sub_0000 (0000) sub_0000 (0000)\n\
+             \x20 block 0000 0000\n\
+             \x20   0000 0000  ! je .loc_0006 => je .loc_0002\n\
+             1 changed, 0 re-encoded, 0 inserted, 0 deleted\n"
+        );
+    }
+}
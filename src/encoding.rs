@@ -0,0 +1,245 @@
+use crate::memory::Displacement;
+
+/// What the `reg` field of a ModRM byte holds.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Reg {
+    Register,
+    /// Segment register, for `mov` to and from segment registers.
+    Segment,
+    /// Opcode extension selecting the operation, like `/0` for `add` in the `80` group.
+    Extension,
+}
+
+/// One row of the 8086 encoding table, which names the opcode and lays out its fields.
+#[derive(Debug)]
+pub(crate) struct Row {
+    /// Bits of the first byte, most significant first. Letters are fields: `d`, `w`, `s`
+    /// and `v` are single bits, `r` a register and `c` a condition.
+    pub(crate) bits: &'static str,
+    pub(crate) name: &'static str,
+}
+
+const fn row(bits: &'static str, name: &'static str) -> Row {
+    Row { bits, name }
+}
+
+const TABLE: &[Row] = &[
+    row("100010dw", "MOV register/memory to/from register"),
+    row("1100011w", "MOV immediate to register/memory"),
+    row("1011wrrr", "MOV immediate to register"),
+    row("1010000w", "MOV memory to accumulator"),
+    row("1010001w", "MOV accumulator to memory"),
+    row("10001110", "MOV register/memory to segment register"),
+    row("10001100", "MOV segment register to register/memory"),
+    row("000000dw", "ADD register/memory with register"),
+    row("0000010w", "ADD immediate to accumulator"),
+    row("001010dw", "SUB register/memory and register"),
+    row("0010110w", "SUB immediate from accumulator"),
+    row("001110dw", "CMP register/memory and register"),
+    row("0011110w", "CMP immediate with accumulator"),
+    row(
+        "100000sw",
+        "ADD/OR/ADC/SBB/SUB/XOR/CMP immediate to register/memory",
+    ),
+    row("000010dw", "OR register/memory and register"),
+    row("0000110w", "OR immediate to accumulator"),
+    row("000100dw", "ADC register/memory with register"),
+    row("0001010w", "ADC immediate to accumulator"),
+    row("000110dw", "SBB register/memory and register"),
+    row("0001110w", "SBB immediate from accumulator"),
+    row("001100dw", "XOR register/memory and register"),
+    row("0011010w", "XOR immediate to accumulator"),
+    row("10011001", "CWD convert word to double word"),
+    row("1111011w", "NOT/NEG/MUL/IMUL/DIV/IDIV register/memory"),
+    row("110100vw", "Shift/rotate register/memory by 1 or CL"),
+    row("1010010w", "MOVS move byte/word"),
+    row("0111cccc", "Jcc conditional jump"),
+    row("11100000", "LOOPNZ"),
+    row("11100001", "LOOPZ"),
+    row("11100010", "LOOP"),
+    row("11100011", "JCXZ"),
+    row("11101000", "CALL direct within segment"),
+    row("10011010", "CALL direct intersegment"),
+    row("11101001", "JMP direct within segment"),
+    row("11101011", "JMP direct within segment-short"),
+    row("11101010", "JMP direct intersegment"),
+    row("11000011", "RET within segment"),
+    row("11000010", "RET within segment adding immediate to SP"),
+    row("11001011", "RET intersegment"),
+    row("11001010", "RET intersegment adding immediate to SP"),
+    row("11001111", "IRET"),
+    row("11001101", "INT type specified"),
+    row("11111000", "CLC clear carry"),
+    row("11111001", "STC set carry"),
+    row("11111010", "CLI clear interrupt"),
+    row("11111011", "STI set interrupt"),
+    row("11111100", "CLD clear direction"),
+    row("11111101", "STD set direction"),
+    row("10010000", "NOP no operation"),
+    row("01010rrr", "PUSH register"),
+    row("01011rrr", "POP register"),
+    row("10001111", "POP register/memory"),
+    row("11111111", "CALL/JMP/PUSH register/memory"),
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ModRm {
+    pub mode: u8,
+    pub reg: u8,
+    pub rm: u8,
+    pub(crate) kind: Reg,
+}
+
+impl ModRm {
+    pub(crate) fn new(byte: u8, kind: Reg) -> ModRm {
+        ModRm {
+            mode: byte >> 6,
+            reg: (byte >> 3) & 0b111,
+            rm: byte & 0b111,
+            kind,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Immediate {
+    Byte(u8),
+    Word(u16),
+    /// Byte sign-extended to a word by the `s` bit.
+    SignExtended(u8),
+}
+
+/// Branch displacement, direct address or far pointer following the opcode.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Location {
+    IpIncrement8(u8),
+    IpIncrement16(u16),
+    Address(u16),
+    Far { offset: u16, segment: u16 },
+}
+
+/// How a decoded instruction was encoded: its bytes and the fields the decoder split them
+/// into.
+#[derive(Clone, Debug, Default)]
+pub struct Encoding {
+    /// Every byte of the instruction, prefixes included.
+    pub bytes: Vec<u8>,
+    /// Prefix bytes in front of the opcode. Only `rep` and `repne` are decoded yet.
+    pub prefixes: Vec<u8>,
+    pub opcode: u8,
+    pub d: Option<u8>,
+    pub w: Option<u8>,
+    pub s: Option<u8>,
+    /// Register encoded in the low bits of the opcode, like in `B8+r`.
+    pub register: Option<u8>,
+    /// Condition encoded in the low bits of a conditional jump.
+    pub condition: Option<u8>,
+    pub mod_rm: Option<ModRm>,
+    /// Displacement of a memory operand, `Disp8(0)` being distinct from no displacement.
+    pub displacement: Displacement,
+    pub immediate: Option<Immediate>,
+    pub location: Option<Location>,
+    row: Option<&'static Row>,
+}
+
+impl Encoding {
+    /// Completes the fields the decoder filled in with the opcode, the first byte after
+    /// the prefixes, and the table row it matches.
+    pub(crate) fn finish(mut self) -> Encoding {
+        self.opcode = self.bytes.get(self.prefixes.len()).copied().unwrap_or(0);
+        self.row = TABLE.iter().find(|row| matches(row, self.opcode));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// Name of the encoding table row the opcode matched.
+    pub fn table_row(&self) -> Option<&'static str> {
+        self.row.map(|row| row.name)
+    }
+
+    pub(crate) fn row(&self) -> Option<&'static Row> {
+        self.row
+    }
+}
+
+fn matches(row: &Row, byte: u8) -> bool {
+    row.bits.chars().enumerate().all(|(index, bit)| {
+        let value = (byte >> (7 - index)) & 1;
+        match bit {
+            '0' => value == 0,
+            '1' => value == 1,
+            _ => true,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_at;
+    use crate::encoding::{Encoding, Immediate, Location, ModRm, Reg};
+    use crate::memory::{Address, Displacement};
+
+    fn encoding(bytes: &[u8]) -> Encoding {
+        decode_at(bytes, Address(0))
+            .expect("decodes")
+            .encoding()
+            .clone()
+    }
+
+    #[test]
+    fn test_fields() {
+        // mov ax, [bp - 4]
+        let mov = encoding(&[0x8B, 0x46, 0xFC]);
+        assert_eq!(mov.bytes, vec![0x8B, 0x46, 0xFC]);
+        assert_eq!(
+            (mov.opcode, mov.d, mov.w, mov.s),
+            (0x8B, Some(1), Some(1), None)
+        );
+        assert_eq!(mov.mod_rm, Some(ModRm::new(0x46, Reg::Register)));
+        assert_eq!(mov.displacement, Displacement::Disp8(0xFC));
+        assert_eq!(mov.immediate, None);
+        assert_eq!(
+            mov.table_row(),
+            Some("MOV register/memory to/from register")
+        );
+
+        // add bx, -2
+        let add = encoding(&[0x83, 0xC3, 0xFE]);
+        assert_eq!((add.s, add.w), (Some(1), Some(1)));
+        assert_eq!(add.mod_rm.map(|mod_rm| mod_rm.kind), Some(Reg::Extension));
+        assert_eq!(add.displacement, Displacement::None);
+        assert_eq!(add.immediate, Some(Immediate::SignExtended(0xFE)));
+
+        // mov cx, 3
+        let mov = encoding(&[0xB9, 0x03, 0x00]);
+        assert_eq!((mov.register, mov.w), (Some(1), Some(1)));
+        assert_eq!(mov.immediate, Some(Immediate::Word(3)));
+
+        // rep movsw
+        let movs = encoding(&[0xF3, 0xA5]);
+        assert_eq!((movs.prefixes.as_slice(), movs.opcode), (&[0xF3][..], 0xA5));
+        assert_eq!(movs.table_row(), Some("MOVS move byte/word"));
+
+        // jne +2, loop -2 and call far f000:1234
+        let jne = encoding(&[0x75, 0x02]);
+        assert_eq!(jne.condition, Some(5));
+        assert_eq!(jne.location, Some(Location::IpIncrement8(2)));
+        assert_eq!(encoding(&[0xE2, 0xFE]).condition, None);
+        let call = encoding(&[0x9A, 0x34, 0x12, 0x00, 0xF0]);
+        assert_eq!(
+            call.location,
+            Some(Location::Far {
+                offset: 0x1234,
+                segment: 0xF000
+            })
+        );
+
+        // mov al, [0x1234]
+        let mov = encoding(&[0xA0, 0x34, 0x12]);
+        assert_eq!(mov.location, Some(Location::Address(0x1234)));
+        assert_eq!(mov.w, Some(0));
+    }
+}
@@ -3,8 +3,52 @@ use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("Unknown instruction ({0:#b}) at {:#x}", .0)]
+    #[error("Unknown instruction ({0:#b}) at {:#x}", .1 .0)]
     UnknownInstruction(u8, Address),
     #[error("Unexpected end of instruction stream")]
     EndOfInstructionStream(),
+    #[error("Invalid symbol on line {0}: {1}")]
+    InvalidSymbolLine(usize, String),
+    #[error("Invalid MZ executable: {0}")]
+    InvalidExecutable(&'static str),
+    #[error("Image of {0:#x} bytes does not fit in a 64 KiB segment")]
+    ImageTooLarge(usize),
+    #[error("Invalid record on line {0}: {1}")]
+    InvalidRecord(usize, &'static str),
+    #[error("Checksum of the record on line {0} is {1:#04x}, expected {2:#04x}")]
+    RecordChecksum(usize, u8, u8),
+    #[error("Invalid OMF record at {0:#x}: {1}")]
+    InvalidObject(usize, &'static str),
+    #[error("Invalid library: {0}")]
+    InvalidLibrary(&'static str),
+    #[error("No module or public named {0} in the library")]
+    UnknownModule(String),
+    #[error("{0} is not supported for object modules")]
+    UnsupportedForObject(&'static str),
+    #[error("Invalid boot sector: {0}")]
+    InvalidBootSector(&'static str),
+    #[error("Invalid ROM: {0}")]
+    InvalidRom(&'static str),
+    #[error("Invalid memory map: {0}")]
+    InvalidMemoryMap(String),
+    #[error("Cannot export {0}")]
+    CannotExport(&'static str),
+    #[error("Rebuilt binary differs from the original at offset {0:#x}")]
+    ExportMismatch(usize),
+    #[error("Cannot verify the export: nasm not found")]
+    AssemblerNotFound,
+    #[error("nasm failed: {0}")]
+    AssemblerFailed(String),
+    #[error("Command tail of {0} bytes does not fit in the PSP")]
+    CommandTailTooLong(usize),
+    #[error("Load segment {0:04x} leaves no room for the PSP below it")]
+    LoadSegmentTooLow(u16),
+    #[error(
+        "Load segment {0:04x} has only {1:#x} bytes of memory, not the 64 KiB a COM program needs"
+    )]
+    LoadSegmentTooHigh(u16, usize),
+    #[error("Simulating {0} is not supported")]
+    Unsupported(String),
+    #[error("Divide error in {0}")]
+    DivideOverflow(String),
 }
@@ -0,0 +1,164 @@
+use crate::encoding::{Immediate, Location, Reg, Row};
+use crate::instruction::Instruction;
+use crate::memory::{Address, Displacement};
+use crate::register::{Register, SegmentRegister};
+
+const EFFECTIVE_ADDRESSES: [&str; 8] = ["bx+si", "bx+di", "bp+si", "bp+di", "si", "di", "bp", "bx"];
+
+/// Lists each instruction with its bytes split into the fields of the encoding table row
+/// it matched, like `100010 d=1 w=1 | mod=01 reg=000(ax) rm=110(bp+disp8) | disp8=0xFC`.
+pub fn render<'a>(instructions: impl Iterator<Item = (Address, &'a Instruction)>) -> String {
+    let mut output = String::new();
+
+    for (address, instruction) in instructions {
+        let encoding = instruction.encoding();
+        let hex: Vec<String> = encoding
+            .bytes
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        output.push_str(&format!(
+            "{:04x}  {:<18} {instruction}\n",
+            address.0,
+            hex.join(" ")
+        ));
+        match (explain(instruction), encoding.table_row()) {
+            (Some(fields), Some(row)) => output.push_str(&format!("      {fields}  [{row}]\n")),
+            _ => output.push_str("      no matching table row\n"),
+        }
+    }
+
+    output
+}
+
+/// Splits the bytes of one instruction into the fields of the row it matched.
+pub fn explain(instruction: &Instruction) -> Option<String> {
+    let encoding = instruction.encoding();
+    let row = encoding.row()?;
+    // Register pushes and pops are always words.
+    let w = encoding.w.unwrap_or(1);
+
+    let mut groups: Vec<String> = encoding
+        .prefixes
+        .iter()
+        .map(|prefix| format!("prefix={prefix:02X}"))
+        .collect();
+    groups.push(describe_opcode(row, encoding.opcode, w));
+
+    if let Some(mod_rm) = encoding.mod_rm {
+        let reg_text = match mod_rm.kind {
+            Reg::Register => Register::decode_reg(mod_rm.reg, w).to_string(),
+            Reg::Segment => SegmentRegister::decode_sreg(mod_rm.reg).to_string(),
+            Reg::Extension => format!("/{} {}", mod_rm.reg, instruction.mnemonic()),
+        };
+        let base = EFFECTIVE_ADDRESSES[mod_rm.rm as usize];
+        let rm_text = match (mod_rm.mode, mod_rm.rm) {
+            (0b11, rm) => Register::decode_reg(rm, w).to_string(),
+            (0b00, 0b110) => "direct address".to_string(),
+            (0b00, _) => base.to_string(),
+            (0b01, _) => format!("{base}+disp8"),
+            _ => format!("{base}+disp16"),
+        };
+        groups.push(format!(
+            "mod={:02b} reg={:03b}({reg_text}) rm={:03b}({rm_text})",
+            mod_rm.mode, mod_rm.reg, mod_rm.rm
+        ));
+    }
+
+    match encoding.displacement {
+        Displacement::Disp8(disp) => groups.push(format!("disp8=0x{disp:02X}")),
+        Displacement::Disp16(disp) => groups.push(format!("disp16=0x{disp:04X}")),
+        Displacement::None => {}
+    }
+
+    match encoding.location {
+        Some(Location::IpIncrement8(increment)) => {
+            groups.push(format!("ip-inc8=0x{increment:02X}"))
+        }
+        Some(Location::IpIncrement16(increment)) => {
+            groups.push(format!("ip-inc16=0x{increment:04X}"))
+        }
+        Some(Location::Address(address)) => groups.push(format!("addr=0x{address:04X}")),
+        Some(Location::Far { offset, segment }) => {
+            groups.push(format!("offset=0x{offset:04X} segment=0x{segment:04X}"))
+        }
+        None => {}
+    }
+
+    match encoding.immediate {
+        Some(Immediate::Byte(data)) => groups.push(format!("data8=0x{data:02X}")),
+        Some(Immediate::Word(data)) => groups.push(format!("data16=0x{data:04X}")),
+        Some(Immediate::SignExtended(data)) => {
+            groups.push(format!("data8=0x{data:02X} (sign-extended)"))
+        }
+        None => {}
+    }
+
+    Some(groups.join(" | "))
+}
+
+fn describe_opcode(row: &Row, opcode: u8, w: u8) -> String {
+    let mut parts = Vec::new();
+    let mut fixed = String::new();
+    let bits: Vec<char> = row.bits.chars().collect();
+
+    let mut index = 0;
+    while index < bits.len() {
+        let letter = bits[index];
+        if letter == '0' || letter == '1' {
+            fixed.push(letter);
+            index += 1;
+            continue;
+        }
+        if !fixed.is_empty() {
+            parts.push(std::mem::take(&mut fixed));
+        }
+
+        let width = bits[index..]
+            .iter()
+            .take_while(|&&bit| bit == letter)
+            .count();
+        let value = (opcode >> (8 - index - width)) & ((1 << width) - 1);
+        let text = format!("{value:0width$b}");
+        parts.push(match letter {
+            'r' => format!("reg={text}({})", Register::decode_reg(value, w)),
+            'c' => format!("cond={text}"),
+            _ => format!("{letter}={text}"),
+        });
+        index += width;
+    }
+    if !fixed.is_empty() {
+        parts.push(fixed);
+    }
+
+    parts.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_at;
+    use crate::explain::explain;
+    use crate::memory::Address;
+
+    #[test]
+    fn test_explain() -> anyhow::Result<()> {
+        let instruction = decode_at(&[0x8B, 0x46, 0xFC], Address(0))?;
+        assert_eq!(
+            explain(&instruction).as_deref(),
+            Some("100010 d=1 w=1 | mod=01 reg=000(ax) rm=110(bp+disp8) | disp8=0xFC")
+        );
+        assert_eq!(
+            instruction.encoding().table_row(),
+            Some("MOV register/memory to/from register")
+        );
+
+        let instruction = decode_at(&[0x83, 0xC3, 0xFF], Address(0))?;
+        assert_eq!(
+            explain(&instruction).as_deref(),
+            Some("100000 s=1 w=1 | mod=11 reg=000(/0 add) rm=011(bx) | data8=0xFF (sign-extended)")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,166 @@
+use crate::error::Error;
+use crate::flow::Program;
+use crate::format::Palette;
+use crate::image::Image;
+use crate::listing::{render_data, render_items};
+use crate::symbols::Symbols;
+use crate::xref::Xrefs;
+use std::fmt::Write;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::process::Command;
+
+/// Data items at least this long are written to their own file and pulled in with `incbin`.
+const INCBIN_THRESHOLD: usize = 256;
+
+/// Writes a NASM source tree for the traced image to `directory`: `NAME.asm` with the `org`,
+/// labels, instructions and data, `NAME.inc` with the `equ`s of symbols that do not start an
+/// item and a `NAME_XXXX.bin` for every large data item.
+///
+/// The export is then verified by assembling `NAME.asm` with `nasm` and comparing the result
+/// with the image. Without `nasm` the export is left unverified, which is an error if
+/// `verify` is set. Returns a report of the files written and whether they were verified,
+/// as assembly comments.
+pub fn export(
+    directory: &Path,
+    name: &str,
+    image: &Image,
+    program: &Program,
+    symbols: &Symbols,
+    xrefs: &Xrefs,
+    verify: bool,
+) -> anyhow::Result<String> {
+    let mut regions = image.regions();
+    let (origin, expected) = match (regions.next(), regions.next()) {
+        (Some(region), None) => region,
+        _ => return Err(Error::CannotExport("an image with gaps between its regions").into()),
+    };
+
+    fs::create_dir_all(directory)?;
+    let include = format!("{name}.inc");
+    let mut equates = String::new();
+    for (symbol, address) in symbols.equates() {
+        let _ = writeln!(equates, "{symbol} equ 0x{:04x}", address.0);
+    }
+    fs::write(directory.join(&include), equates)?;
+
+    let mut blobs = Vec::new();
+    let body = render_items(program, symbols, xrefs, Palette::Plain, |address, data| {
+        if data.len() < INCBIN_THRESHOLD {
+            return render_data(address, data, symbols.data_width(address));
+        }
+        let file = format!("{name}_{:04X}.bin", address.0);
+        let directive = format!("incbin \"{file}\"\n");
+        blobs.push((file, data.to_vec()));
+        directive
+    });
+    for (file, data) in &blobs {
+        fs::write(directory.join(file), data)?;
+    }
+
+    let source = format!("{name}.asm");
+    let mut text = String::new();
+    let _ = writeln!(text, "; nasm -f bin -o {name}.bin {source}");
+    let _ = writeln!(text, "bits 16");
+    let _ = writeln!(text, "org 0x{origin:04x}");
+    let _ = writeln!(text, "%include \"{include}\"");
+    text.push_str(&body);
+    fs::write(directory.join(&source), text)?;
+
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "; wrote {source}, {include} and {} incbin files to {}",
+        blobs.len(),
+        directory.display()
+    );
+    match assemble(directory, &source, name)? {
+        Some(rebuilt) => {
+            compare(&rebuilt, expected)?;
+            let _ = writeln!(report, "; nasm rebuilt all {} bytes", expected.len());
+        }
+        None if verify => return Err(Error::AssemblerNotFound.into()),
+        None => {
+            let _ = writeln!(report, "; not verified (nasm not found)");
+        }
+    }
+    Ok(report)
+}
+
+/// Assembles `source` with `nasm`, or returns `None` when it is not installed.
+fn assemble(directory: &Path, source: &str, name: &str) -> anyhow::Result<Option<Vec<u8>>> {
+    let output = format!("{name}.bin");
+    let result = Command::new("nasm")
+        .args(["-f", "bin", "-o", &output, source])
+        .current_dir(directory)
+        .output();
+    let result = match result {
+        Ok(result) => result,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(error) => return Err(error.into()),
+    };
+    if !result.status.success() {
+        let message = String::from_utf8_lossy(&result.stderr).trim().to_string();
+        return Err(Error::AssemblerFailed(message).into());
+    }
+    Ok(Some(fs::read(directory.join(output))?))
+}
+
+fn compare(rebuilt: &[u8], expected: &[u8]) -> Result<(), Error> {
+    match rebuilt.iter().zip(expected).position(|(a, b)| a != b) {
+        Some(offset) => Err(Error::ExportMismatch(offset)),
+        None if rebuilt.len() != expected.len() => {
+            Err(Error::ExportMismatch(rebuilt.len().min(expected.len())))
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::export;
+    use crate::flow::{self, Layout};
+    use crate::functions;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::symbols::Symbols;
+    use crate::xref::Xrefs;
+    use std::fs;
+
+    #[test]
+    fn test_export() -> anyhow::Result<()> {
+        // mov bx, [0x0106]; jmp $; then a word and a 300 byte table
+        let mut bytes = vec![0x8B, 0x1E, 0x06, 0x01, 0xEB, 0xFE, 0x34, 0x12];
+        bytes.extend((0..300).map(|i| i as u8));
+        let mut image = Image::default();
+        image.insert(0x100, &bytes);
+
+        let layout = Layout {
+            code: Vec::new(),
+            data: vec![Address(0x108)..Address(0x108 + 300)],
+            linked: Vec::new(),
+        };
+        let program = flow::trace(&image, &[Address(0x100)], &layout);
+        let symbols = Symbols::new(&program, &functions::discover(&program));
+        let xrefs = Xrefs::collect(&program, &symbols);
+
+        let directory = std::env::temp_dir().join(format!("export-{}", std::process::id()));
+        let report = export(
+            &directory, "test", &image, &program, &symbols, &xrefs, false,
+        )?;
+        assert!(report.contains("1 incbin files"));
+        if report.contains("; not verified (nasm not found)") {
+            assert!(export(&directory, "test", &image, &program, &symbols, &xrefs, true).is_err());
+        }
+
+        let source = fs::read_to_string(directory.join("test.asm"))?;
+        assert!(source.contains("org 0x0100\n"));
+        assert!(source.contains("mov bx, [word_0106]\n"));
+        assert!(source.contains("incbin \"test_0108.bin\"\n"));
+        assert_eq!(fs::read(directory.join("test_0108.bin"))?, bytes[8..]);
+
+        fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}
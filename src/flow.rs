@@ -0,0 +1,278 @@
+use crate::decode::decode_at;
+use crate::image::Image;
+use crate::instruction::Instruction;
+use crate::memory::Address;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+/// A gap is data when more than one in this many of its instructions are zero fill (`00 00`).
+const ZERO_FILL_THRESHOLD: usize = 4;
+
+pub enum Item {
+    Instruction(Instruction),
+    Data(Vec<u8>),
+}
+
+/// Ranges forced to be code or data regardless of what tracing finds.
+#[derive(Default)]
+pub struct Layout {
+    pub code: Vec<Range<Address>>,
+    pub data: Vec<Range<Address>>,
+    /// Fields the linker fills in. Branches through them lead outside the image, as a
+    /// `call` to an external does, and are not followed.
+    pub linked: Vec<Range<Address>>,
+}
+
+impl Layout {
+    fn is_code(&self, address: Address) -> bool {
+        self.code.iter().any(|range| range.contains(&address))
+    }
+}
+
+/// A binary split into instructions and data, keyed by the address of each item.
+pub struct Program {
+    pub entry_points: Vec<Address>,
+    pub items: BTreeMap<Address, Item>,
+    /// Fields the linker fills in, from [`Layout::linked`].
+    pub linked: Vec<Range<Address>>,
+}
+
+impl Program {
+    /// Where the instruction at `address` branches to, unless the linker fills in the target.
+    pub fn branch_target(&self, address: Address, instruction: &Instruction) -> Option<Address> {
+        branch_target(&self.linked, address, instruction)
+    }
+
+    /// Branch targets that start an item and can therefore carry a label.
+    pub fn branch_targets(&self) -> BTreeSet<Address> {
+        self.instructions()
+            .filter_map(|(address, instruction)| self.branch_target(address, instruction))
+            .filter(|target| self.items.contains_key(target))
+            .collect()
+    }
+
+    pub fn is_instruction(&self, address: Address) -> bool {
+        matches!(self.items.get(&address), Some(Item::Instruction(_)))
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = (Address, &Instruction)> {
+        self.items.iter().filter_map(|(address, item)| match item {
+            Item::Instruction(instruction) => Some((*address, instruction)),
+            Item::Data(_) => None,
+        })
+    }
+}
+
+/// Follows control flow from `entry_points`, marking every byte reached as code.
+///
+/// The image must lie in the first 64 KiB; addresses it does not load are neither code nor
+/// data. Bytes that are never reached are decoded linearly if they look like code and
+/// are kept as data otherwise. Data is split wherever an instruction branches to or
+/// directly addresses it. Code ranges in `layout` are decoded in full and its data ranges
+/// are never decoded.
+pub fn trace(image: &Image, entry_points: &[Address], layout: &Layout) -> Program {
+    let mut instructions = BTreeMap::new();
+
+    // Unloaded bytes count as covered so that nothing is decoded from or kept as them.
+    let end = image.end() as usize;
+    let mut bytes = vec![0; end];
+    let mut covered = vec![true; end];
+    for (start, data) in image.regions() {
+        let start = start as usize;
+        bytes[start..start + data.len()].copy_from_slice(data);
+        covered[start..start + data.len()].fill(false);
+    }
+    let bytes = bytes.as_slice();
+    let mut pending = entry_points.to_vec();
+    pending.extend(layout.code.iter().map(|range| range.start));
+
+    let mut data_gaps = Vec::new();
+    for range in &layout.data {
+        for (region, data) in image.regions() {
+            let start = (range.start.0 as usize).max(region as usize);
+            let end = (range.end.0 as usize).min(region as usize + data.len());
+            if start < end {
+                covered[start..end].fill(true);
+                data_gaps.push((start, end));
+            }
+        }
+    }
+
+    while let Some(mut address) = pending.pop() {
+        while let Some(instruction) = decode_unclaimed(bytes, &covered, address) {
+            let size = instruction.get_size() as usize;
+            covered[address.0 as usize..address.0 as usize + size].fill(true);
+
+            if let Some(target) = branch_target(&layout.linked, address, &instruction) {
+                pending.push(target);
+            }
+
+            let falls_through = instruction.falls_through();
+            instructions.insert(address, instruction);
+
+            address = Address(address.0.wrapping_add(size as u16));
+            if !falls_through && !layout.is_code(address) {
+                break;
+            }
+        }
+    }
+
+    for (start, end) in gaps(&covered) {
+        match decode_gap(bytes, start, end, &instructions) {
+            Some(decoded) => instructions.extend(decoded),
+            None => data_gaps.push((start, end)),
+        }
+    }
+
+    let targets: BTreeSet<Address> = instructions
+        .iter()
+        .flat_map(|(address, instruction)| {
+            let mut targets = instruction.direct_addresses();
+            targets.extend(branch_target(&layout.linked, *address, instruction));
+            targets
+        })
+        .collect();
+
+    let mut items: BTreeMap<Address, Item> = instructions
+        .into_iter()
+        .map(|(address, instruction)| (address, Item::Instruction(instruction)))
+        .collect();
+
+    for (start, end) in data_gaps {
+        let mut run_start = start;
+        for offset in start + 1..end {
+            if targets.contains(&Address(offset as u16)) {
+                items.insert(
+                    Address(run_start as u16),
+                    Item::Data(bytes[run_start..offset].to_vec()),
+                );
+                run_start = offset;
+            }
+        }
+        items.insert(
+            Address(run_start as u16),
+            Item::Data(bytes[run_start..end].to_vec()),
+        );
+    }
+
+    Program {
+        entry_points: entry_points.to_vec(),
+        items,
+        linked: layout.linked.clone(),
+    }
+}
+
+/// Target of a branch whose operand, after the opcode, is not in one of the `linked` fields.
+fn branch_target(
+    linked: &[Range<Address>],
+    address: Address,
+    instruction: &Instruction,
+) -> Option<Address> {
+    let field = address.0 as u32 + 1..address.0 as u32 + instruction.get_size() as u32;
+    let is_linked = linked
+        .iter()
+        .any(|range| (range.start.0 as u32) < field.end && field.start < range.end.0 as u32);
+    if is_linked {
+        return None;
+    }
+    instruction.branch_target(address)
+}
+
+fn decode_unclaimed(bytes: &[u8], covered: &[bool], address: Address) -> Option<Instruction> {
+    let start = address.0 as usize;
+    if start >= bytes.len() || covered[start] {
+        return None;
+    }
+
+    let instruction = decode_at(bytes, address).ok()?;
+    let end = start + instruction.get_size() as usize;
+    if covered[start..end].iter().any(|&c| c) {
+        return None;
+    }
+
+    Some(instruction)
+}
+
+fn gaps(covered: &[bool]) -> Vec<(usize, usize)> {
+    let mut gaps = Vec::new();
+    let mut start = None;
+
+    for (index, &c) in covered.iter().enumerate() {
+        match (c, start) {
+            (false, None) => start = Some(index),
+            (true, Some(s)) => {
+                gaps.push((s, index));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        gaps.push((s, covered.len()));
+    }
+
+    gaps
+}
+
+/// Decodes an unreached region linearly and keeps the result only if it looks like code:
+/// the instructions must tile the region exactly, it must not be padding or zero fill,
+/// and every branch must land on the start of an instruction.
+fn decode_gap(
+    bytes: &[u8],
+    start: usize,
+    end: usize,
+    known: &BTreeMap<Address, Instruction>,
+) -> Option<Vec<(Address, Instruction)>> {
+    let region = &bytes[start..end];
+    if region.len() >= 4 && region.iter().all(|&b| b == region[0]) {
+        return None;
+    }
+
+    let mut decoded = Vec::new();
+    let mut offset = start;
+    let mut zero_fill = 0;
+
+    while offset < end {
+        let instruction = decode_at(&bytes[..end], Address(offset as u16)).ok()?;
+        if bytes[offset] == 0 && bytes.get(offset + 1) == Some(&0) {
+            zero_fill += 1;
+        }
+        let size = instruction.get_size() as usize;
+        decoded.push((Address(offset as u16), instruction));
+        offset += size;
+    }
+
+    if zero_fill * ZERO_FILL_THRESHOLD > decoded.len() {
+        return None;
+    }
+
+    let starts: BTreeSet<Address> = decoded.iter().map(|(address, _)| *address).collect();
+    let branches_land =
+        decoded.iter().all(
+            |(address, instruction)| match instruction.branch_target(*address) {
+                Some(target) => starts.contains(&target) || known.contains_key(&target),
+                None => true,
+            },
+        );
+
+    branches_land.then_some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Item, Layout};
+    use crate::image::Image;
+    use crate::memory::Address;
+
+    #[test]
+    fn test_trace_separates_data() {
+        // mov ax, 1; jne back to the mov; followed by bytes that do not decode
+        let bytes = [0xB8, 0x01, 0x00, 0x75, 0xFB, 0x0F, b'h', b'i'];
+        let program = trace(&Image::flat(&bytes), &[Address(0)], &Layout::default());
+
+        let addresses: Vec<u16> = program.items.keys().map(|address| address.0).collect();
+        assert_eq!(addresses, vec![0, 3, 5]);
+        assert!(matches!(program.items[&Address(5)], Item::Data(ref data) if data.len() == 3));
+        assert!(program.branch_targets().contains(&Address(0)));
+    }
+}
@@ -0,0 +1,179 @@
+use crate::instruction::{Instruction, Spelling};
+use crate::memory::Address;
+
+/// Hooks that let the listing print operands symbolically.
+pub trait FormatContext {
+    /// Name to print instead of a fixed memory address.
+    fn memory_name(&self, _address: Address) -> Option<String> {
+        None
+    }
+
+    /// Text to print instead of an immediate's decimal value.
+    fn immediate(&self, _value: u16) -> Option<String> {
+        None
+    }
+
+    /// Name to print instead of the 16-bit displacement added to base or index registers.
+    fn displacement(&self, _value: u16) -> Option<String> {
+        None
+    }
+
+    /// Name to print instead of the `segment:offset` of a far `call` or `jmp`.
+    fn far_target(&self, _segment: u16, _offset: u16) -> Option<String> {
+        None
+    }
+
+    fn palette(&self) -> Palette {
+        Palette::Plain
+    }
+}
+
+/// Prints every operand numerically.
+pub struct Plain;
+
+impl FormatContext for Plain {}
+
+/// Kinds of text that are coloured differently.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Style {
+    Mnemonic,
+    /// Mnemonic of a jump, call or return.
+    Branch,
+    Register,
+    Immediate,
+    Memory,
+    Label,
+    Comment,
+}
+
+/// Whether styled text carries ANSI colours. Only bold, dim and the basic colours are used,
+/// which terminals adapt to their background, so the output reads on dark and light themes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Palette {
+    Plain,
+    Ansi,
+}
+
+impl Palette {
+    pub fn paint(self, style: Style, text: &str) -> String {
+        if self == Palette::Plain {
+            return text.to_string();
+        }
+
+        let code = match style {
+            Style::Mnemonic => "1",
+            Style::Branch => "1;31",
+            Style::Register => "34",
+            Style::Immediate => "32",
+            Style::Memory => "35",
+            Style::Label => "1;4",
+            Style::Comment => "2",
+        };
+        format!("\x1b[{code}m{text}\x1b[0m")
+    }
+}
+
+/// Applies `palette` on top of another context.
+pub struct Styled<'a> {
+    context: &'a dyn FormatContext,
+    palette: Palette,
+}
+
+impl<'a> Styled<'a> {
+    pub fn new(context: &'a dyn FormatContext, palette: Palette) -> Styled<'a> {
+        Styled { context, palette }
+    }
+}
+
+impl FormatContext for Styled<'_> {
+    fn memory_name(&self, address: Address) -> Option<String> {
+        self.context.memory_name(address)
+    }
+
+    fn immediate(&self, value: u16) -> Option<String> {
+        self.context.immediate(value)
+    }
+
+    fn displacement(&self, value: u16) -> Option<String> {
+        self.context.displacement(value)
+    }
+
+    fn far_target(&self, segment: u16, offset: u16) -> Option<String> {
+        self.context.far_target(segment, offset)
+    }
+
+    fn palette(&self) -> Palette {
+        self.palette
+    }
+}
+
+/// Returns `text` unless NASM would assemble it to something other than the bytes
+/// `instruction` was decoded from. The bytes are then emitted with `db`, keeping the
+/// instruction as a comment.
+pub fn exact(instruction: &Instruction, text: String, palette: Palette) -> String {
+    if instruction.nasm_spelling() != Spelling::Bytes {
+        return text;
+    }
+
+    let bytes: Vec<String> = instruction
+        .encoding()
+        .bytes
+        .iter()
+        .map(|byte| format!("0x{byte:02x}"))
+        .collect();
+    format!(
+        "{} {} {} {text}",
+        palette.paint(Style::Mnemonic, "db"),
+        bytes.join(", "),
+        palette.paint(Style::Comment, ";"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_at;
+    use crate::format::{exact, Palette, Plain, Styled};
+    use crate::memory::Address;
+
+    /// Drops the `ESC [ ... m` sequences from `text`.
+    fn strip(text: &str) -> String {
+        let mut stripped = String::new();
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                stripped.push(c);
+            }
+        }
+        stripped
+    }
+
+    #[test]
+    fn test_palettes() -> anyhow::Result<()> {
+        // mov ax, [bx + 4]
+        let mov = decode_at(&[0x8B, 0x47, 0x04], Address(0))?;
+        let plain = mov.format(&Styled::new(&Plain, Palette::Plain));
+        let ansi = mov.format(&Styled::new(&Plain, Palette::Ansi));
+        assert_eq!(plain, "mov ax, [bx + 4]");
+        assert_eq!(
+            ansi,
+            "\x1b[1mmov\x1b[0m \x1b[34max\x1b[0m, \x1b[35m[bx + 4]\x1b[0m"
+        );
+        assert_eq!(strip(&ansi), plain);
+
+        // mov ax, bx with the d bit set, which NASM would encode the other way
+        let mov = decode_at(&[0x8B, 0xC3], Address(0))?;
+        let text = mov.format(&Plain);
+        assert_eq!(
+            exact(&mov, text.clone(), Palette::Plain),
+            "db 0x8b, 0xc3 ; mov ax, bx"
+        );
+        assert_eq!(
+            exact(&mov, text, Palette::Ansi),
+            "\x1b[1mdb\x1b[0m 0x8b, 0xc3 \x1b[2m;\x1b[0m mov ax, bx"
+        );
+
+        Ok(())
+    }
+}
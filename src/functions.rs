@@ -0,0 +1,122 @@
+use crate::flow::Program;
+use crate::instruction::Instruction;
+use crate::memory::Address;
+use crate::operand::Operand;
+use crate::register::Register;
+use std::collections::BTreeSet;
+
+pub struct Function {
+    pub start: Address,
+    /// Address just past the last `ret`, `retf`, `iret` or `jmp` before the next function starts.
+    pub end: Option<Address>,
+}
+
+/// Finds function entry points from the program entry points, direct call targets and
+/// `push bp; mov bp, sp` prologues. Each function extends up to the next one.
+pub fn discover(program: &Program) -> Vec<Function> {
+    let mut starts: BTreeSet<Address> = program
+        .entry_points
+        .iter()
+        .copied()
+        .filter(|address| program.is_instruction(*address))
+        .collect();
+
+    let mut instructions = program.instructions().peekable();
+    while let Some((address, instruction)) = instructions.next() {
+        if let (Some(jmp), Some(target)) = (
+            instruction.to_jump(),
+            program.branch_target(address, instruction),
+        ) {
+            if jmp.is_call() && program.is_instruction(target) {
+                starts.insert(target);
+            }
+        }
+
+        let next_address = Address(address.0.wrapping_add(instruction.get_size() as u16));
+        if let Some((following_address, following)) = instructions.peek() {
+            if *following_address == next_address && is_prologue(instruction, following) {
+                starts.insert(address);
+            }
+        }
+    }
+
+    let starts: Vec<Address> = starts.into_iter().collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(index, &start)| {
+            let limit = starts.get(index + 1).copied();
+            let end = program
+                .instructions()
+                .skip_while(|(address, _)| *address < start)
+                .take_while(|(address, _)| limit.is_none_or(|limit| *address < limit))
+                .filter(|(_, instruction)| !instruction.falls_through())
+                .last()
+                .map(|(address, instruction)| {
+                    Address(address.0.wrapping_add(instruction.get_size() as u16))
+                });
+
+            Function { start, end }
+        })
+        .collect()
+}
+
+fn is_prologue(first: &Instruction, second: &Instruction) -> bool {
+    matches!(
+        first,
+        Instruction::Push {
+            src: Operand::Register(Register::Bp),
+            ..
+        }
+    ) && matches!(
+        second,
+        Instruction::Mov {
+            dst: Operand::Register(Register::Bp),
+            src: Operand::Register(Register::Sp),
+            ..
+        }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Layout};
+    use crate::functions::discover;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn test_discover() {
+        let bin = [
+            0xE8, 0x02, 0x00, // call 0005
+            0xEB, 0xFE, // jmp $
+            0xB8, 0x01, 0x00, // mov ax, 1
+            0xC3, // ret
+            0x55, // push bp, never called
+            0x8B, 0xEC, // mov bp, sp
+            0x5D, // pop bp
+            0xC3, // ret
+        ];
+        let program = trace(&Image::flat(&bin), &[Address(0)], &Layout::default());
+        let functions = discover(&program);
+
+        let bounds: Vec<(u16, Option<u16>)> = functions
+            .iter()
+            .map(|function| (function.start.0, function.end.map(|end| end.0)))
+            .collect();
+        assert_eq!(bounds, vec![(0, Some(5)), (5, Some(9)), (9, Some(0xE))]);
+
+        let symbols = Symbols::new(&program, &functions);
+        let names: Vec<(u16, &str)> = symbols
+            .functions()
+            .map(|(start, name)| (start.0, name))
+            .collect();
+        assert_eq!(
+            names,
+            vec![(0, "sub_0000"), (5, "sub_0005"), (9, "sub_0009")]
+        );
+        assert_eq!(symbols.function_ending_at(Address(9)), Some("sub_0005"));
+        assert_eq!(symbols.describe(Address(7)), "sub_0005+0x2");
+    }
+}
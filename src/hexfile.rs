@@ -0,0 +1,215 @@
+use crate::error::Error;
+use crate::image::Image;
+
+/// Parses an Intel HEX file into the image its data records describe.
+///
+/// Extended segment (`02`) and extended linear (`04`) address records move the base the
+/// following data records are placed relative to, and start segment (`03`) and start linear
+/// (`05`) address records give the entry point.
+pub fn parse_intel(text: &str) -> Result<Image, Error> {
+    let mut image = Image::default();
+    let mut base = 0u32;
+
+    for (index, line) in records(text) {
+        let record = line
+            .strip_prefix(':')
+            .ok_or(Error::InvalidRecord(index, "missing ':'"))?;
+        let bytes = hex_bytes(record, index)?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            return Err(Error::InvalidRecord(
+                index,
+                "length does not match the record",
+            ));
+        }
+        let (contents, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = sum(contents).wrapping_neg();
+        if checksum[0] != expected {
+            return Err(Error::RecordChecksum(index, checksum[0], expected));
+        }
+
+        let offset = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &contents[4..];
+        let word = || match data {
+            [high, low] => Ok(u16::from_be_bytes([*high, *low]) as u32),
+            _ => Err(Error::InvalidRecord(
+                index,
+                "address record is not two bytes",
+            )),
+        };
+        match bytes[3] {
+            0x00 => image.insert(base + offset, data),
+            0x01 => break,
+            0x02 => base = word()? << 4,
+            0x04 => base = word()? << 16,
+            0x03 => match data {
+                [cs_high, cs_low, ip_high, ip_low] => {
+                    let cs = u16::from_be_bytes([*cs_high, *cs_low]) as u32;
+                    let ip = u16::from_be_bytes([*ip_high, *ip_low]) as u32;
+                    image.entry = Some((cs << 4) + ip);
+                }
+                _ => {
+                    return Err(Error::InvalidRecord(
+                        index,
+                        "start address is not four bytes",
+                    ))
+                }
+            },
+            0x05 => match data {
+                [a, b, c, d] => image.entry = Some(u32::from_be_bytes([*a, *b, *c, *d])),
+                _ => {
+                    return Err(Error::InvalidRecord(
+                        index,
+                        "start address is not four bytes",
+                    ))
+                }
+            },
+            _ => return Err(Error::InvalidRecord(index, "unknown record type")),
+        }
+    }
+
+    Ok(image)
+}
+
+/// Parses a Motorola S-record file into the image its `S1`/`S2`/`S3` records describe,
+/// taking the entry point from the `S7`/`S8`/`S9` record that ends it.
+pub fn parse_srecord(text: &str) -> Result<Image, Error> {
+    let mut image = Image::default();
+
+    for (index, line) in records(text) {
+        let record = line
+            .strip_prefix('S')
+            .ok_or(Error::InvalidRecord(index, "missing 'S'"))?;
+        let kind = record
+            .chars()
+            .next()
+            .ok_or(Error::InvalidRecord(index, "missing record type"))?;
+        let bytes = hex_bytes(&record[kind.len_utf8()..], index)?;
+        if bytes.len() < 2 || bytes.len() != bytes[0] as usize + 1 {
+            return Err(Error::InvalidRecord(
+                index,
+                "length does not match the record",
+            ));
+        }
+        let (contents, checksum) = bytes.split_at(bytes.len() - 1);
+        let expected = !sum(contents);
+        if checksum[0] != expected {
+            return Err(Error::RecordChecksum(index, checksum[0], expected));
+        }
+
+        let address_size = match kind {
+            '0' | '1' | '5' | '9' => 2,
+            '2' | '6' | '8' => 3,
+            '3' | '7' => 4,
+            _ => return Err(Error::InvalidRecord(index, "unknown record type")),
+        };
+        let fields = &contents[1..];
+        if fields.len() < address_size {
+            return Err(Error::InvalidRecord(
+                index,
+                "record too short for its address",
+            ));
+        }
+        let (address, data) = fields.split_at(address_size);
+        let address = address
+            .iter()
+            .fold(0u32, |address, &byte| (address << 8) | byte as u32);
+        match kind {
+            '1' | '2' | '3' => image.insert(address, data),
+            '7' | '8' | '9' => {
+                image.entry = Some(address);
+                break;
+            }
+            // Header and record counts.
+            _ => {}
+        }
+    }
+
+    Ok(image)
+}
+
+/// Non-blank lines with their line numbers.
+fn records(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty())
+}
+
+fn hex_bytes(text: &str, line: usize) -> Result<Vec<u8>, Error> {
+    if !text.is_ascii() {
+        return Err(Error::InvalidRecord(line, "invalid hex digit"));
+    }
+    if !text.len().is_multiple_of(2) {
+        return Err(Error::InvalidRecord(line, "odd number of hex digits"));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&text[i..i + 2], 16)
+                .map_err(|_| Error::InvalidRecord(line, "invalid hex digit"))
+        })
+        .collect()
+}
+
+fn sum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::Error;
+    use crate::hexfile::{parse_intel, parse_srecord};
+
+    #[test]
+    fn test_parse_intel() -> anyhow::Result<()> {
+        let text = "\
+:02000002F0000C
+:03000000B8010044
+:020010009090CE
+:04000003F000000009
+:00000001FF
+";
+        let image = parse_intel(text)?;
+        let regions: Vec<(u32, &[u8])> = image.regions().collect();
+        assert_eq!(
+            regions,
+            vec![
+                (0xF0000, &[0xB8, 0x01, 0x00][..]),
+                (0xF0010, &[0x90, 0x90][..])
+            ]
+        );
+        assert_eq!(image.entry, Some(0xF0000));
+
+        assert!(matches!(
+            parse_intel(&text.replace("9090CE", "9090CF")),
+            Err(Error::RecordChecksum(3, 0xCF, 0xCE))
+        ));
+        assert!(matches!(
+            parse_intel(&text.replace(":03", ":04")),
+            Err(Error::InvalidRecord(2, _))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_srecord() -> anyhow::Result<()> {
+        let text = "\
+S00600004844521B
+S1060100B801003F
+S10501039090D6
+S9030100FB
+";
+        let image = parse_srecord(text)?;
+        let regions: Vec<(u32, &[u8])> = image.regions().collect();
+        assert_eq!(regions, vec![(0x100, &[0xB8, 0x01, 0x00, 0x90, 0x90][..])]);
+        assert_eq!(image.entry, Some(0x100));
+
+        assert!(matches!(
+            parse_srecord(&text.replace("9090D6", "9090D7")),
+            Err(Error::RecordChecksum(3, 0xD7, 0xD6))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use crate::cfg::ControlFlowGraph;
+use crate::explain::explain;
+use crate::flow::{Item, Program};
+use crate::format::{exact, Palette};
+use crate::idioms;
+use crate::instruction::Instruction;
+use crate::listing::{format_instruction, item_size, render_data, trailing_comment};
+use crate::memory::Address;
+use crate::symbols::Symbols;
+use crate::xref::Xrefs;
+use std::collections::BTreeMap;
+
+const STYLE: &str = "
+body { font-family: monospace; margin: 0; display: flex; }
+nav { position: sticky; top: 0; height: 100vh; overflow-y: auto; padding: 1em; min-width: 14em;
+      border-right: 1px solid #ccc; background: #f7f7f7; }
+nav a { display: block; }
+main { padding: 1em; }
+.line { white-space: pre; }
+.line:target, a:target + .line { background: #ffe680; }
+.address { color: #888; }
+.bytes { color: #aaa; display: inline-block; width: 14ch; }
+.label { font-weight: bold; }
+.comment { color: #777; }
+.instruction[title] { cursor: help; }
+details { border-left: 2px solid #ddd; padding-left: 0.5em; margin: 0.2em 0; }
+summary { color: #888; cursor: pointer; }
+a { color: #0645ad; text-decoration: none; }
+a:hover { text-decoration: underline; }
+";
+
+/// Unfolds the block holding a link's target, which a folded block would hide.
+const SCRIPT: &str = "
+function reveal() {
+  const target = document.getElementById(location.hash.slice(1));
+  for (let node = target; node; node = node.parentElement) {
+    if (node.tagName === 'DETAILS') node.open = true;
+  }
+}
+window.addEventListener('hashchange', reveal);
+window.addEventListener('load', reveal);
+";
+
+/// Renders the traced program as a single HTML page needing no other files.
+///
+/// Every item is anchored by its address, so branch targets and cross-references are
+/// links. Basic blocks fold, and hovering an instruction shows its encoding fields.
+pub fn render(title: &str, program: &Program, symbols: &Symbols, xrefs: &Xrefs) -> String {
+    let blocks: BTreeMap<Address, usize> = ControlFlowGraph::build(program, symbols)
+        .blocks
+        .iter()
+        .map(|block| (block.start, block.instructions.len()))
+        .collect();
+
+    let idioms = idioms::recognize(program);
+
+    let mut page = String::new();
+    page.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    page.push_str(&format!("<title>{}</title>\n", escape(title)));
+    page.push_str(&format!("<style>{STYLE}</style>\n</head>\n<body>\n"));
+
+    page.push_str("<nav>\n<b>Functions</b>\n");
+    for (address, name) in symbols.functions() {
+        page.push_str(&format!("{}\n", link(address, name)));
+    }
+    page.push_str("</nav>\n<main>\n");
+    page.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+
+    let mut in_block = false;
+    for (address, item) in &program.items {
+        let starts_block = blocks.contains_key(address) || matches!(item, Item::Data(_));
+        if in_block && starts_block {
+            page.push_str("</details>\n");
+            in_block = false;
+        }
+
+        if let Some(function) = symbols.function_ending_at(*address) {
+            page.push_str(&comment(&format!("; {function} endp")));
+        }
+        page.push_str(&format!("<a id=\"{}\"></a>", anchor(*address)));
+        if symbols.is_function(*address) {
+            page.push_str("<hr>\n");
+        }
+        for line in symbols.source(*address) {
+            page.push_str(&comment(&format!("; {line}")));
+        }
+        if let Some(name) = symbols.label(*address) {
+            page.push_str(&format!(
+                "<div class=\"line label\">{}:</div>\n",
+                escape(name)
+            ));
+        }
+        if let Some(text) = symbols.comment(*address) {
+            page.push_str(&comment(&format!("; {text}")));
+        }
+        page.push_str(&xref_comment(*address, symbols, xrefs));
+
+        match item {
+            Item::Instruction(instruction) => {
+                if !in_block {
+                    let count = blocks.get(address).copied().unwrap_or(0);
+                    page.push_str(&format!(
+                        "<details open><summary>block {:04X}, {count} instruction{}</summary>\n",
+                        address.0,
+                        if count == 1 { "" } else { "s" }
+                    ));
+                    in_block = true;
+                }
+                page.push_str(&render_instruction(
+                    *address,
+                    instruction,
+                    program,
+                    symbols,
+                    trailing_comment(*address, instruction, symbols, &idioms),
+                ));
+            }
+            Item::Data(data) => {
+                let lines = render_data(*address, data, symbols.data_width(*address));
+                for (index, line) in lines.lines().enumerate() {
+                    let shown = match index {
+                        0 => format!("{:04X}", address.0),
+                        _ => "    ".to_string(),
+                    };
+                    page.push_str(&format!(
+                        "<div class=\"line\"><span class=\"address\">{shown}</span>  <span class=\"bytes\"></span> {}</div>\n",
+                        escape(line)
+                    ));
+                }
+            }
+        }
+    }
+    if in_block {
+        page.push_str("</details>\n");
+    }
+
+    if let Some((address, item)) = program.items.last_key_value() {
+        let end = Address(address.0.wrapping_add(item_size(item) as u16));
+        if let Some(function) = symbols.function_ending_at(end) {
+            page.push_str(&comment(&format!("; {function} endp")));
+        }
+    }
+
+    page.push_str(&format!(
+        "</main>\n<script>{SCRIPT}</script>\n</body>\n</html>\n"
+    ));
+    page
+}
+
+fn render_instruction(
+    address: Address,
+    instruction: &Instruction,
+    program: &Program,
+    symbols: &Symbols,
+    trailing: Option<String>,
+) -> String {
+    let bytes: Vec<String> = instruction
+        .encoding()
+        .bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+
+    // Same text as the listing, with a branch target that was traced turned into a link.
+    let plain = format_instruction(address, instruction, symbols, Palette::Plain);
+    let text = match instruction.to_jump() {
+        Some(jmp) if program.items.contains_key(&jmp.target(address)) => {
+            let mnemonic = jmp.to_string();
+            let name = plain
+                .strip_prefix(&format!("{mnemonic} "))
+                .unwrap_or(&plain);
+            format!("{} {}", escape(&mnemonic), link(jmp.target(address), name))
+        }
+        _ => escape(&plain),
+    };
+    let text = exact(instruction, text, Palette::Plain);
+
+    let tooltip = match (explain(instruction), instruction.encoding().table_row()) {
+        (Some(fields), Some(row)) => format!(" title=\"{}\"", escape(&format!("{row}\n{fields}"))),
+        _ => String::new(),
+    };
+
+    let trailing = match trailing {
+        Some(text) => format!(" <span class=\"comment\">{}</span>", escape(&text)),
+        None => String::new(),
+    };
+
+    format!(
+        "<div class=\"line\"><span class=\"address\">{:04X}</span>  <span class=\"bytes\">{}</span> <span class=\"instruction\"{tooltip}>{text}</span>{trailing}</div>\n",
+        address.0,
+        bytes.join(" ")
+    )
+}
+
+/// Cross-references to `address` as links back to the referencing instructions.
+fn xref_comment(address: Address, symbols: &Symbols, xrefs: &Xrefs) -> String {
+    let references = xrefs.to(address);
+    if references.is_empty() {
+        return String::new();
+    }
+
+    let links: Vec<String> = references
+        .iter()
+        .map(|xref| {
+            format!(
+                "{} ({})",
+                link(xref.from, &symbols.describe(xref.from)),
+                xref.access
+            )
+        })
+        .collect();
+    format!(
+        "<div class=\"line comment\">; xref: {}</div>\n",
+        links.join(", ")
+    )
+}
+
+fn comment(text: &str) -> String {
+    format!("<div class=\"line comment\">{}</div>\n", escape(text))
+}
+
+fn anchor(address: Address) -> String {
+    format!("a{:04X}", address.0)
+}
+
+fn link(address: Address, text: &str) -> String {
+    format!("<a href=\"#{}\">{}</a>", anchor(address), escape(text))
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\n' => escaped.push_str("&#10;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Layout};
+    use crate::functions::discover;
+    use crate::html::render;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::source_map::SourceLine;
+    use crate::symbols::{OperandField, Symbols};
+    use crate::xref::Xrefs;
+
+    #[test]
+    fn test_render_links_targets() {
+        // mov ax, bx; add ax, bx; je $-2; ret
+        let program = trace(
+            &Image::flat(&[0x89, 0xD8, 0x01, 0xD8, 0x74, 0xFC, 0xC3]),
+            &[Address(0)],
+            &Layout::default(),
+        );
+        let symbols = Symbols::new(&program, &discover(&program));
+        let xrefs = Xrefs::collect(&program, &symbols);
+        let page = render("test.bin", &program, &symbols, &xrefs);
+
+        assert!(page.contains(r##"je <a href="#a0002">.loc_0002</a>"##));
+        assert!(page.contains(r##"; xref: <a href="#a0004">sub_0000+0x4</a> (x)"##));
+        assert!(page.contains(r##"<a href="#a0000">sub_0000</a>"##));
+        assert!(page.contains("<details open><summary>block 0002, 2 instructions</summary>"));
+        assert!(page.contains("title=\"ADD register/memory with register&#10;000000 d=0 w=1"));
+    }
+
+    #[test]
+    fn test_render_matches_listing() {
+        // mov ax, bx with d set; mov ax, 0; call +0x1000; ret
+        let program = trace(
+            &Image::flat(&[0x8B, 0xC3, 0xB8, 0x00, 0x00, 0xE8, 0x00, 0x10, 0xC3]),
+            &[Address(0)],
+            &Layout::default(),
+        );
+        let mut symbols = Symbols::new(&program, &discover(&program));
+        symbols.name_operand(Address(2), OperandField::Immediate, "_data".to_string());
+        symbols.name_operand(Address(5), OperandField::Branch, "_printf".to_string());
+        let line = SourceLine {
+            number: Some(12),
+            text: "start: mov ax, bx".to_string(),
+        };
+        symbols.add_source(Address(0), vec![line]);
+        let xrefs = Xrefs::collect(&program, &symbols);
+        let page = render("test.bin", &program, &symbols, &xrefs);
+
+        assert!(page.contains("<div class=\"line comment\">; 12: start: mov ax, bx</div>"));
+        assert!(page.contains(">db 0x8b, 0xc3 ; mov ax, bx</span>"));
+        assert!(page.contains(">mov ax, word _data</span>"));
+        assert!(page.contains(">call _printf</span>"));
+    }
+}
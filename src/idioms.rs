@@ -0,0 +1,261 @@
+use crate::flow::Program;
+use crate::instruction::Instruction;
+use crate::memory::Address;
+use crate::operand::Operand;
+use crate::register::Register;
+use std::collections::BTreeMap;
+
+/// How far back from a `rep movs` to look for the `mov cx, N` setting its count.
+const COUNT_LOOKBACK: usize = 8;
+
+/// Recognizes common 8086 idioms in the traced code and describes what they compute,
+/// keyed by the address of the instruction the description goes on.
+///
+/// Only instructions that directly follow each other in memory form a sequence.
+pub fn recognize(program: &Program) -> BTreeMap<Address, String> {
+    let mut runs: Vec<Vec<(Address, &Instruction)>> = Vec::new();
+    let mut next_address = None;
+    for (address, instruction) in program.instructions() {
+        match runs.last_mut() {
+            Some(run) if next_address == Some(address) => run.push((address, instruction)),
+            _ => runs.push(vec![(address, instruction)]),
+        }
+        next_address = Some(Address(
+            address.0.wrapping_add(instruction.get_size() as u16),
+        ));
+    }
+
+    let mut idioms = BTreeMap::new();
+    for run in &runs {
+        for index in 0..run.len() {
+            if let Some((address, text)) = recognize_at(run, index) {
+                idioms.entry(address).or_insert(text);
+            }
+        }
+    }
+
+    idioms
+}
+
+/// Idiom starting at `run[index]`, with the address of the instruction it is noted on.
+fn recognize_at(run: &[(Address, &Instruction)], index: usize) -> Option<(Address, String)> {
+    let (address, instruction) = run[index];
+    let next = run.get(index + 1).map(|(_, next)| *next);
+
+    let text = match (instruction, next) {
+        (Instruction::Xor { dst, src, .. } | Instruction::Sub { dst, src, .. }, _)
+            if same_register(dst, src) =>
+        {
+            format!("{dst} = 0")
+        }
+        (
+            Instruction::Add { dst, src, .. },
+            Some(Instruction::Adc {
+                dst: high,
+                src: high_src,
+                ..
+            }),
+        ) => {
+            format!(
+                "{} += {} (32-bit add)",
+                pair(high, dst),
+                pair(high_src, src)
+            )
+        }
+        (
+            Instruction::Sub { dst, src, .. },
+            Some(Instruction::Sbb {
+                dst: high,
+                src: high_src,
+                ..
+            }),
+        ) => {
+            format!(
+                "{} -= {} (32-bit subtract)",
+                pair(high, dst),
+                pair(high_src, src)
+            )
+        }
+        (Instruction::Cwd { .. }, Some(divide @ Instruction::Idiv { src, .. }))
+            if divide.operand_width() == Some(2) =>
+        {
+            let divisor = describe(src);
+            format!("ax = ax / {divisor}, dx = ax % {divisor} (signed divide)")
+        }
+        (Instruction::Or { dst, src, .. }, Some(jump)) if same_register(dst, src) => {
+            let condition = match jump.to_jump()?.to_string().as_str() {
+                "je" => "== 0",
+                "jne" => "!= 0",
+                "js" => "< 0",
+                "jns" => ">= 0",
+                _ => return None,
+            };
+            format!("jump if {dst} {condition}")
+        }
+        (Instruction::Mov { dst, src, .. }, _) if shift_chain(run, index + 1, src) > 0 => {
+            let count = shift_chain(run, index + 1, src);
+            match run.get(index + 1 + count) {
+                Some((
+                    _,
+                    Instruction::Add {
+                        dst: sum,
+                        src: saved,
+                        ..
+                    },
+                )) if same_register(sum, src) && same_register(saved, dst) => {
+                    format!("{src} *= {}", (1u32 << count) + 1)
+                }
+                _ => return None,
+            }
+        }
+        (Instruction::Shl { dst, .. }, _) => {
+            // Only the start of a chain is noted, and not at all when it multiplies a saved copy.
+            if let Some(previous) = index.checked_sub(1) {
+                let continues = matches!(run[previous].1, Instruction::Shl { dst: shifted, count: Operand::Immediate8(1), .. } if same_register(shifted, dst));
+                if continues || recognize_at(run, previous).is_some() {
+                    return None;
+                }
+            }
+            let count = shift_chain(run, index, dst);
+            if count < 2 {
+                return None;
+            }
+            format!("{dst} *= {}", 1u32 << count)
+        }
+        (Instruction::Movs { rep: Some(_), .. }, _) => {
+            let unit = if instruction.operand_width() == Some(1) {
+                1
+            } else {
+                2
+            };
+            let copied = match repeat_count(run, index) {
+                Some(count) => format!("{} bytes", count as u32 * unit),
+                None if unit == 1 => "cx bytes".to_string(),
+                None => "cx words".to_string(),
+            };
+            format!("copy {copied} from ds:si to es:di")
+        }
+        _ => return None,
+    };
+
+    Some((address, text))
+}
+
+/// Number of `shl reg, 1` in a row from `run[start]` shifting `operand`.
+fn shift_chain(run: &[(Address, &Instruction)], start: usize, operand: &Operand) -> usize {
+    run.get(start..)
+        .unwrap_or_default()
+        .iter()
+        .take_while(|(_, instruction)| {
+            matches!(instruction, Instruction::Shl { dst, count: Operand::Immediate8(1), .. } if same_register(dst, operand))
+        })
+        .count()
+}
+
+/// Value `mov cx, N` loaded into `cx` before the `rep` at `run[index]`, as long as
+/// nothing in between writes `cx`.
+fn repeat_count(run: &[(Address, &Instruction)], index: usize) -> Option<u16> {
+    for (_, instruction) in run[index.saturating_sub(COUNT_LOOKBACK)..index]
+        .iter()
+        .rev()
+    {
+        if let Instruction::Mov {
+            dst: Operand::Register(Register::Cx),
+            src: Operand::Immediate16(count),
+            ..
+        } = instruction
+        {
+            return Some(*count);
+        }
+        // The destination is always the first operand.
+        let written = instruction.operands().first().copied();
+        if matches!(
+            written,
+            Some(Operand::Register(
+                Register::Cx | Register::Cl | Register::Ch
+            ))
+        ) {
+            return None;
+        }
+    }
+
+    None
+}
+
+fn same_register(a: &Operand, b: &Operand) -> bool {
+    matches!((a, b), (Operand::Register(a), Operand::Register(b)) if a == b)
+}
+
+/// Two 16-bit halves as one 32-bit value, like `dx:ax`, or a single number when both
+/// halves are immediates.
+fn pair(high: &Operand, low: &Operand) -> String {
+    match (immediate(high), immediate(low)) {
+        (Some(high), Some(low)) => (((high as u32) << 16) | low as u32).to_string(),
+        _ => format!("{}:{}", describe(high), describe(low)),
+    }
+}
+
+fn describe(operand: &Operand) -> String {
+    match immediate(operand) {
+        Some(value) => value.to_string(),
+        None => operand.to_string(),
+    }
+}
+
+fn immediate(operand: &Operand) -> Option<u16> {
+    match operand {
+        Operand::Immediate8(value) => Some(*value as u16),
+        Operand::Immediate16(value) => Some(*value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Layout};
+    use crate::idioms::recognize;
+    use crate::image::Image;
+    use crate::memory::Address;
+
+    #[test]
+    fn test_recognize() {
+        let bin = [
+            0x31, 0xC0, // xor ax, ax
+            0x29, 0xD8, // sub ax, bx
+            0x19, 0xCA, // sbb dx, cx
+            0x99, // cwd
+            0xF7, 0xFB, // idiv bx
+            0x89, 0xC3, // mov bx, ax
+            0xD1, 0xE0, // shl ax, 1
+            0xD1, 0xE0, // shl ax, 1
+            0x01, 0xD8, // add ax, bx
+            0xD1, 0xE2, // shl dx, 1
+            0xD1, 0xE2, // shl dx, 1
+            0xD1, 0xE2, // shl dx, 1
+            0xB9, 0x10, 0x00, // mov cx, 16
+            0xF3, 0xA5, // rep movsw
+            0x09, 0xC0, // or ax, ax
+            0x74, 0x00, // je $+2
+            0xC3, // ret
+        ];
+        let program = trace(&Image::flat(&bin), &[Address(0)], &Layout::default());
+        let idioms = recognize(&program);
+
+        let expected = [
+            (0x00, "ax = 0"),
+            (0x02, "dx:ax -= cx:bx (32-bit subtract)"),
+            (0x06, "ax = ax / bx, dx = ax % bx (signed divide)"),
+            (0x09, "ax *= 5"),
+            (0x11, "dx *= 8"),
+            (0x1A, "copy 32 bytes from ds:si to es:di"),
+            (0x1C, "jump if ax == 0"),
+        ];
+        assert_eq!(
+            idioms
+                .iter()
+                .map(|(address, text)| (address.0, text.as_str()))
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+}
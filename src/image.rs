@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Bytes placed at linear addresses, with nothing in between the regions that were loaded.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Image {
+    /// Contiguous runs of bytes keyed by their start. Runs never touch or overlap.
+    regions: BTreeMap<u32, Vec<u8>>,
+    /// Start address given by the file, if any.
+    pub entry: Option<u32>,
+}
+
+impl Image {
+    /// A flat binary: one region at address 0.
+    pub fn flat(bytes: &[u8]) -> Image {
+        let mut image = Image::default();
+        image.insert(0, bytes);
+        image
+    }
+
+    /// Places `bytes` at `address`, replacing what was there and joining the regions they
+    /// touch.
+    pub fn insert(&mut self, address: u32, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut start = address;
+        let mut end = address + bytes.len() as u32;
+
+        let touching: Vec<u32> = self
+            .regions
+            .range(..=end)
+            .filter(|(&region, data)| region + data.len() as u32 >= address)
+            .map(|(&region, _)| region)
+            .collect();
+        let mut merged = BTreeMap::new();
+        for region in touching {
+            let data = self.regions.remove(&region).expect("region just listed");
+            start = start.min(region);
+            end = end.max(region + data.len() as u32);
+            merged.insert(region, data);
+        }
+
+        let mut joined = vec![0; (end - start) as usize];
+        for (region, data) in merged {
+            let offset = (region - start) as usize;
+            joined[offset..offset + data.len()].copy_from_slice(&data);
+        }
+        let offset = (address - start) as usize;
+        joined[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.regions.insert(start, joined);
+    }
+
+    pub fn regions(&self) -> impl Iterator<Item = (u32, &[u8])> {
+        self.regions
+            .iter()
+            .map(|(&start, data)| (start, data.as_slice()))
+    }
+
+    /// Lowest loaded address, 0 for an empty image.
+    pub fn start(&self) -> u32 {
+        self.regions.keys().next().copied().unwrap_or(0)
+    }
+
+    /// Address just past the highest loaded byte.
+    pub fn end(&self) -> u32 {
+        self.regions
+            .iter()
+            .next_back()
+            .map_or(0, |(&start, data)| start + data.len() as u32)
+    }
+
+    /// Segment of the paragraph the image starts in.
+    pub fn segment(&self) -> u16 {
+        (self.start() >> 4) as u16
+    }
+
+    /// The same image with addresses counted from the start of `segment`.
+    pub fn relative_to(&self, segment: u16) -> Image {
+        let base = segment as u32 * 16;
+        Image {
+            regions: self
+                .regions
+                .iter()
+                .map(|(&start, data)| (start - base, data.clone()))
+                .collect(),
+            entry: self.entry.map(|entry| entry.wrapping_sub(base)),
+        }
+    }
+
+    /// The part of the image inside the 64 KiB of `segment`, with addresses counted from
+    /// its start.
+    pub fn window(&self, segment: u16) -> Image {
+        let base = segment as u32 * 16;
+        let mut window = Image::default();
+        for (start, data) in self.regions() {
+            let from = start.max(base);
+            let to = (start + data.len() as u32).min(base + 0x10000);
+            if from < to {
+                window.insert(
+                    from - base,
+                    &data[(from - start) as usize..(to - start) as usize],
+                );
+            }
+        }
+        window.entry = self.entry.map(|entry| entry.wrapping_sub(base));
+        window
+    }
+
+    /// Loaded regions and the start address as assembly comments, for the top of a listing.
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        for (start, data) in self.regions() {
+            let _ = writeln!(
+                text,
+                "; {:#07x}..{:#07x}: {} bytes",
+                start,
+                start + data.len() as u32,
+                data.len()
+            );
+        }
+        if let Some(entry) = self.entry {
+            let _ = writeln!(text, "; start address {entry:#07x}");
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::Image;
+
+    #[test]
+    fn test_insert() {
+        let mut image = Image::default();
+        image.insert(0x100, &[1, 2]);
+        image.insert(0x200, &[3]);
+        image.insert(0x180, &[]);
+        let regions: Vec<(u32, &[u8])> = image.regions().collect();
+        assert_eq!(regions, vec![(0x100, &[1, 2][..]), (0x200, &[3][..])]);
+
+        // Touching either end joins the regions.
+        image.insert(0x102, &[4]);
+        image.insert(0x1FF, &[9]);
+        let regions: Vec<(u32, &[u8])> = image.regions().collect();
+        assert_eq!(regions, vec![(0x100, &[1, 2, 4][..]), (0x1FF, &[9, 3][..])]);
+
+        // Overlapping bytes are replaced and the region grows.
+        image.insert(0x101, &[7, 7, 7]);
+        let regions: Vec<(u32, &[u8])> = image.regions().collect();
+        assert_eq!(regions[0], (0x100, &[1, 7, 7, 7][..]));
+
+        // Bytes spanning the gap merge everything into one region.
+        image.insert(0x102, &[5; 0x100]);
+        let regions: Vec<(u32, &[u8])> = image.regions().collect();
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].0, 0x100);
+        assert_eq!(regions[0].1.len(), 0x102);
+        assert_eq!(regions[0].1[..3], [1, 7, 5]);
+        assert_eq!((image.start(), image.end()), (0x100, 0x202));
+    }
+}
@@ -1,124 +1,601 @@
+use crate::encoding::Encoding;
+use crate::format::{FormatContext, Palette, Plain, Style};
+use crate::memory::Address;
 use crate::operand::Operand;
+use crate::register::{Register, SegmentRegister};
 use std::fmt::Formatter;
 
+/// Opcode family an instruction was decoded from, for the instructions the 8086 can
+/// encode in more than one way.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Form {
+    /// Register and `r/m` operands through a ModRM byte. `d` is set when the `reg` field
+    /// names the destination.
+    RegisterMemory { d: bool },
+    /// Immediate to `r/m` through a ModRM byte (`80`-`83`, `C6`, `C7`).
+    ImmediateToRegisterMemory { opcode: u8 },
+    /// Register named by the opcode itself, like `B8+r`, `50+r`, or the accumulator
+    /// forms `04`/`05`.
+    Short,
+    /// Accumulator to or from a direct address (`A0`-`A3`).
+    Accumulator,
+}
+
+/// Repeat prefix in front of a string instruction.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Repeat {
+    /// `F3`
+    Rep,
+    /// `F2`, which `movs` repeats under just like `rep`.
+    Repne,
+}
+
+/// How an instruction has to be written for NASM to assemble it back to the same bytes.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Spelling {
+    Plain,
+    /// The immediate needs `strict` to keep NASM from shortening it to a sign-extended byte.
+    StrictImmediate,
+    /// NASM always picks another encoding, so the bytes have to be emitted with `db`.
+    Bytes,
+}
+
 pub enum Instruction {
-    Mov { sz: u8, dst: Operand, src: Operand },
-    Add { sz: u8, dst: Operand, src: Operand },
-    Sub { sz: u8, dst: Operand, src: Operand },
-    Cmp { sz: u8, dst: Operand, src: Operand },
-    Je { sz: u8, ip_increment: i8 },
-    Jl { sz: u8, ip_increment: i8 },
-    Jle { sz: u8, ip_increment: i8 },
-    Jb { sz: u8, ip_increment: i8 },
-    Jbe { sz: u8, ip_increment: i8 },
-    Jp { sz: u8, ip_increment: i8 },
-    Jo { sz: u8, ip_increment: i8 },
-    Js { sz: u8, ip_increment: i8 },
-    Jne { sz: u8, ip_increment: i8 },
-    Jnl { sz: u8, ip_increment: i8 },
-    Jnle { sz: u8, ip_increment: i8 },
-    Jnb { sz: u8, ip_increment: i8 },
-    Jnbe { sz: u8, ip_increment: i8 },
-    Jnp { sz: u8, ip_increment: i8 },
-    Jno { sz: u8, ip_increment: i8 },
-    Jns { sz: u8, ip_increment: i8 },
-    Loop { sz: u8, ip_increment: i8 },
-    Loopz { sz: u8, ip_increment: i8 },
-    Loopnz { sz: u8, ip_increment: i8 },
-    Jcxz { sz: u8, ip_increment: i8 },
+    Mov {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    Add {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    Sub {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    Cmp {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    Or {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    Adc {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    Sbb {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    Xor {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+        src: Operand,
+    },
+    /// `mov` between a segment register and a word register or memory (`8C`, `8E`).
+    MovSegment {
+        encoding: Encoding,
+        /// Whether the segment register is the destination, as in `8E`.
+        to_segment: bool,
+        segment: SegmentRegister,
+        operand: Operand,
+    },
+    /// Sign-extends `ax` into `dx`.
+    Cwd {
+        encoding: Encoding,
+    },
+    /// Signed divide of `ax` (bytes) or `dx:ax` (words).
+    Idiv {
+        encoding: Encoding,
+        src: Operand,
+    },
+    Shl {
+        encoding: Encoding,
+        dst: Operand,
+        /// `cl`, or the immediate 1 of the shift-by-one form.
+        count: Operand,
+    },
+    /// `movsb` or `movsw`, optionally with a `rep` or `repne` prefix.
+    Movs {
+        encoding: Encoding,
+        rep: Option<Repeat>,
+    },
+    Je {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jl {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jle {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jb {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jbe {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jp {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jo {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Js {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jne {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jnl {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jnle {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jnb {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jnbe {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jnp {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jno {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jns {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Loop {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Loopz {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Loopnz {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Jcxz {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    Call {
+        encoding: Encoding,
+        ip_increment: i16,
+    },
+    CallIndirect {
+        encoding: Encoding,
+        far: bool,
+        target: Operand,
+    },
+    CallFar {
+        encoding: Encoding,
+        segment: u16,
+        offset: u16,
+    },
+    Jmp {
+        encoding: Encoding,
+        ip_increment: i16,
+    },
+    JmpShort {
+        encoding: Encoding,
+        ip_increment: i8,
+    },
+    JmpIndirect {
+        encoding: Encoding,
+        far: bool,
+        target: Operand,
+    },
+    JmpFar {
+        encoding: Encoding,
+        segment: u16,
+        offset: u16,
+    },
+    Ret {
+        encoding: Encoding,
+        pop: Option<u16>,
+    },
+    Retf {
+        encoding: Encoding,
+        pop: Option<u16>,
+    },
+    Iret {
+        encoding: Encoding,
+    },
+    Int {
+        encoding: Encoding,
+        vector: u8,
+    },
+    Clc {
+        encoding: Encoding,
+    },
+    Stc {
+        encoding: Encoding,
+    },
+    Cli {
+        encoding: Encoding,
+    },
+    Sti {
+        encoding: Encoding,
+    },
+    Cld {
+        encoding: Encoding,
+    },
+    Std {
+        encoding: Encoding,
+    },
+    Nop {
+        encoding: Encoding,
+    },
+    Push {
+        encoding: Encoding,
+        form: Form,
+        src: Operand,
+    },
+    Pop {
+        encoding: Encoding,
+        form: Form,
+        dst: Operand,
+    },
 }
 
 impl Instruction {
     pub fn get_size(&self) -> u8 {
+        self.encoding().len() as u8
+    }
+
+    /// Bytes the instruction was decoded from and the fields they split into.
+    pub fn encoding(&self) -> &Encoding {
+        match self {
+            Instruction::Mov { encoding, .. }
+            | Instruction::Add { encoding, .. }
+            | Instruction::Sub { encoding, .. }
+            | Instruction::Cmp { encoding, .. }
+            | Instruction::Or { encoding, .. }
+            | Instruction::Adc { encoding, .. }
+            | Instruction::Sbb { encoding, .. }
+            | Instruction::Xor { encoding, .. }
+            | Instruction::MovSegment { encoding, .. }
+            | Instruction::Cwd { encoding }
+            | Instruction::Idiv { encoding, .. }
+            | Instruction::Shl { encoding, .. }
+            | Instruction::Movs { encoding, .. }
+            | Instruction::Je { encoding, .. }
+            | Instruction::Jl { encoding, .. }
+            | Instruction::Jle { encoding, .. }
+            | Instruction::Jb { encoding, .. }
+            | Instruction::Jbe { encoding, .. }
+            | Instruction::Jp { encoding, .. }
+            | Instruction::Jo { encoding, .. }
+            | Instruction::Js { encoding, .. }
+            | Instruction::Jne { encoding, .. }
+            | Instruction::Jnl { encoding, .. }
+            | Instruction::Jnle { encoding, .. }
+            | Instruction::Jnb { encoding, .. }
+            | Instruction::Jnbe { encoding, .. }
+            | Instruction::Jnp { encoding, .. }
+            | Instruction::Jno { encoding, .. }
+            | Instruction::Jns { encoding, .. }
+            | Instruction::Loop { encoding, .. }
+            | Instruction::Loopz { encoding, .. }
+            | Instruction::Loopnz { encoding, .. }
+            | Instruction::Jcxz { encoding, .. }
+            | Instruction::Call { encoding, .. }
+            | Instruction::CallIndirect { encoding, .. }
+            | Instruction::CallFar { encoding, .. }
+            | Instruction::Jmp { encoding, .. }
+            | Instruction::JmpShort { encoding, .. }
+            | Instruction::JmpIndirect { encoding, .. }
+            | Instruction::JmpFar { encoding, .. }
+            | Instruction::Ret { encoding, .. }
+            | Instruction::Retf { encoding, .. }
+            | Instruction::Iret { encoding }
+            | Instruction::Int { encoding, .. }
+            | Instruction::Clc { encoding }
+            | Instruction::Stc { encoding }
+            | Instruction::Cli { encoding }
+            | Instruction::Sti { encoding }
+            | Instruction::Cld { encoding }
+            | Instruction::Std { encoding }
+            | Instruction::Nop { encoding }
+            | Instruction::Push { encoding, .. }
+            | Instruction::Pop { encoding, .. } => encoding,
+        }
+    }
+
+    pub fn mnemonic(&self) -> String {
         match self {
-            Instruction::Mov { sz, .. }
-            | Instruction::Add { sz, .. }
-            | Instruction::Sub { sz, .. }
-            | Instruction::Cmp { sz, .. }
-            | Instruction::Je { sz, .. }
-            | Instruction::Jl { sz, .. }
-            | Instruction::Jle { sz, .. }
-            | Instruction::Jb { sz, .. }
-            | Instruction::Jbe { sz, .. }
-            | Instruction::Jp { sz, .. }
-            | Instruction::Jo { sz, .. }
-            | Instruction::Js { sz, .. }
-            | Instruction::Jne { sz, .. }
-            | Instruction::Jnl { sz, .. }
-            | Instruction::Jnle { sz, .. }
-            | Instruction::Jnb { sz, .. }
-            | Instruction::Jnbe { sz, .. }
-            | Instruction::Jnp { sz, .. }
-            | Instruction::Jno { sz, .. }
-            | Instruction::Jns { sz, .. }
-            | Instruction::Loop { sz, .. }
-            | Instruction::Loopz { sz, .. }
-            | Instruction::Loopnz { sz, .. }
-            | Instruction::Jcxz { sz, .. } => *sz,
+            Instruction::Mov { .. } | Instruction::MovSegment { .. } => "mov".to_string(),
+            Instruction::Add { .. } => "add".to_string(),
+            Instruction::Sub { .. } => "sub".to_string(),
+            Instruction::Cmp { .. } => "cmp".to_string(),
+            Instruction::Or { .. } => "or".to_string(),
+            Instruction::Adc { .. } => "adc".to_string(),
+            Instruction::Sbb { .. } => "sbb".to_string(),
+            Instruction::Xor { .. } => "xor".to_string(),
+            Instruction::Cwd { .. } => "cwd".to_string(),
+            Instruction::Idiv { .. } => "idiv".to_string(),
+            Instruction::Shl { .. } => "shl".to_string(),
+            Instruction::Movs { encoding, .. } => {
+                let width = if encoding.w == Some(0) { "b" } else { "w" };
+                format!("movs{width}")
+            }
+            Instruction::CallIndirect { .. } | Instruction::CallFar { .. } => "call".to_string(),
+            Instruction::Jmp { .. }
+            | Instruction::JmpShort { .. }
+            | Instruction::JmpIndirect { .. }
+            | Instruction::JmpFar { .. } => "jmp".to_string(),
+            Instruction::Ret { .. } => "ret".to_string(),
+            Instruction::Retf { .. } => "retf".to_string(),
+            Instruction::Iret { .. } => "iret".to_string(),
+            Instruction::Int { .. } => "int".to_string(),
+            Instruction::Clc { .. } => "clc".to_string(),
+            Instruction::Stc { .. } => "stc".to_string(),
+            Instruction::Cli { .. } => "cli".to_string(),
+            Instruction::Sti { .. } => "sti".to_string(),
+            Instruction::Cld { .. } => "cld".to_string(),
+            Instruction::Std { .. } => "std".to_string(),
+            Instruction::Nop { .. } => "nop".to_string(),
+            Instruction::Push { .. } => "push".to_string(),
+            Instruction::Pop { .. } => "pop".to_string(),
+            _ => self
+                .to_jump()
+                .expect("Every other instruction is a branch")
+                .to_string(),
         }
     }
 
+    pub fn operands(&self) -> Vec<&Operand> {
+        match self {
+            Instruction::Mov { dst, src, .. }
+            | Instruction::Add { dst, src, .. }
+            | Instruction::Sub { dst, src, .. }
+            | Instruction::Cmp { dst, src, .. }
+            | Instruction::Or { dst, src, .. }
+            | Instruction::Adc { dst, src, .. }
+            | Instruction::Sbb { dst, src, .. }
+            | Instruction::Xor { dst, src, .. } => vec![dst, src],
+            Instruction::MovSegment { operand, .. } | Instruction::Idiv { src: operand, .. } => {
+                vec![operand]
+            }
+            Instruction::Shl { dst, count, .. } => vec![dst, count],
+            Instruction::CallIndirect { target, .. } | Instruction::JmpIndirect { target, .. } => {
+                vec![target]
+            }
+            Instruction::Push { src, .. } => vec![src],
+            Instruction::Pop { dst, .. } => vec![dst],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Width in bytes of the data the instruction operates on, taken from its operands.
+    pub fn operand_width(&self) -> Option<u8> {
+        match self {
+            Instruction::CallIndirect { far: true, .. }
+            | Instruction::JmpIndirect { far: true, .. } => Some(4),
+            Instruction::CallIndirect { .. }
+            | Instruction::JmpIndirect { .. }
+            | Instruction::MovSegment { .. }
+            | Instruction::Push { .. }
+            | Instruction::Pop { .. } => Some(2),
+            Instruction::Idiv { encoding, .. }
+            | Instruction::Shl { encoding, .. }
+            | Instruction::Movs { encoding, .. } => encoding.w.map(|w| w + 1),
+            _ => self
+                .operands()
+                .into_iter()
+                .find_map(|operand| operand.width()),
+        }
+    }
+
+    /// Fixed memory locations read or written by this instruction.
+    pub fn direct_addresses(&self) -> Vec<Address> {
+        self.operands()
+            .into_iter()
+            .filter_map(Operand::direct_address)
+            .collect()
+    }
+
+    /// How to write the instruction so that NASM reassembles it to the bytes it was
+    /// decoded from. NASM prefers the short register and accumulator forms, the `reg, r/m`
+    /// direction with the `d` bit clear and sign-extended byte immediates, and never
+    /// emits the `82` alias of `80`.
+    pub fn nasm_spelling(&self) -> Spelling {
+        let (form, dst, src) = match self {
+            Instruction::Mov { form, dst, src, .. }
+            | Instruction::Add { form, dst, src, .. }
+            | Instruction::Sub { form, dst, src, .. }
+            | Instruction::Cmp { form, dst, src, .. }
+            | Instruction::Or { form, dst, src, .. }
+            | Instruction::Adc { form, dst, src, .. }
+            | Instruction::Sbb { form, dst, src, .. }
+            | Instruction::Xor { form, dst, src, .. } => (*form, dst, src),
+            Instruction::Push {
+                form: Form::RegisterMemory { .. },
+                src: Operand::Register(_),
+                ..
+            }
+            | Instruction::Pop {
+                form: Form::RegisterMemory { .. },
+                dst: Operand::Register(_),
+                ..
+            } => return Spelling::Bytes,
+            _ => return Spelling::Plain,
+        };
+        let is_mov = matches!(self, Instruction::Mov { .. });
+        let is_accumulator = matches!(dst, Operand::Register(Register::Al | Register::Ax));
+        let fits_byte =
+            matches!(src, Operand::Immediate16(value) if (*value as i16) == (*value as i8) as i16);
+
+        match form {
+            Form::RegisterMemory { d: true }
+                if matches!((dst, src), (Operand::Register(_), Operand::Register(_))) =>
+            {
+                Spelling::Bytes
+            }
+            Form::RegisterMemory { .. }
+                if is_mov
+                    && (is_accumulator && src.direct_address().is_some()
+                        || matches!(src, Operand::Register(Register::Al | Register::Ax))
+                            && dst.direct_address().is_some()) =>
+            {
+                Spelling::Bytes
+            }
+            Form::ImmediateToRegisterMemory { opcode: 0x82 } => Spelling::Bytes,
+            Form::ImmediateToRegisterMemory { .. }
+                if is_mov && matches!(dst, Operand::Register(_)) =>
+            {
+                Spelling::Bytes
+            }
+            Form::ImmediateToRegisterMemory {
+                opcode: 0x80 | 0x81,
+            } if !is_mov && is_accumulator => Spelling::Bytes,
+            Form::ImmediateToRegisterMemory { opcode: 0x81 } | Form::Short
+                if !is_mov && fits_byte =>
+            {
+                Spelling::StrictImmediate
+            }
+            _ => Spelling::Plain,
+        }
+    }
+
+    /// Value of the 16-bit immediate operand, the only kind that can hold an address.
+    pub fn immediate(&self) -> Option<u16> {
+        self.operands()
+            .into_iter()
+            .find_map(|operand| match operand {
+                Operand::Immediate16(value) => Some(*value),
+                _ => None,
+            })
+    }
+
+    /// Address control is transferred to when the branch is taken, if this is a branch.
+    pub fn branch_target(&self, address: Address) -> Option<Address> {
+        self.to_jump().map(|jmp| jmp.target(address))
+    }
+
+    /// Whether execution can continue with the instruction that follows in memory.
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            Instruction::Jmp { .. }
+                | Instruction::JmpShort { .. }
+                | Instruction::JmpIndirect { .. }
+                | Instruction::JmpFar { .. }
+                | Instruction::Ret { .. }
+                | Instruction::Retf { .. }
+                | Instruction::Iret { .. }
+        )
+    }
+
     pub fn to_jump(&self) -> Option<Jump> {
         match self {
             Instruction::Je { ip_increment, .. } => Some(Jump::Je {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jl { ip_increment, .. } => Some(Jump::Jl {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jle { ip_increment, .. } => Some(Jump::Jle {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jb { ip_increment, .. } => Some(Jump::Jb {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jbe { ip_increment, .. } => Some(Jump::Jbe {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jp { ip_increment, .. } => Some(Jump::Jp {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jo { ip_increment, .. } => Some(Jump::Jo {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Js { ip_increment, .. } => Some(Jump::Js {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jne { ip_increment, .. } => Some(Jump::Jne {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jnl { ip_increment, .. } => Some(Jump::Jnl {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jnle { ip_increment, .. } => Some(Jump::Jnle {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jnb { ip_increment, .. } => Some(Jump::Jnb {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jnbe { ip_increment, .. } => Some(Jump::Jnbe {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jnp { ip_increment, .. } => Some(Jump::Jnp {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jno { ip_increment, .. } => Some(Jump::Jno {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jns { ip_increment, .. } => Some(Jump::Jns {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Loop { ip_increment, .. } => Some(Jump::Loop {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Loopz { ip_increment, .. } => Some(Jump::Loopz {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Loopnz { ip_increment, .. } => Some(Jump::Loopnz {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
             }),
             Instruction::Jcxz { ip_increment, .. } => Some(Jump::Jcxz {
-                ip_increment: (*ip_increment as i8) as i16,
+                ip_increment: *ip_increment as i16,
+            }),
+            Instruction::Call { ip_increment, .. } => Some(Jump::Call {
+                ip_increment: *ip_increment,
+            }),
+            Instruction::Jmp { ip_increment, .. } => Some(Jump::Jmp {
+                ip_increment: *ip_increment,
+            }),
+            Instruction::JmpShort { ip_increment, .. } => Some(Jump::JmpShort {
+                ip_increment: *ip_increment as i16,
             }),
             _ => None,
         }
@@ -127,83 +604,269 @@ impl Instruction {
 
 impl std::fmt::Display for Instruction {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.write_with(f, &Plain)
+    }
+}
+
+struct WithContext<'a> {
+    instruction: &'a Instruction,
+    context: &'a dyn FormatContext,
+}
+
+impl std::fmt::Display for WithContext<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.instruction.write_with(f, self.context)
+    }
+}
+
+impl Instruction {
+    /// Formats the instruction, letting `context` name the operands it knows about.
+    pub fn format(&self, context: &dyn FormatContext) -> String {
+        let text = WithContext {
+            instruction: self,
+            context,
+        }
+        .to_string();
+
+        let palette = context.palette();
+        if palette == Palette::Plain {
+            return text;
+        }
+        let style = if self.transfers_control() {
+            Style::Branch
+        } else {
+            Style::Mnemonic
+        };
+        match text.split_once(' ') {
+            Some((mnemonic, operands)) => format!("{} {operands}", palette.paint(style, mnemonic)),
+            None => palette.paint(style, &text),
+        }
+    }
+
+    /// Whether this is a jump, call or return.
+    fn transfers_control(&self) -> bool {
+        self.to_jump().is_some()
+            || matches!(
+                self,
+                Instruction::CallIndirect { .. }
+                    | Instruction::CallFar { .. }
+                    | Instruction::JmpIndirect { .. }
+                    | Instruction::JmpFar { .. }
+                    | Instruction::Ret { .. }
+                    | Instruction::Retf { .. }
+                    | Instruction::Iret { .. }
+            )
+    }
+
+    fn write_with(&self, f: &mut Formatter<'_>, context: &dyn FormatContext) -> std::fmt::Result {
         match self {
-            Instruction::Mov { dst, src, .. } => {
-                write!(f, "mov {dst}, {src}")
+            Instruction::Mov { dst, src, .. }
+            | Instruction::Add { dst, src, .. }
+            | Instruction::Sub { dst, src, .. }
+            | Instruction::Cmp { dst, src, .. }
+            | Instruction::Or { dst, src, .. }
+            | Instruction::Adc { dst, src, .. }
+            | Instruction::Sbb { dst, src, .. }
+            | Instruction::Xor { dst, src, .. } => {
+                let strict = match self.nasm_spelling() {
+                    Spelling::StrictImmediate => "strict ",
+                    _ => "",
+                };
+                write!(
+                    f,
+                    "{} {}, {strict}{}",
+                    self.mnemonic(),
+                    dst.format(context),
+                    src.format(context)
+                )
+            }
+            Instruction::MovSegment {
+                to_segment: true,
+                segment,
+                operand,
+                ..
+            } => write!(f, "mov {segment}, {}", operand.format(context)),
+            Instruction::MovSegment {
+                to_segment: false,
+                segment,
+                operand,
+                ..
+            } => write!(f, "mov {}, {segment}", operand.format(context)),
+            Instruction::Cwd { .. }
+            | Instruction::Clc { .. }
+            | Instruction::Stc { .. }
+            | Instruction::Cli { .. }
+            | Instruction::Sti { .. }
+            | Instruction::Cld { .. }
+            | Instruction::Std { .. }
+            | Instruction::Nop { .. } => write!(f, "{}", self.mnemonic()),
+            Instruction::Movs { rep, .. } => {
+                let rep = match rep {
+                    Some(Repeat::Rep) => "rep ",
+                    Some(Repeat::Repne) => "repne ",
+                    None => "",
+                };
+                write!(f, "{rep}{}", self.mnemonic())
+            }
+            Instruction::Idiv { src, .. } => {
+                write!(f, "idiv {}{}", self.memory_size(src), src.format(context))
+            }
+            Instruction::Shl { dst, count, .. } => {
+                write!(
+                    f,
+                    "shl {}{}, {}",
+                    self.memory_size(dst),
+                    dst.format(context),
+                    count.format(context)
+                )
             }
-            Instruction::Add { dst, src, .. } => {
-                write!(f, "add {dst}, {src}")
+            Instruction::Je { ip_increment, .. } => {
+                write!(f, "je ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Sub { dst, src, .. } => {
-                write!(f, "sub {dst}, {src}")
+            Instruction::Jl { ip_increment, .. } => {
+                write!(f, "jl ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Cmp { dst, src, .. } => {
-                write!(f, "cmp {dst}, {src}")
+            Instruction::Jle { ip_increment, .. } => {
+                write!(f, "jle ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Je { ip_increment, sz } => {
-                write!(f, "je ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jb { ip_increment, .. } => {
+                write!(f, "jb ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jl { ip_increment, sz } => {
-                write!(f, "jl ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jbe { ip_increment, .. } => {
+                write!(f, "jbe ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jle { ip_increment, sz } => {
-                write!(f, "jle ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jp { ip_increment, .. } => {
+                write!(f, "jp ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jb { ip_increment, sz } => {
-                write!(f, "jb ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jo { ip_increment, .. } => {
+                write!(f, "jo ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jbe { ip_increment, sz } => {
-                write!(f, "jbe ${}", (ip_increment) + (*sz as i8))
+            Instruction::Js { ip_increment, .. } => {
+                write!(f, "js ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jp { ip_increment, sz } => {
-                write!(f, "jp ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jne { ip_increment, .. } => {
+                write!(f, "jne ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jo { ip_increment, sz } => {
-                write!(f, "jo ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jnl { ip_increment, .. } => {
+                write!(f, "jnl ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Js { ip_increment, sz } => {
-                write!(f, "js ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jnle { ip_increment, .. } => {
+                write!(f, "jnle ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jne { ip_increment, sz } => {
-                write!(f, "jne ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jnb { ip_increment, .. } => {
+                write!(f, "jnb ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jnl { ip_increment, sz } => {
-                write!(f, "jnl ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jnbe { ip_increment, .. } => {
+                write!(f, "jnbe ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jnle { ip_increment, sz } => {
-                write!(f, "jnle ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jnp { ip_increment, .. } => {
+                write!(f, "jnp ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jnb { ip_increment, sz } => {
-                write!(f, "jnb ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jno { ip_increment, .. } => {
+                write!(f, "jno ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jnbe { ip_increment, sz } => {
-                write!(f, "jnbe ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jns { ip_increment, .. } => {
+                write!(f, "jns ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jnp { ip_increment, sz } => {
-                write!(f, "jnp ${}", (ip_increment) + (*sz as i8))
+            Instruction::Loop { ip_increment, .. } => {
+                write!(f, "loop ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jno { ip_increment, sz } => {
-                write!(f, "jno ${}", (ip_increment) + (*sz as i8))
+            Instruction::Loopz { ip_increment, .. } => {
+                write!(f, "loopz ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Jns { ip_increment, sz } => {
-                write!(f, "jns ${}", (ip_increment) + (*sz as i8))
+            Instruction::Loopnz { ip_increment, .. } => {
+                write!(f, "loopnz ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Loop { ip_increment, sz } => {
-                write!(f, "loop ${}", (ip_increment) + (*sz as i8))
+            Instruction::Jcxz { ip_increment, .. } => {
+                write!(f, "jcxz ${}", (ip_increment) + (self.get_size() as i8))
             }
-            Instruction::Loopz { ip_increment, sz } => {
-                write!(f, "loopz ${}", (ip_increment) + (*sz as i8))
+            Instruction::Call { ip_increment, .. } => {
+                write!(
+                    f,
+                    "call ${:+}",
+                    ip_increment.wrapping_add(self.get_size() as i16)
+                )
             }
-            Instruction::Loopnz { ip_increment, sz } => {
-                write!(f, "loopnz ${}", (ip_increment) + (*sz as i8))
+            Instruction::CallIndirect { far, target, .. } => {
+                write!(
+                    f,
+                    "call {}{}",
+                    indirect_size(*far, target),
+                    target.format(context)
+                )
             }
-            Instruction::Jcxz { ip_increment, sz } => {
-                write!(f, "jcxz ${}", (ip_increment) + (*sz as i8))
+            Instruction::CallFar {
+                segment, offset, ..
+            } => match context.far_target(*segment, *offset) {
+                Some(name) => write!(f, "call far {name}"),
+                None => write!(f, "call {segment:#x}:{offset:#x}"),
+            },
+            Instruction::Jmp { ip_increment, .. } => {
+                write!(
+                    f,
+                    "jmp near ${:+}",
+                    ip_increment.wrapping_add(self.get_size() as i16)
+                )
             }
+            Instruction::JmpShort { ip_increment, .. } => {
+                write!(
+                    f,
+                    "jmp short ${:+}",
+                    (*ip_increment as i16) + (self.get_size() as i16)
+                )
+            }
+            Instruction::JmpIndirect { far, target, .. } => {
+                write!(
+                    f,
+                    "jmp {}{}",
+                    indirect_size(*far, target),
+                    target.format(context)
+                )
+            }
+            Instruction::JmpFar {
+                segment, offset, ..
+            } => match context.far_target(*segment, *offset) {
+                Some(name) => write!(f, "jmp far {name}"),
+                None => write!(f, "jmp {segment:#x}:{offset:#x}"),
+            },
+            Instruction::Ret { pop: None, .. } => write!(f, "ret"),
+            Instruction::Ret { pop: Some(pop), .. } => write!(f, "ret {pop}"),
+            Instruction::Retf { pop: None, .. } => write!(f, "retf"),
+            Instruction::Retf { pop: Some(pop), .. } => write!(f, "retf {pop}"),
+            Instruction::Iret { .. } => write!(f, "iret"),
+            Instruction::Int { vector, .. } => write!(f, "int {vector:#04x}"),
+            Instruction::Push { src, .. } => match src {
+                Operand::Memory(_) => write!(f, "push word {}", src.format(context)),
+                _ => write!(f, "push {}", src.format(context)),
+            },
+            Instruction::Pop { dst, .. } => match dst {
+                Operand::Memory(_) => write!(f, "pop word {}", dst.format(context)),
+                _ => write!(f, "pop {}", dst.format(context)),
+            },
         }
     }
 }
 
+impl Instruction {
+    /// Size keyword for a lone memory operand, whose width only the `w` bit gives.
+    fn memory_size(&self, operand: &Operand) -> &'static str {
+        match (operand, self.operand_width()) {
+            (Operand::Memory(_), Some(1)) => "byte ",
+            (Operand::Memory(_), _) => "word ",
+            _ => "",
+        }
+    }
+}
+
+/// Size keyword NASM needs to pick the right form of an indirect `call` or `jmp`.
+fn indirect_size(far: bool, target: &Operand) -> &'static str {
+    match (far, target) {
+        (true, _) => "far ",
+        (false, Operand::Memory(_)) => "word ",
+        (false, _) => "",
+    }
+}
+
 pub enum Jump {
     Je { ip_increment: i16 },
     Jl { ip_increment: i16 },
@@ -225,6 +888,9 @@ pub enum Jump {
     Loopz { ip_increment: i16 },
     Loopnz { ip_increment: i16 },
     Jcxz { ip_increment: i16 },
+    Call { ip_increment: i16 },
+    Jmp { ip_increment: i16 },
+    JmpShort { ip_increment: i16 },
 }
 
 impl Jump {
@@ -249,12 +915,41 @@ impl Jump {
             | Jump::Loop { ip_increment }
             | Jump::Loopz { ip_increment }
             | Jump::Loopnz { ip_increment }
-            | Jump::Jcxz { ip_increment } => *ip_increment,
+            | Jump::Jcxz { ip_increment }
+            | Jump::Call { ip_increment }
+            | Jump::Jmp { ip_increment }
+            | Jump::JmpShort { ip_increment } => *ip_increment,
         }
     }
 
     pub fn len(&self) -> i16 {
-        2
+        match self {
+            Jump::Call { .. } | Jump::Jmp { .. } => 3,
+            _ => 2,
+        }
+    }
+
+    pub fn is_call(&self) -> bool {
+        matches!(self, Jump::Call { .. })
+    }
+
+    /// Whether the branch is one of the `cx`-driven loop instructions.
+    pub fn is_loop(&self) -> bool {
+        matches!(
+            self,
+            Jump::Loop { .. } | Jump::Loopz { .. } | Jump::Loopnz { .. } | Jump::Jcxz { .. }
+        )
+    }
+
+    /// Address the branch lands on, relative to the end of the instruction at `address`.
+    /// Targets past either end of the segment wrap around it like `ip` does.
+    pub fn target(&self, address: Address) -> Address {
+        Address(
+            address
+                .0
+                .wrapping_add(self.len() as u16)
+                .wrapping_add(self.ip_increment() as u16),
+        )
     }
 }
 
@@ -321,6 +1016,15 @@ impl std::fmt::Display for Jump {
             Jump::Jcxz { .. } => {
                 write!(f, "jcxz")
             }
+            Jump::Call { .. } => {
+                write!(f, "call")
+            }
+            Jump::Jmp { .. } => {
+                write!(f, "jmp near")
+            }
+            Jump::JmpShort { .. } => {
+                write!(f, "jmp short")
+            }
         }
     }
 }
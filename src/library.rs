@@ -0,0 +1,160 @@
+use crate::error::Error;
+use crate::omf::Module;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+const LIBHDR: u8 = 0xF0;
+const LIBEND: u8 = 0xF1;
+
+/// Size of a dictionary block, which holds this many buckets and then the entries.
+const BLOCK_SIZE: usize = 512;
+const BUCKETS: usize = 37;
+
+/// Microsoft-format `.LIB` archive: object modules aligned to pages, followed by a hashed
+/// dictionary of their public names.
+pub struct Library {
+    pub page_size: usize,
+    pub modules: Vec<Member>,
+    /// Public names and module names (ending in `!`) with the page of the module
+    /// defining them.
+    pub dictionary: BTreeMap<String, usize>,
+}
+
+pub struct Member {
+    /// Page the module starts at, which is how the dictionary refers to it.
+    pub page: usize,
+    pub module: Module,
+}
+
+impl Library {
+    pub fn parse(bytes: &[u8]) -> Result<Library, Error> {
+        if bytes.len() < 10 || bytes[0] != LIBHDR {
+            return Err(Error::InvalidLibrary("missing library header"));
+        }
+        let page_size = u16::from_le_bytes([bytes[1], bytes[2]]) as usize + 3;
+        let dictionary_offset =
+            u32::from_le_bytes([bytes[3], bytes[4], bytes[5], bytes[6]]) as usize;
+        let blocks = u16::from_le_bytes([bytes[7], bytes[8]]) as usize;
+        if !page_size.is_power_of_two() || page_size < 16 {
+            return Err(Error::InvalidLibrary("page size is not a power of two"));
+        }
+
+        let mut modules = Vec::new();
+        let mut position = page_size;
+        while position < bytes.len() && bytes[position] != LIBEND {
+            let (module, end) = Module::parse_at(bytes, position)?;
+            modules.push(Member {
+                page: position / page_size,
+                module,
+            });
+            position = end.next_multiple_of(page_size);
+        }
+
+        let dictionary_end = dictionary_offset + blocks * BLOCK_SIZE;
+        let dictionary = bytes
+            .get(dictionary_offset..dictionary_end)
+            .ok_or(Error::InvalidLibrary("dictionary past the end of the file"))?;
+        let mut entries = BTreeMap::new();
+        for block in dictionary.chunks(BLOCK_SIZE) {
+            for &bucket in &block[..BUCKETS] {
+                if bucket == 0 {
+                    continue;
+                }
+                let entry = bucket as usize * 2;
+                let length = block[entry] as usize;
+                let page = block
+                    .get(entry + 1 + length..entry + 3 + length)
+                    .ok_or(Error::InvalidLibrary("dictionary entry past its block"))?;
+                let name = String::from_utf8_lossy(&block[entry + 1..entry + 1 + length]);
+                entries.insert(
+                    name.into_owned(),
+                    u16::from_le_bytes([page[0], page[1]]) as usize,
+                );
+            }
+        }
+
+        Ok(Library {
+            page_size,
+            modules,
+            dictionary: entries,
+        })
+    }
+
+    /// Module named `name`, or the one the dictionary says defines the public `name`.
+    pub fn find(&self, name: &str) -> Option<&Member> {
+        let by_name = self
+            .modules
+            .iter()
+            .find(|member| member.module.name.eq_ignore_ascii_case(name));
+        by_name.or_else(|| {
+            let page = self.dictionary.get(name)?;
+            self.modules.iter().find(|member| member.page == *page)
+        })
+    }
+
+    /// Every module with the page it starts at and its public names, as assembly comments.
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "; library: {} modules, {} byte pages, {} dictionary entries",
+            self.modules.len(),
+            self.page_size,
+            self.dictionary.len()
+        );
+        for member in &self.modules {
+            let publics: Vec<&str> = member
+                .module
+                .publics
+                .iter()
+                .map(|public| public.name.as_str())
+                .collect();
+            let _ = writeln!(
+                text,
+                "; page {:#06x} {}: {}",
+                member.page,
+                member.module.name,
+                publics.join(", ")
+            );
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::library::Library;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let mut bytes = vec![0; 16];
+        // 16 byte pages, dictionary of one block at 0x40
+        bytes[..9].copy_from_slice(&[0xF0, 0x0D, 0x00, 0x40, 0x00, 0x00, 0x00, 0x01, 0x00]);
+        // THEADR "a", PUBDEF _f at 0 without a segment, MODEND; checksums left out
+        let module = [
+            0x80, 0x03, 0x00, 0x01, b'a', 0x00, //
+            0x90, 0x0B, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, b'_', b'f', 0x00, 0x00, 0x00, 0x00,
+            0x8A, 0x02, 0x00, 0x00, 0x00,
+        ];
+        bytes.extend(module);
+        bytes.resize(0x30, 0);
+        bytes.extend([0xF1, 0x0D, 0x00]);
+        bytes.resize(0x40, 0);
+
+        let mut block = vec![0; 512];
+        block[0] = 19;
+        block[38..44].copy_from_slice(&[0x02, b'_', b'f', 0x01, 0x00, 0x00]);
+        bytes.extend(block);
+
+        let library = Library::parse(&bytes)?;
+        assert_eq!(library.modules.len(), 1);
+        assert_eq!(library.modules[0].page, 1);
+        assert_eq!(library.dictionary.get("_f"), Some(&1));
+        assert_eq!(
+            library.find("_f").map(|member| member.module.name.as_str()),
+            Some("a")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+use crate::flow::{Item, Program};
+use crate::format::{exact, Palette, Style, Styled};
+use crate::idioms;
+use crate::instruction::Instruction;
+use crate::memory::Address;
+use crate::symbols::{OperandField, Symbols};
+use crate::xref::Xrefs;
+use std::collections::BTreeMap;
+
+const DATA_UNITS_PER_LINE: usize = 8;
+
+/// Shortest run of printable characters emitted as a string.
+const MIN_STRING_LENGTH: usize = 4;
+
+pub fn render(program: &Program, symbols: &Symbols, xrefs: &Xrefs, palette: Palette) -> String {
+    let mut disassembly = String::new();
+    disassembly.push_str("bits 16\n");
+
+    let mut equates = symbols.equates().peekable();
+    if equates.peek().is_some() {
+        disassembly.push('\n');
+    }
+    for (name, address) in equates {
+        let name = palette.paint(Style::Label, name);
+        disassembly.push_str(&format!("{name} equ 0x{:04x}\n", address.0));
+    }
+
+    disassembly.push_str(&render_items(
+        program,
+        symbols,
+        xrefs,
+        palette,
+        |address, data| render_data(address, data, symbols.data_width(address)),
+    ));
+    disassembly
+}
+
+/// Labels, comments, instructions and data of every item, with `data` writing out the
+/// data items.
+pub fn render_items(
+    program: &Program,
+    symbols: &Symbols,
+    xrefs: &Xrefs,
+    palette: Palette,
+    mut data: impl FnMut(Address, &[u8]) -> String,
+) -> String {
+    let label = |name: &str| palette.paint(Style::Label, name);
+    let comment = |text: &str| palette.paint(Style::Comment, text) + "\n";
+
+    let idioms = idioms::recognize(program);
+
+    let mut disassembly = String::new();
+    for (address, item) in &program.items {
+        if let Some(function) = symbols.function_ending_at(*address) {
+            disassembly.push_str(&comment(&format!("; {function} endp")));
+        }
+        if symbols.is_function(*address) {
+            disassembly.push('\n');
+        }
+        for line in symbols.source(*address) {
+            disassembly.push_str(&comment(&format!("; {line}")));
+        }
+        if let Some(name) = symbols.label(*address) {
+            disassembly.push_str(&format!("{}:\n", label(name)));
+        }
+        if let Some(text) = symbols.comment(*address) {
+            disassembly.push_str(&comment(&format!("; {text}")));
+        }
+        if let Some(text) = xrefs.comment(*address, symbols) {
+            disassembly.push_str(&comment(&text));
+        }
+
+        match item {
+            Item::Instruction(instruction) => {
+                let text = format_instruction(*address, instruction, symbols, palette);
+                disassembly.push_str(&exact(instruction, text, palette));
+                if let Some(text) = trailing_comment(*address, instruction, symbols, &idioms) {
+                    disassembly.push_str(&format!(" {}", palette.paint(Style::Comment, &text)));
+                }
+                disassembly.push('\n');
+            }
+            Item::Data(bytes) => disassembly.push_str(&data(*address, bytes)),
+        }
+    }
+
+    if let Some((address, item)) = program.items.last_key_value() {
+        let end = Address(address.0.wrapping_add(item_size(item) as u16));
+        if let Some(function) = symbols.function_ending_at(end) {
+            disassembly.push_str(&comment(&format!("; {function} endp")));
+        }
+    }
+
+    disassembly
+}
+
+/// Formats an instruction, naming its branch target when a label exists for it
+/// and falling back to a `$`-relative target otherwise.
+pub fn format_instruction(
+    address: Address,
+    instruction: &Instruction,
+    symbols: &Symbols,
+    palette: Palette,
+) -> String {
+    match instruction.to_jump() {
+        Some(jmp) => {
+            let target = jmp.target(address);
+            let name = symbols
+                .operand_name(address, OperandField::Branch)
+                .map(str::to_string)
+                .or_else(|| symbols.reference(address, target));
+            let target = match name {
+                Some(name) => palette.paint(Style::Label, &name),
+                None => relative_target(address, target),
+            };
+            format!(
+                "{} {target}",
+                palette.paint(Style::Branch, &jmp.to_string())
+            )
+        }
+        None => instruction.format(&Styled::new(&symbols.at(address), palette)),
+    }
+}
+
+/// Comment after an instruction: the idiom it starts and the segment fixup it holds.
+pub fn trailing_comment(
+    address: Address,
+    instruction: &Instruction,
+    symbols: &Symbols,
+    idioms: &BTreeMap<Address, String>,
+) -> Option<String> {
+    let mut notes = Vec::new();
+    if let Some(idiom) = idioms.get(&address) {
+        notes.push(idiom.clone());
+    }
+    if let Some(segment) = symbols.fixup_in(address, instruction.get_size() as u16) {
+        notes.push(format!("fixup: seg {segment:#06x}"));
+    }
+
+    (!notes.is_empty()).then(|| format!("; {}", notes.join("; ")))
+}
+
+/// Target written relative to the instruction, like `$+12`, for when it has no name.
+pub fn relative_target(address: Address, target: Address) -> String {
+    format!("${:+}", target.0.wrapping_sub(address.0) as i16)
+}
+
+pub fn item_size(item: &Item) -> usize {
+    match item {
+        Item::Instruction(instruction) => instruction.get_size() as usize,
+        Item::Data(bytes) => bytes.len(),
+    }
+}
+
+/// Emits printable runs as strings and everything else as `db`/`dw` directives, using
+/// the width the data is accessed with when it is known.
+pub fn render_data(address: Address, bytes: &[u8], width: Option<u8>) -> String {
+    let mut data = String::new();
+    let detect_strings = matches!(width, None | Some(1));
+
+    let mut start = 0;
+    while start < bytes.len() {
+        let printable = printable_run(&bytes[start..]);
+        if detect_strings && printable >= MIN_STRING_LENGTH {
+            let text = String::from_utf8_lossy(&bytes[start..start + printable]);
+            if bytes.get(start + printable) == Some(&0) {
+                data.push_str(&format!("db \"{text}\", 0\n"));
+                start += printable + 1;
+            } else {
+                data.push_str(&format!("db \"{text}\"\n"));
+                start += printable;
+            }
+            continue;
+        }
+
+        let end = (start + 1..bytes.len())
+            .find(|&i| detect_strings && printable_run(&bytes[i..]) >= MIN_STRING_LENGTH)
+            .unwrap_or(bytes.len());
+        let words = match width {
+            Some(1) => false,
+            Some(_) => start == 0 && (end - start).is_multiple_of(2),
+            None => {
+                (address.0 as usize + start).is_multiple_of(2) && (end - start).is_multiple_of(2)
+            }
+        };
+        data.push_str(&render_numbers(&bytes[start..end], words));
+        start = end;
+    }
+
+    data
+}
+
+fn render_numbers(bytes: &[u8], words: bool) -> String {
+    let mut data = String::new();
+
+    if words {
+        for line in bytes.chunks(DATA_UNITS_PER_LINE * 2) {
+            let words: Vec<String> = line
+                .chunks(2)
+                .map(|word| format!("0x{:04x}", u16::from_le_bytes([word[0], word[1]])))
+                .collect();
+            data.push_str(&format!("dw {}\n", words.join(", ")));
+        }
+    } else {
+        for line in bytes.chunks(DATA_UNITS_PER_LINE) {
+            let bytes: Vec<String> = line.iter().map(|byte| format!("0x{byte:02x}")).collect();
+            data.push_str(&format!("db {}\n", bytes.join(", ")));
+        }
+    }
+
+    data
+}
+
+/// Length of the run of characters at the start of `bytes` that can go in a NASM string.
+fn printable_run(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .take_while(|&&byte| (0x20..0x7f).contains(&byte) && byte != b'"')
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Layout};
+    use crate::format::Palette;
+    use crate::functions::discover;
+    use crate::image::Image;
+    use crate::listing::{render, render_data};
+    use crate::memory::Address;
+    use crate::symbols::Symbols;
+    use crate::xref::Xrefs;
+
+    #[test]
+    fn test_render() {
+        let mut bin = vec![
+            0xA0, 0x08, 0x00, // mov al, [0x08]
+            0xA1, 0x0E, 0x00, // mov ax, [0x0E]
+            0xEB, 0xFE, // jmp $
+        ];
+        bin.extend(b"Hello\0");
+        bin.extend([0x34, 0x12]);
+        let program = trace(&Image::flat(&bin), &[Address(0)], &Layout::default());
+        let symbols = Symbols::new(&program, &discover(&program));
+        let xrefs = Xrefs::collect(&program, &symbols);
+
+        assert_eq!(
+            render(&program, &symbols, &xrefs, Palette::Plain),
+            "bits 16\n\
+             \n\
+             sub_0000:\n\
+             mov al, [byte_0008]\n\
+             mov ax, [word_000E]\n\
+             .loc_0006:\n\
+             ; xref: sub_0000+0x6 (x)\n\
+             jmp short .loc_0006\n\
+             ; sub_0000 endp\n\
+             byte_0008:\n\
+             ; xref: sub_0000 (r)\n\
+             db \"Hello\", 0\n\
+             word_000E:\n\
+             ; xref: sub_0000+0x3 (r)\n\
+             dw 0x1234\n"
+        );
+    }
+
+    #[test]
+    fn test_render_data() {
+        // Runs shorter than a string stay numbers, and words need an even start.
+        assert_eq!(
+            render_data(Address(1), b"ab\x01\x02", None),
+            "db 0x61, 0x62, 0x01, 0x02\n"
+        );
+        assert_eq!(
+            render_data(Address(2), &[1, 0, 2, 0], None),
+            "dw 0x0001, 0x0002\n"
+        );
+        assert_eq!(
+            render_data(Address(0), b"\x07text here\x00\x01", None),
+            "db 0x07\ndb \"text here\", 0\ndb 0x01\n"
+        );
+        // Words are read as words even where they look like text.
+        assert_eq!(
+            render_data(Address(0), b"ABCD", Some(2)),
+            "dw 0x4241, 0x4443\n"
+        );
+        assert_eq!(render_data(Address(0), b"ABCD", Some(1)), "db \"ABCD\"\n");
+    }
+}
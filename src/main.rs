@@ -1,44 +1,525 @@
 mod args;
+mod boot;
+mod cfg;
 mod cpu_state;
 mod decode;
+mod diff;
+mod encoding;
 mod error;
+mod explain;
+mod export;
+mod flow;
+mod format;
+mod functions;
+mod hexfile;
+mod html;
+mod idioms;
+mod image;
 mod instruction;
+mod library;
+mod listing;
 mod memory;
+mod memory_map;
+mod model;
+mod mz;
+mod omf;
 mod operand;
+mod project;
 mod register;
+mod rom;
+mod source_map;
+mod symbol_file;
+mod symbols;
+mod xref;
 
-use args::Args;
+use args::{Annotate, Args, CfgFormat, Command, Diff, ImageKind, OutputFormat};
+use cfg::ControlFlowGraph;
 use clap::Parser;
 use cpu_state::CpuState;
-use decode::disassemble;
+use decode::{decode_linear, disassemble};
+use image::Image;
 use memory::Address;
+use model::InstructionRecord;
+use mz::Executable;
+use project::{HexAddress, Project, Range};
+use symbols::Symbols;
+use xref::Xrefs;
 
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::Read;
+use std::path::Path;
 
 fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    match args.command {
+        Some(Command::Annotate(annotate)) => return add_annotations(annotate),
+        Some(Command::Diff(diff)) => return compare(diff),
+        None => {}
+    }
+
+    let path = args
+        .file
+        .as_deref()
+        .expect("FILE is required without a subcommand");
+    let project = Project::load(&Project::path_for(path))?;
+
+    let mut file = File::open(path)?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+
+    let mut imported = Vec::new();
+    for path in &args.symbols {
+        imported.extend(symbol_file::load(path)?);
+    }
+
+    imported.extend(project.symbols());
+
+    let image_kind = args.image_kind(path, &bytes);
+    match image_kind {
+        ImageKind::Obj => return print_module(&args, &omf::Module::parse(&bytes)?),
+        ImageKind::Lib => return print_library(&args, &library::Library::parse(&bytes)?),
+        _ => {}
+    }
+    let executable = match image_kind {
+        ImageKind::Mz => Some(
+            Executable::parse(&bytes)?
+                .ok_or(error::Error::InvalidExecutable("missing MZ signature"))?,
+        ),
+        _ => None,
+    };
+    let rom = match image_kind {
+        ImageKind::Rom => Some(rom::Rom::parse(&bytes, args.rom_segment)?),
+        _ => None,
+    };
+    let memory_map = match image_kind {
+        ImageKind::MemoryMap => Some(memory_map::MemoryMap::load(path)?),
+        _ => None,
+    };
+    let image = match (&rom, &memory_map) {
+        (Some(rom), _) => rom.image(),
+        (_, Some(memory_map)) => memory_map.image.clone(),
+        (None, None) => load_image(image_kind, &bytes, executable.as_ref())?,
+    };
+    let sparse = matches!(image_kind, ImageKind::Hex | ImageKind::Srec);
+    let boot = image_kind == ImageKind::Boot;
+    let boot_fields = if boot {
+        boot::data_fields(boot::boot_sector(&bytes)?)
+    } else {
+        Vec::new()
+    };
+    let floppy = boot::Geometry::of(&bytes).is_floppy();
+    let drive = args.drive.unwrap_or(if floppy { 0x00 } else { 0x80 });
 
-    let mut file = File::open(args.file)?;
+    // Boot code addresses its own data at 0000:7C00, so the sector keeps its offset. A
+    // memory map can span more than a segment, so only the entry's code segment is
+    // disassembled.
+    let window = match &memory_map {
+        _ if boot => Ok(image.relative_to(0)),
+        Some(memory_map) => Ok(image.window(memory_map.entry.0)),
+        None => disassembly_window(&image, executable.as_ref()),
+    };
 
-    if args.disassemble {
-        let reader = BufReader::new(file);
-        let bytes = reader
-            .bytes()
+    let forced = executable.is_some() || rom.is_some() || memory_map.is_some() || sparse || boot;
+    if args.disassemble && (args.traces() || !project.is_empty() || forced) {
+        let window = window?;
+        let mut entry_points = if !args.entry.is_empty() {
+            args.entry.clone()
+        } else if let Some(rom) = &rom {
+            let base = rom.segment as u32 * 16;
+            rom.entry_points()
+                .into_iter()
+                .map(|entry| Address((entry - base) as u16))
+                .collect()
+        } else {
+            vec![Address(window.entry.unwrap_or(window.start()) as u16)]
+        };
+        entry_points.extend(project.entry_points());
+        imported.extend(
+            boot_fields
+                .iter()
+                .map(|(address, field)| symbol_file::ImportedSymbol {
+                    address: *address,
+                    name: field.name.to_string(),
+                    comment: Some(field.description.to_string()),
+                }),
+        );
+        let mut layout = project.layout();
+        layout.data.extend(
+            boot_fields
+                .iter()
+                .map(|(address, field)| *address..Address(address.0 + field.size as u16)),
+        );
+        let program = flow::trace(&window, &entry_points, &layout);
+        let mut symbols = annotate(Some(&program), &imported, &project);
+        add_source_lines(&mut symbols, &args.listings, &window)?;
+        for (address, field) in &boot_fields {
+            if matches!(field.size, 2 | 4) {
+                symbols.set_data_width(*address, field.size);
+            }
+        }
+        if let Some(executable) = &executable {
+            for (location, segment) in executable.fixups_in(executable.header.cs) {
+                symbols.add_fixup(Address(location), segment);
+            }
+        }
+        let xrefs = Xrefs::collect(&program, &symbols);
+        match (args.cfg, args.format) {
+            _ if args.export.is_some() => {
+                let directory = Path::new(args.export.as_deref().expect("checked by the guard"));
+                let name = Path::new(path)
+                    .file_stem()
+                    .map_or("program".into(), |stem| stem.to_string_lossy());
+                let report = export::export(
+                    directory,
+                    &name,
+                    &window,
+                    &program,
+                    &symbols,
+                    &xrefs,
+                    args.verify,
+                )?;
+                print!("{report}");
+            }
+            _ if args.xrefs => print!("{}", xrefs.report(&symbols)),
+            _ if args.explain => print!("{}", explain::render(program.instructions())),
+            (Some(CfgFormat::Dot), _) => {
+                print!("{}", ControlFlowGraph::build(&program, &symbols).to_dot())
+            }
+            (Some(CfgFormat::Json), _) => {
+                println!("{}", ControlFlowGraph::build(&program, &symbols).to_json()?)
+            }
+            (None, OutputFormat::Json) => {
+                let records = model::records(&program);
+                println!("{}", serde_json::to_string_pretty(&records)?)
+            }
+            (None, OutputFormat::Html) => {
+                print!("{}", html::render(path, &program, &symbols, &xrefs))
+            }
+            (None, OutputFormat::Text) => {
+                if let Some(executable) = &executable {
+                    print!("{}", executable.describe());
+                    if executable.header.cs != 0 {
+                        println!("; addresses count from {:04x}:0000", executable.header.cs);
+                    }
+                }
+                if boot {
+                    print!("{}", boot::describe(&bytes, drive));
+                }
+                if let Some(rom) = &rom {
+                    print!("{}", rom.describe());
+                }
+                if let Some(memory_map) = &memory_map {
+                    print!("{}", memory_map.describe());
+                    println!("; addresses count from {:04x}:0000", memory_map.entry.0);
+                }
+                if sparse {
+                    print!("{}", image.describe());
+                    println!("; addresses count from {:04x}:0000", image.segment());
+                }
+                println!(
+                    "{}",
+                    listing::render(&program, &symbols, &xrefs, args.palette())
+                )
+            }
+        }
+    } else if args.disassemble {
+        let mut address_bytes = bytes
+            .iter()
             .enumerate()
-            .filter_map(|(index, result)| match result {
-                Ok(byte) => Some((Address(index as u16), byte)),
-                Err(_) => None,
-            });
-        let disassembly = disassemble(&mut bytes.peekable())?;
-        println!("{}", disassembly);
+            .map(|(index, byte)| (Address(index as u16), *byte));
+        match args.format {
+            _ if args.explain => {
+                let instructions = decode_linear(&mut address_bytes)?;
+                let instructions = instructions
+                    .iter()
+                    .map(|(address, instruction)| (*address, instruction));
+                print!("{}", explain::render(instructions))
+            }
+            OutputFormat::Json => {
+                let records: Vec<InstructionRecord> = decode_linear(&mut address_bytes)?
+                    .iter()
+                    .map(|(address, instruction)| InstructionRecord::new(*address, instruction))
+                    .collect();
+                println!("{}", serde_json::to_string_pretty(&records)?)
+            }
+            OutputFormat::Html => unreachable!("HTML reports always trace the program"),
+            OutputFormat::Text => println!("{}", disassemble(&mut address_bytes, args.palette())?),
+        }
     } else {
-        let mut bytes = Vec::new();
-        file.read_to_end(&mut bytes)?;
-        let mut cpu_state = CpuState::new(&bytes);
+        let mut symbols = annotate(None, &imported, &project);
+        if let Ok(window) = &window {
+            add_source_lines(&mut symbols, &args.listings, window)?;
+        }
+        let mut cpu_state = match (&executable, image_kind) {
+            (Some(executable), _) => {
+                CpuState::load_executable(executable, args.load_segment, &args.arguments, &symbols)?
+            }
+            (None, ImageKind::Com) => {
+                CpuState::load_com(&bytes, args.load_segment, &args.arguments, &symbols)?
+            }
+            (None, ImageKind::Boot) => CpuState::load_boot_sector(&bytes, drive, &symbols)?,
+            (None, ImageKind::Rom) => {
+                let rom = rom.as_ref().expect("ROMs are parsed before loading");
+                if rom.checksum() != 0 {
+                    eprintln!("warning: ROM checksum is {:#04x}, not 0", rom.checksum());
+                }
+                CpuState::load_rom(rom, &symbols)?
+            }
+            (None, ImageKind::MemoryMap) => {
+                let memory_map = memory_map
+                    .as_ref()
+                    .expect("memory maps are read before loading");
+                CpuState::load_memory_map(memory_map, &symbols)?
+            }
+            (None, _) => CpuState::new(&image, &symbols)?,
+        };
         cpu_state.exec()?;
         cpu_state.print_registers();
     }
 
     Ok(())
 }
+
+/// The bytes of FILE as they are placed in memory. An executable's load module is placed at
+/// 0 with its entry point as the start address, and a boot sector at 0000:7C00.
+fn load_image(
+    kind: ImageKind,
+    bytes: &[u8],
+    executable: Option<&Executable>,
+) -> anyhow::Result<Image> {
+    let image = match (kind, executable) {
+        (_, Some(executable)) => {
+            let mut image = Image::flat(&executable.image);
+            image.entry = Some(executable.entry_point() as u32);
+            image
+        }
+        (ImageKind::Hex, None) => hexfile::parse_intel(std::str::from_utf8(bytes)?)?,
+        (ImageKind::Srec, None) => hexfile::parse_srecord(std::str::from_utf8(bytes)?)?,
+        (ImageKind::Boot, None) => {
+            let mut image = Image::default();
+            image.insert(boot::LOAD_ADDRESS as u32, boot::boot_sector(bytes)?);
+            image.entry = Some(boot::LOAD_ADDRESS as u32);
+            image
+        }
+        (_, None) => Image::flat(bytes),
+    };
+    Ok(image)
+}
+
+/// The image with addresses counted from the segment it starts in, which must leave it
+/// inside one 64 KiB segment. An executable can be larger, so only the segment of its
+/// entry point is disassembled.
+fn disassembly_window(image: &Image, executable: Option<&Executable>) -> anyhow::Result<Image> {
+    if let Some(executable) = executable {
+        return Ok(image.window(executable.header.cs));
+    }
+    let window = image.relative_to(image.segment());
+    if window.end() > 0x10000 {
+        return Err(error::Error::ImageTooLarge(window.end() as usize).into());
+    }
+    Ok(window)
+}
+
+/// Lists every segment of an object module, with its public names as labels and relocated
+/// operands named after their targets.
+fn print_module(args: &Args, module: &omf::Module) -> anyhow::Result<()> {
+    if args.simulate {
+        return Err(error::Error::UnsupportedForObject("Simulation").into());
+    }
+    if args.cfg.is_some() || args.format != OutputFormat::Text {
+        return Err(error::Error::UnsupportedForObject("Output other than a listing").into());
+    }
+
+    print!("{}", module.describe());
+    for (index, segment) in module.segments.iter().enumerate() {
+        println!(
+            "\nsegment {} class={} ; {:#x} bytes",
+            segment.name,
+            segment.class,
+            segment.data.len()
+        );
+        if !segment.initialized {
+            continue;
+        }
+
+        let (program, symbols) = trace_segment(module, index);
+        let xrefs = Xrefs::collect(&program, &symbols);
+        if args.xrefs {
+            print!("{}", xrefs.report(&symbols));
+        } else if args.explain {
+            print!("{}", explain::render(program.instructions()));
+        } else {
+            println!(
+                "{}",
+                listing::render(&program, &symbols, &xrefs, args.palette())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Lists the modules of a library, then disassembles all of them or the one asked for.
+fn print_library(args: &Args, library: &library::Library) -> anyhow::Result<()> {
+    print!("{}", library.describe());
+    if args.list_modules {
+        return Ok(());
+    }
+
+    let members: Vec<&library::Member> = match &args.module {
+        Some(name) => vec![library
+            .find(name)
+            .ok_or_else(|| error::Error::UnknownModule(name.clone()))?],
+        None => library.modules.iter().collect(),
+    };
+    for member in members {
+        println!("\n; page {:#06x}", member.page);
+        print_module(args, &member.module)?;
+    }
+
+    Ok(())
+}
+
+/// Traces a segment of an object module from its public names, or as data if it does not
+/// hold code.
+fn trace_segment(module: &omf::Module, index: usize) -> (flow::Program, Symbols) {
+    let segment = &module.segments[index];
+    let publics: Vec<symbol_file::ImportedSymbol> = module
+        .publics
+        .iter()
+        .filter(|public| public.segment == Some(index))
+        .map(|public| symbol_file::ImportedSymbol {
+            address: Address(public.offset as u16),
+            name: public.name.clone(),
+            comment: None,
+        })
+        .collect();
+
+    let mut layout = flow::Layout {
+        linked: omf::linked_fields(module, index),
+        ..flow::Layout::default()
+    };
+    let mut entry_points = Vec::new();
+    if segment.is_code() {
+        entry_points.extend(publics.iter().map(|public| public.address));
+        if entry_points.is_empty() {
+            entry_points.push(Address(0));
+        }
+    } else {
+        let end = segment.data.len().min(0xFFFF) as u16;
+        layout.data.push(Address(0)..Address(end));
+    }
+
+    let program = flow::trace(&Image::flat(&segment.data), &entry_points, &layout);
+    let mut symbols = annotate(Some(&program), &publics, &Project::default());
+    omf::name_operands(module, index, &program, &mut symbols);
+    (program, symbols)
+}
+
+/// Shows the source lines of assembler listings with the code they assembled to in `window`.
+fn add_source_lines(
+    symbols: &mut Symbols,
+    listings: &[String],
+    window: &Image,
+) -> anyhow::Result<()> {
+    for path in listings {
+        for (address, lines) in source_map::SourceMap::load(path)?.place(window) {
+            symbols.add_source(address, lines);
+        }
+    }
+    Ok(())
+}
+
+/// Names functions and data, then applies imported symbols and the project's annotations.
+fn annotate(
+    program: Option<&flow::Program>,
+    imported: &[symbol_file::ImportedSymbol],
+    project: &Project,
+) -> Symbols {
+    let mut symbols = match program {
+        Some(program) => Symbols::new(program, &functions::discover(program)),
+        None => Symbols::default(),
+    };
+    symbols.import(program, imported);
+    for (address, comment) in project.comments() {
+        symbols.add_comment(address, comment);
+    }
+    for (address, display) in project.operands() {
+        symbols.set_operand_display(program, address, display);
+    }
+
+    symbols
+}
+
+fn add_annotations(annotate: Annotate) -> anyhow::Result<()> {
+    let path = Project::path_for(&annotate.file);
+    let mut project = Project::load(&path)?;
+
+    for (address, name) in annotate.names {
+        project.names.insert(HexAddress(address), name);
+    }
+    for (address, comment) in annotate.comments {
+        project.comments.insert(HexAddress(address), comment);
+    }
+    for (start, end) in annotate.code {
+        project.code.push(Range {
+            start: HexAddress(start),
+            end: HexAddress(end),
+        });
+    }
+    for (start, end) in annotate.data {
+        project.data.push(Range {
+            start: HexAddress(start),
+            end: HexAddress(end),
+        });
+    }
+    for address in annotate.entry {
+        if !project.entry_points.contains(&HexAddress(address)) {
+            project.entry_points.push(HexAddress(address));
+        }
+    }
+    for (address, display) in annotate.operands {
+        project.operands.insert(HexAddress(address), display);
+    }
+
+    project.save(&path)
+}
+
+/// Prints the differences between the traced code of two binaries, each traced with the
+/// annotations of its own project file.
+fn compare(diff: Diff) -> anyhow::Result<()> {
+    let trace = |path: &str| -> anyhow::Result<(flow::Program, Symbols)> {
+        let project = Project::load(&Project::path_for(path))?;
+        let bytes = std::fs::read(path)?;
+        let executable = Executable::parse(&bytes)?;
+        let image = load_image(ImageKind::detect(path, &bytes), &bytes, executable.as_ref())?;
+        let window = disassembly_window(&image, executable.as_ref())?;
+        let mut entry_points = diff.entry.clone();
+        if entry_points.is_empty() {
+            entry_points.push(Address(window.entry.unwrap_or(window.start()) as u16));
+        }
+        entry_points.extend(project.entry_points());
+        let program = flow::trace(&window, &entry_points, &project.layout());
+        let mut symbols = annotate(Some(&program), &project.symbols(), &project);
+        if let Some(executable) = &executable {
+            for (location, segment) in executable.fixups_in(executable.header.cs) {
+                symbols.add_fixup(Address(location), segment);
+            }
+        }
+        Ok((program, symbols))
+    };
+
+    let (old_program, old_symbols) = trace(&diff.old)?;
+    let (new_program, new_symbols) = trace(&diff.new)?;
+    let old = diff::Side {
+        program: &old_program,
+        symbols: &old_symbols,
+    };
+    let new = diff::Side {
+        program: &new_program,
+        symbols: &new_symbols,
+    };
+    print!("{}", diff::diff(&old, &new));
+
+    Ok(())
+}
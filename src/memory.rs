@@ -1,8 +1,10 @@
+use crate::format::{FormatContext, Plain, Style};
 use crate::register::Register;
+use serde::Serialize;
 use std::fmt::Formatter;
 
 #[repr(transparent)]
-#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, PartialOrd, Ord, Serialize)]
 pub struct Address(pub u16);
 
 pub struct Memory {
@@ -10,15 +12,34 @@ pub struct Memory {
     pub registers: [Option<Register>; 2],
 }
 
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
 pub enum Displacement {
     Disp8(u8),
     Disp16(u16),
+    #[default]
     None,
 }
 
 impl std::fmt::Display for Memory {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "[")?;
+        write!(f, "{}", self.format(&Plain))
+    }
+}
+
+impl Memory {
+    pub fn format(&self, context: &dyn FormatContext) -> String {
+        context
+            .palette()
+            .paint(Style::Memory, &self.format_unstyled(context))
+    }
+
+    fn format_unstyled(&self, context: &dyn FormatContext) -> String {
+        if let (Displacement::Disp16(disp), [None, None]) = (&self.displacement, &self.registers) {
+            return match context.memory_name(Address(*disp)) {
+                Some(name) => format!("[{name}]"),
+                None => format!("[{disp}]"),
+            };
+        }
 
         let mut s = String::new();
         let mut flag = false;
@@ -46,22 +67,33 @@ impl std::fmt::Display for Memory {
                 }
                 s.push_str(&(disp as i32).abs().to_string());
             }
-            Displacement::Disp16(disp) => {
-                let disp = disp as i16;
-                if flag {
-                    if disp >= 0 {
-                        s.push_str(" + ");
-                    } else {
-                        s.push_str(" - ");
+            Displacement::Disp16(disp) => match context.displacement(disp) {
+                Some(name) if flag => {
+                    s.push_str(" + ");
+                    s.push_str(&name);
+                }
+                _ => {
+                    let disp = disp as i16;
+                    if flag {
+                        if disp >= 0 {
+                            s.push_str(" + ");
+                        } else {
+                            s.push_str(" - ");
+                        }
                     }
+                    s.push_str(&(disp as i32).abs().to_string());
                 }
-                s.push_str(&(disp as i32).abs().to_string());
-            }
+            },
             Displacement::None => {}
         }
 
-        write!(f, "{}", s)?;
+        // NASM picks the shortest displacement, so a longer one has to be asked for.
+        let size = match self.displacement {
+            Displacement::Disp8(0) if self.registers != [Some(Register::Bp), None] => "byte ",
+            Displacement::Disp16(disp) if (disp as i16) == (disp as i8) as i16 => "word ",
+            _ => "",
+        };
 
-        write!(f, "]")
+        format!("[{size}{s}]")
     }
 }
@@ -0,0 +1,266 @@
+use crate::error::Error;
+use crate::image::Image;
+use serde::{Deserialize, Deserializer};
+use std::fmt::Write;
+use std::ops::Range;
+use std::path::Path;
+
+/// Linear address written as hexadecimal text, either plain (`"0xf0000"`) or as
+/// `"segment:offset"`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Linear(u32);
+
+/// Linear address just past a range, written like [`Linear`], which may be the end of
+/// memory at `0x100000`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct End(u32);
+
+/// `segment:offset` written as hexadecimal text, like `"f000:fff0"`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Far(u16, u16);
+
+impl<'de> Deserialize<'de> for Linear {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_linear(&text)
+            .filter(|&address| address < 0x10_0000)
+            .map(Linear)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid address {text:?}")))
+    }
+}
+
+impl<'de> Deserialize<'de> for End {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_linear(&text)
+            .filter(|&address| address <= 0x10_0000)
+            .map(End)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid end address {text:?}")))
+    }
+}
+
+impl<'de> Deserialize<'de> for Far {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_far(&text).ok_or_else(|| {
+            serde::de::Error::custom(format!("expected segment:offset, got {text:?}"))
+        })
+    }
+}
+
+fn parse_linear(text: &str) -> Option<u32> {
+    match parse_far(text) {
+        Some(Far(segment, offset)) => Some(segment as u32 * 16 + offset as u32),
+        None => {
+            let digits = text.trim_start_matches("0x").trim_start_matches("0X");
+            u32::from_str_radix(digits, 16).ok()
+        }
+    }
+}
+
+fn parse_far(text: &str) -> Option<Far> {
+    let (segment, offset) = text.split_once(':')?;
+    let segment = u16::from_str_radix(segment, 16).ok()?;
+    let offset = u16::from_str_radix(offset, 16).ok()?;
+    Some(Far(segment, offset))
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    regions: Vec<RegionConfig>,
+    #[serde(default)]
+    mmio: Vec<RangeConfig>,
+    entry: Option<Far>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RegionConfig {
+    /// Path of the blob, relative to the memory map file.
+    file: String,
+    address: Linear,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RangeConfig {
+    start: Linear,
+    end: End,
+}
+
+/// Blob of a memory map, where it is loaded and whether programs may write to it.
+pub struct Region {
+    pub file: String,
+    pub range: Range<u32>,
+    pub read_only: bool,
+}
+
+/// Several files placed at their own addresses, with holes left for memory-mapped devices,
+/// read from a JSON file like:
+///
+/// ```json
+/// {
+///   "regions": [
+///     { "file": "bios.rom", "address": "f000:0000", "read_only": true },
+///     { "file": "ram.bin", "address": "0x00500" }
+///   ],
+///   "mmio": [{ "start": "0xa0000", "end": "0xc0000" }],
+///   "entry": "f000:fff0"
+/// }
+/// ```
+pub struct MemoryMap {
+    pub image: Image,
+    pub regions: Vec<Region>,
+    /// Device memory, which reads as `FF` and ignores writes.
+    pub mmio: Vec<Range<u32>>,
+    /// `cs:ip` to start at, by default the start of the first region.
+    pub entry: (u16, u16),
+}
+
+impl MemoryMap {
+    /// Reads the memory map at `path` and the files it lists.
+    pub fn load(path: &str) -> anyhow::Result<MemoryMap> {
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        let text = std::fs::read_to_string(path)?;
+        MemoryMap::parse(&text, |file| Ok(std::fs::read(directory.join(file))?))
+    }
+
+    /// Parses a memory map, reading the contents of each region with `read`.
+    pub fn parse(
+        text: &str,
+        mut read: impl FnMut(&str) -> anyhow::Result<Vec<u8>>,
+    ) -> anyhow::Result<MemoryMap> {
+        let config: Config = serde_json::from_str(text)?;
+
+        let mut image = Image::default();
+        let mut regions: Vec<Region> = Vec::new();
+        for region in config.regions {
+            let bytes = read(&region.file)?;
+            let start = region.address.0;
+            let range = start..start + bytes.len() as u32;
+            if range.end > 0x10_0000 {
+                return Err(Error::InvalidMemoryMap(format!(
+                    "{} extends past the end of memory",
+                    region.file
+                ))
+                .into());
+            }
+            if let Some(other) = regions.iter().find(|other| overlaps(&other.range, &range)) {
+                return Err(Error::InvalidMemoryMap(format!(
+                    "{} overlaps {}",
+                    region.file, other.file
+                ))
+                .into());
+            }
+            image.insert(start, &bytes);
+            regions.push(Region {
+                file: region.file,
+                range,
+                read_only: region.read_only,
+            });
+        }
+
+        let mmio: Vec<Range<u32>> = config
+            .mmio
+            .iter()
+            .map(|range| range.start.0..range.end.0)
+            .collect();
+        for hole in &mmio {
+            if let Some(region) = regions.iter().find(|region| overlaps(&region.range, hole)) {
+                return Err(Error::InvalidMemoryMap(format!(
+                    "MMIO at {:#07x} overlaps {}",
+                    hole.start, region.file
+                ))
+                .into());
+            }
+        }
+
+        let entry = match config.entry {
+            Some(Far(segment, offset)) => (segment, offset),
+            None => {
+                let start = regions.first().map_or(0, |region| region.range.start);
+                ((start >> 4) as u16, (start & 0xF) as u16)
+            }
+        };
+        image.entry = Some(entry.0 as u32 * 16 + entry.1 as u32);
+
+        Ok(MemoryMap {
+            image,
+            regions,
+            mmio,
+            entry,
+        })
+    }
+
+    /// Regions, MMIO holes and the entry point as assembly comments, for the top of a listing.
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        for region in &self.regions {
+            let _ = writeln!(
+                text,
+                "; {:#07x}..{:#07x}: {}{}",
+                region.range.start,
+                region.range.end,
+                region.file,
+                if region.read_only { " (read-only)" } else { "" }
+            );
+        }
+        for hole in &self.mmio {
+            let _ = writeln!(text, "; {:#07x}..{:#07x}: MMIO", hole.start, hole.end);
+        }
+        let _ = writeln!(text, "; entry {:04x}:{:04x}", self.entry.0, self.entry.1);
+        text
+    }
+}
+
+fn overlaps(a: &Range<u32>, b: &Range<u32>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory_map::MemoryMap;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let text = r#"{
+            "regions": [
+                { "file": "rom.bin", "address": "f000:fff0", "read_only": true },
+                { "file": "ram.bin", "address": "0x500" }
+            ],
+            "mmio": [{ "start": "b800:0000", "end": "0xc0000" }],
+            "entry": "f000:fff0"
+        }"#;
+        let read = |file: &str| match file {
+            "rom.bin" => Ok(vec![0xEA, 0x00, 0x05, 0x00, 0x00]),
+            _ => Ok(vec![0x90; 4]),
+        };
+        let map = MemoryMap::parse(text, read)?;
+        let regions: Vec<(u32, &[u8])> = map.image.regions().collect();
+        assert_eq!(regions[0], (0x500, &[0x90; 4][..]));
+        assert_eq!(regions[1].0, 0xFFFF0);
+        assert!(map.regions[0].read_only && !map.regions[1].read_only);
+        assert_eq!(map.mmio, vec![0xB8000..0xC0000]);
+        assert_eq!(map.entry, (0xF000, 0xFFF0));
+        assert_eq!(map.image.entry, Some(0xFFFF0));
+
+        let overlapping = text.replace("0x500", "ffff:0000");
+        assert!(MemoryMap::parse(&overlapping, read).is_err());
+
+        // Ranges may end at the end of memory, but nothing starts there.
+        let top = r#"{
+            "regions": [{ "file": "ram.bin", "address": "0x500" }],
+            "mmio": [{ "start": "0xc0000", "end": "0x100000" }]
+        }"#;
+        assert_eq!(MemoryMap::parse(top, read)?.mmio, vec![0xC0000..0x10_0000]);
+        let past_end = top.replace("0x100000", "0x100001");
+        assert!(MemoryMap::parse(&past_end, read).is_err());
+        let start_at_end = top.replace("0x500", "0x100000");
+        assert!(MemoryMap::parse(&start_at_end, read).is_err());
+
+        Ok(())
+    }
+}
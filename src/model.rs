@@ -0,0 +1,402 @@
+use crate::encoding::{Encoding, Immediate};
+use crate::flow::{Item, Program};
+use crate::instruction::Instruction;
+use crate::memory::{Address, Displacement};
+use crate::operand::Operand;
+use crate::register::Register;
+use serde::Serialize;
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperandKind {
+    Register,
+    Memory,
+    Immediate,
+    Relative,
+    /// Far `segment:offset` pointer, with the offset in `immediate`.
+    Pointer,
+}
+
+/// How far a jump or call reaches.
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Distance {
+    /// Within -128..127 bytes of the next instruction.
+    Short,
+    /// Anywhere in the current code segment.
+    Near,
+    /// To another code segment.
+    Far,
+}
+
+#[derive(Serialize)]
+pub struct OperandRecord {
+    pub kind: OperandKind,
+    pub register: Option<String>,
+    pub base: Option<String>,
+    pub index: Option<String>,
+    pub displacement: Option<i32>,
+    pub immediate: Option<u16>,
+    pub segment: Option<u16>,
+    /// Operand width in bytes.
+    pub size: Option<u8>,
+}
+
+/// Serializable view of a decoded instruction, or of a data run in traced output.
+#[derive(Serialize)]
+pub struct InstructionRecord {
+    pub address: Address,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub operands: Vec<OperandRecord>,
+    pub prefixes: Vec<String>,
+    pub branch_target: Option<Address>,
+    pub distance: Option<Distance>,
+    pub length: u16,
+    pub encoding: Option<EncodingRecord>,
+}
+
+/// Fields the instruction's bytes split into.
+#[derive(Serialize)]
+pub struct EncodingRecord {
+    pub table_row: Option<&'static str>,
+    pub d: Option<u8>,
+    pub w: Option<u8>,
+    pub s: Option<u8>,
+    #[serde(rename = "mod")]
+    pub mode: Option<u8>,
+    pub reg: Option<u8>,
+    pub rm: Option<u8>,
+    /// Displacement width in bytes, 0 when the memory operand has none.
+    pub displacement_size: u8,
+    /// Immediate width in bytes as encoded, before any sign extension.
+    pub immediate_size: u8,
+    pub sign_extended: bool,
+}
+
+impl EncodingRecord {
+    fn new(encoding: &Encoding) -> EncodingRecord {
+        EncodingRecord {
+            table_row: encoding.table_row(),
+            d: encoding.d,
+            w: encoding.w,
+            s: encoding.s,
+            mode: encoding.mod_rm.map(|mod_rm| mod_rm.mode),
+            reg: encoding.mod_rm.map(|mod_rm| mod_rm.reg),
+            rm: encoding.mod_rm.map(|mod_rm| mod_rm.rm),
+            displacement_size: match encoding.displacement {
+                Displacement::Disp8(_) => 1,
+                Displacement::Disp16(_) => 2,
+                Displacement::None => 0,
+            },
+            immediate_size: match encoding.immediate {
+                Some(Immediate::Byte(_) | Immediate::SignExtended(_)) => 1,
+                Some(Immediate::Word(_)) => 2,
+                None => 0,
+            },
+            sign_extended: matches!(encoding.immediate, Some(Immediate::SignExtended(_))),
+        }
+    }
+}
+
+impl InstructionRecord {
+    pub fn new(address: Address, instruction: &Instruction) -> InstructionRecord {
+        let encoding = instruction.encoding();
+
+        let operands = match instruction.to_jump() {
+            Some(jmp) => vec![OperandRecord {
+                kind: OperandKind::Relative,
+                register: None,
+                base: None,
+                index: None,
+                displacement: Some(jmp.ip_increment() as i32),
+                immediate: None,
+                segment: None,
+                size: Some(jmp.len() as u8 - 1),
+            }],
+            None => OperandRecord::all(instruction),
+        };
+
+        InstructionRecord {
+            address,
+            bytes: encoding.bytes.clone(),
+            mnemonic: instruction.mnemonic(),
+            operands,
+            prefixes: encoding
+                .prefixes
+                .iter()
+                .map(|prefix| format!("{prefix:02x}"))
+                .collect(),
+            branch_target: instruction.branch_target(address),
+            distance: distance(instruction),
+            length: encoding.len() as u16,
+            encoding: Some(EncodingRecord::new(encoding)),
+        }
+    }
+
+    pub fn data(address: Address, bytes: &[u8]) -> InstructionRecord {
+        InstructionRecord {
+            address,
+            bytes: bytes.to_vec(),
+            mnemonic: "db".to_string(),
+            operands: Vec::new(),
+            prefixes: Vec::new(),
+            branch_target: None,
+            distance: None,
+            length: bytes.len() as u16,
+            encoding: None,
+        }
+    }
+}
+
+impl OperandRecord {
+    /// Records for the operands of an instruction that is not a relative branch,
+    /// including the ones its variant keeps as plain numbers.
+    fn all(instruction: &Instruction) -> Vec<OperandRecord> {
+        match instruction {
+            Instruction::CallFar {
+                segment, offset, ..
+            }
+            | Instruction::JmpFar {
+                segment, offset, ..
+            } => vec![OperandRecord {
+                kind: OperandKind::Pointer,
+                register: None,
+                base: None,
+                index: None,
+                displacement: None,
+                immediate: Some(*offset),
+                segment: Some(*segment),
+                size: Some(4),
+            }],
+            Instruction::MovSegment {
+                to_segment,
+                segment,
+                operand,
+                ..
+            } => {
+                let segment = OperandRecord {
+                    kind: OperandKind::Register,
+                    register: Some(segment.to_string()),
+                    base: None,
+                    index: None,
+                    displacement: None,
+                    immediate: None,
+                    segment: None,
+                    size: Some(2),
+                };
+                let operand = OperandRecord::new(operand, Some(2));
+                if *to_segment {
+                    vec![segment, operand]
+                } else {
+                    vec![operand, segment]
+                }
+            }
+            Instruction::Int { vector, .. } => {
+                vec![OperandRecord::new(&Operand::Immediate8(*vector), None)]
+            }
+            Instruction::Ret { pop, .. } | Instruction::Retf { pop, .. } => pop
+                .iter()
+                .map(|pop| OperandRecord::new(&Operand::Immediate16(*pop), None))
+                .collect(),
+            _ => {
+                let width = instruction.operand_width();
+                instruction
+                    .operands()
+                    .into_iter()
+                    .map(|operand| OperandRecord::new(operand, width))
+                    .collect()
+            }
+        }
+    }
+
+    fn new(operand: &Operand, instruction_width: Option<u8>) -> OperandRecord {
+        let mut record = OperandRecord {
+            kind: OperandKind::Register,
+            register: None,
+            base: None,
+            index: None,
+            displacement: None,
+            immediate: None,
+            segment: None,
+            size: operand.width().or(instruction_width),
+        };
+
+        match operand {
+            Operand::Register(reg) => record.register = Some(reg.to_string()),
+            Operand::Memory(mem) => {
+                record.kind = OperandKind::Memory;
+                for reg in mem.registers.iter().flatten() {
+                    match reg {
+                        Register::Bx | Register::Bp => record.base = Some(reg.to_string()),
+                        _ => record.index = Some(reg.to_string()),
+                    }
+                }
+                let direct = mem.registers.iter().all(Option::is_none);
+                record.displacement = match mem.displacement {
+                    Displacement::Disp8(disp) => Some(disp as i8 as i32),
+                    Displacement::Disp16(disp) if direct => Some(disp as i32),
+                    Displacement::Disp16(disp) => Some(disp as i16 as i32),
+                    Displacement::None => None,
+                };
+            }
+            Operand::Immediate8(imm) => {
+                record.kind = OperandKind::Immediate;
+                record.immediate = Some(*imm as u16);
+            }
+            Operand::Immediate16(imm) => {
+                record.kind = OperandKind::Immediate;
+                record.immediate = Some(*imm);
+            }
+        }
+
+        record
+    }
+}
+
+/// Distance of a jump or call, which the mnemonic leaves out.
+fn distance(instruction: &Instruction) -> Option<Distance> {
+    match instruction {
+        Instruction::CallFar { .. }
+        | Instruction::JmpFar { .. }
+        | Instruction::CallIndirect { far: true, .. }
+        | Instruction::JmpIndirect { far: true, .. } => Some(Distance::Far),
+        Instruction::CallIndirect { .. } | Instruction::JmpIndirect { .. } => Some(Distance::Near),
+        _ => instruction.to_jump().map(|jmp| match jmp.len() {
+            2 => Distance::Short,
+            _ => Distance::Near,
+        }),
+    }
+}
+
+pub fn records(program: &Program) -> Vec<InstructionRecord> {
+    program
+        .items
+        .iter()
+        .map(|(address, item)| match item {
+            Item::Instruction(instruction) => InstructionRecord::new(*address, instruction),
+            Item::Data(data) => InstructionRecord::data(*address, data),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::decode::decode_at;
+    use crate::memory::Address;
+    use crate::model::InstructionRecord;
+    use serde_json::{json, Value};
+
+    /// The record of the instruction `bytes` start with.
+    fn decode_record(bytes: &[u8]) -> anyhow::Result<Value> {
+        let instruction = decode_at(bytes, Address(0))?;
+        Ok(serde_json::to_value(InstructionRecord::new(
+            Address(0),
+            &instruction,
+        ))?)
+    }
+
+    #[test]
+    fn test_records() -> anyhow::Result<()> {
+        let bin = [
+            0x8B, 0x46, 0xFC, // mov ax, [bp - 4]
+            0x83, 0xC3, 0xFE, // add bx, -2 sign-extended from a byte
+            0xEB, 0xFE, // jmp $
+        ];
+
+        let mov = decode_at(&bin, Address(0))?;
+        let record = serde_json::to_value(InstructionRecord::new(Address(0), &mov))?;
+        assert_eq!(
+            record,
+            json!({
+                "address": 0,
+                "bytes": [0x8B, 0x46, 0xFC],
+                "mnemonic": "mov",
+                "operands": [
+                    {"kind": "register", "register": "ax", "base": null, "index": null,
+                     "displacement": null, "immediate": null, "segment": null, "size": 2},
+                    {"kind": "memory", "register": null, "base": "bp", "index": null,
+                     "displacement": -4, "immediate": null, "segment": null, "size": 2},
+                ],
+                "prefixes": [],
+                "branch_target": null,
+                "distance": null,
+                "length": 3,
+                "encoding": {
+                    "table_row": "MOV register/memory to/from register",
+                    "d": 1, "w": 1, "s": null, "mod": 1, "reg": 0, "rm": 6,
+                    "displacement_size": 1, "immediate_size": 0, "sign_extended": false,
+                },
+            })
+        );
+
+        let add = decode_at(&bin, Address(3))?;
+        let record = serde_json::to_value(InstructionRecord::new(Address(3), &add))?;
+        assert_eq!(record["operands"][1]["immediate"], 0xFFFE);
+        assert_eq!(record["encoding"]["immediate_size"], 1);
+        assert_eq!(record["encoding"]["sign_extended"], true);
+
+        // jmp $ is relative to the next instruction and lands on itself
+        let jmp = decode_at(&bin, Address(6))?;
+        let record = serde_json::to_value(InstructionRecord::new(Address(6), &jmp))?;
+        assert_eq!(record["operands"][0]["kind"], "relative");
+        assert_eq!(record["operands"][0]["displacement"], -2);
+        assert_eq!(record["branch_target"], 6);
+        assert_eq!(record["mnemonic"], "jmp");
+        assert_eq!(record["distance"], "short");
+        let jmp = decode_record(&[0xE9, 0x00, 0x00])?;
+        assert_eq!(
+            (&jmp["mnemonic"], &jmp["distance"]),
+            (&json!("jmp"), &json!("near"))
+        );
+
+        // Operands the instruction keeps as plain numbers rather than as operands.
+        let int = decode_record(&[0xCD, 0x21])?;
+        assert_eq!(
+            int["operands"],
+            json!([{"kind": "immediate", "register": null, "base": null, "index": null,
+                    "displacement": null, "immediate": 0x21, "segment": null, "size": 1}])
+        );
+        let call = decode_record(&[0x9A, 0x78, 0x56, 0x34, 0x12])?;
+        assert_eq!(
+            (&call["mnemonic"], &call["distance"]),
+            (&json!("call"), &json!("far"))
+        );
+        assert_eq!(
+            call["operands"],
+            json!([{"kind": "pointer", "register": null, "base": null, "index": null,
+                    "displacement": null, "immediate": 0x5678, "segment": 0x1234, "size": 4}])
+        );
+        let ret = decode_record(&[0xC2, 0x04, 0x00])?;
+        assert_eq!(ret["operands"][0]["immediate"], 4);
+        assert_eq!(ret["operands"][0]["size"], 2);
+        assert_eq!(decode_record(&[0xC3])?["operands"], json!([]));
+
+        // The shift count is an operand of its own, cl or the implied 1
+        let shl = decode_record(&[0xD3, 0xE0])?;
+        assert_eq!(shl["mnemonic"], "shl");
+        assert_eq!(shl["operands"][0]["register"], "ax");
+        assert_eq!(shl["operands"][1]["register"], "cl");
+        assert_eq!(shl["operands"][1]["size"], 1);
+        let shl = decode_record(&[0xD1, 0xE0])?;
+        assert_eq!(shl["operands"][1]["kind"], "immediate");
+        assert_eq!(shl["operands"][1]["immediate"], 1);
+        assert_eq!(shl["encoding"]["immediate_size"], 0);
+
+        let movs = decode_record(&[0xF3, 0xA5])?;
+        assert_eq!(movs["mnemonic"], "movsw");
+        assert_eq!(movs["prefixes"], json!(["f3"]));
+        assert_eq!(movs["operands"], json!([]));
+
+        let idiv = decode_record(&[0xF7, 0xF9])?;
+        assert_eq!(idiv["mnemonic"], "idiv");
+        assert_eq!(idiv["operands"][0]["register"], "cx");
+        assert_eq!(idiv["operands"][0]["size"], 2);
+
+        let data = serde_json::to_value(InstructionRecord::data(Address(2), &[1, 2]))?;
+        assert_eq!(data["mnemonic"], "db");
+        assert_eq!(data["encoding"], serde_json::Value::Null);
+
+        Ok(())
+    }
+}
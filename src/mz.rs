@@ -0,0 +1,216 @@
+use crate::error::Error;
+use std::fmt::Write;
+
+const PAGE_SIZE: usize = 512;
+const PARAGRAPH_SIZE: usize = 16;
+const HEADER_SIZE: usize = 0x1C;
+
+/// Fixed part of the MZ header, in the units the file stores them in.
+#[derive(Debug)]
+pub struct Header {
+    /// Size of the header, relocation table included, in 16-byte paragraphs.
+    pub header_paragraphs: u16,
+    /// Paragraphs the program needs past the end of the load module.
+    pub min_alloc: u16,
+    /// Paragraphs the program would like past the end of the load module.
+    pub max_alloc: u16,
+    /// Initial `ss`, relative to the load segment.
+    pub ss: u16,
+    pub sp: u16,
+    pub checksum: u16,
+    pub ip: u16,
+    /// Initial `cs`, relative to the load segment.
+    pub cs: u16,
+    pub overlay_number: u16,
+}
+
+/// Location of a segment word the loader adds the load segment to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Relocation {
+    pub offset: u16,
+    /// Segment of the word, relative to the load segment.
+    pub segment: u16,
+}
+
+impl Relocation {
+    /// Offset of the word from the start of the load module.
+    pub fn location(&self) -> usize {
+        self.segment as usize * PARAGRAPH_SIZE + self.offset as usize
+    }
+}
+
+/// DOS `.EXE` file split into its header, relocations and load module.
+pub struct Executable {
+    pub header: Header,
+    pub relocations: Vec<Relocation>,
+    /// Bytes DOS loads into memory: the file after the header, up to the size the header gives.
+    pub image: Vec<u8>,
+    /// Bytes past the end of the load module, which DOS does not load.
+    pub overlay: Vec<u8>,
+}
+
+impl Executable {
+    /// Parses `bytes` as an MZ executable, or returns `None` if they do not start with the
+    /// `MZ` signature.
+    pub fn parse(bytes: &[u8]) -> Result<Option<Executable>, Error> {
+        if !(bytes.starts_with(b"MZ") || bytes.starts_with(b"ZM")) {
+            return Ok(None);
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::InvalidExecutable("truncated header"));
+        }
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+
+        let last_page_bytes = word(0x02) as usize;
+        let pages = word(0x04) as usize;
+        let relocation_count = word(0x06) as usize;
+        let header = Header {
+            header_paragraphs: word(0x08),
+            min_alloc: word(0x0A),
+            max_alloc: word(0x0C),
+            ss: word(0x0E),
+            sp: word(0x10),
+            checksum: word(0x12),
+            ip: word(0x14),
+            cs: word(0x16),
+            overlay_number: word(0x1A),
+        };
+        let relocation_table = word(0x18) as usize;
+
+        let mut file_size = pages * PAGE_SIZE;
+        if last_page_bytes != 0 {
+            file_size = file_size.saturating_sub(PAGE_SIZE - last_page_bytes);
+        }
+        let file_size = file_size.min(bytes.len());
+        let header_size = header.header_paragraphs as usize * PARAGRAPH_SIZE;
+        if header_size > file_size {
+            return Err(Error::InvalidExecutable("header larger than the file"));
+        }
+
+        let table_end = relocation_table + relocation_count * 4;
+        if table_end > bytes.len() {
+            return Err(Error::InvalidExecutable("truncated relocation table"));
+        }
+        let relocations = (relocation_table..table_end)
+            .step_by(4)
+            .map(|entry| Relocation {
+                offset: word(entry),
+                segment: word(entry + 2),
+            })
+            .collect();
+
+        Ok(Some(Executable {
+            header,
+            relocations,
+            image: bytes[header_size..file_size].to_vec(),
+            overlay: bytes[file_size..].to_vec(),
+        }))
+    }
+
+    /// Offset of the initial `cs:ip` from the start of the load module.
+    pub fn entry_point(&self) -> usize {
+        self.header.cs as usize * PARAGRAPH_SIZE + self.header.ip as usize
+    }
+
+    /// Location and unrelocated value of every segment word the loader fixes up.
+    pub fn fixups(&self) -> impl Iterator<Item = (usize, u16)> + '_ {
+        self.relocations.iter().filter_map(|relocation| {
+            let location = relocation.location();
+            let bytes = self.image.get(location..location + 2)?;
+            Some((location, u16::from_le_bytes([bytes[0], bytes[1]])))
+        })
+    }
+
+    /// Fixups inside the 64 KiB of `segment`, by their offset in it.
+    pub fn fixups_in(&self, segment: u16) -> impl Iterator<Item = (u16, u16)> + '_ {
+        let base = segment as usize * PARAGRAPH_SIZE;
+        self.fixups().filter_map(move |(location, value)| {
+            let offset = location.checked_sub(base)?;
+            Some((u16::try_from(offset).ok()?, value))
+        })
+    }
+
+    /// The load module as it is in memory when loaded at `load_segment`.
+    pub fn relocated(&self, load_segment: u16) -> Vec<u8> {
+        let mut image = self.image.clone();
+        for (location, value) in self.fixups().collect::<Vec<_>>() {
+            let value = value.wrapping_add(load_segment).to_le_bytes();
+            image[location..location + 2].copy_from_slice(&value);
+        }
+        image
+    }
+
+    /// Header fields as assembly comments, for the top of a listing.
+    pub fn describe(&self) -> String {
+        let header = &self.header;
+        let mut text = String::new();
+        let _ = writeln!(
+            text,
+            "; MZ executable: {} byte header, {} byte load module, {} relocations, {} byte overlay",
+            header.header_paragraphs as usize * PARAGRAPH_SIZE,
+            self.image.len(),
+            self.relocations.len(),
+            self.overlay.len()
+        );
+        let _ = writeln!(
+            text,
+            "; cs:ip {:04x}:{:04x}, ss:sp {:04x}:{:04x}, minalloc {:#x}, maxalloc {:#x}",
+            header.cs, header.ip, header.ss, header.sp, header.min_alloc, header.max_alloc
+        );
+        let _ = writeln!(
+            text,
+            "; checksum {:#06x}, overlay number {}",
+            header.checksum, header.overlay_number
+        );
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mz::{Executable, Relocation};
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let mut bytes = vec![0; 0x20];
+        bytes[..0x1C].copy_from_slice(&[
+            b'M', b'Z', // signature
+            0x27, 0x00, // 0x27 bytes in the last page
+            0x01, 0x00, // one page
+            0x01, 0x00, // one relocation
+            0x02, 0x00, // 2 paragraph header
+            0x10, 0x00, // minalloc
+            0xFF, 0xFF, // maxalloc
+            0x01, 0x00, // ss
+            0x00, 0x01, // sp
+            0x00, 0x00, // checksum
+            0x02, 0x00, // ip
+            0x00, 0x00, // cs
+            0x1C, 0x00, // relocation table
+            0x00, 0x00, // overlay number
+        ]);
+        bytes[0x1C..0x20].copy_from_slice(&[0x03, 0x00, 0x00, 0x00]);
+        // jmp short $+3, then the entry point: mov ax, 1 with its immediate relocated,
+        // two nops and one byte of overlay
+        bytes.extend([0xEB, 0x01, 0xB8, 0x01, 0x00, 0x90, 0x90, 0xCC]);
+
+        let executable = Executable::parse(&bytes)?.expect("MZ signature");
+        assert_eq!(executable.image.len(), 7);
+        assert_eq!(executable.overlay, vec![0xCC]);
+        assert_eq!(
+            executable.relocations,
+            vec![Relocation {
+                offset: 3,
+                segment: 0
+            }]
+        );
+        assert_eq!(executable.entry_point(), 2);
+        assert_eq!(executable.relocated(0x1000)[3..5], [0x01, 0x10]);
+        assert_eq!(executable.fixups_in(0).collect::<Vec<_>>(), vec![(3, 1)]);
+        assert_eq!(executable.fixups_in(1).count(), 0);
+
+        assert!(Executable::parse(&[0xB8, 0x01, 0x00])?.is_none());
+
+        Ok(())
+    }
+}
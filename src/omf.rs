@@ -0,0 +1,741 @@
+use crate::encoding::Immediate;
+use crate::error::Error;
+use crate::flow::Program;
+use crate::instruction::Instruction;
+use crate::memory::Address;
+use crate::operand::Operand;
+use crate::symbols::{OperandField, Symbols};
+use std::fmt::Write;
+use std::ops::Range;
+
+const THEADR: u8 = 0x80;
+const MODEND: u8 = 0x8A;
+const EXTDEF: u8 = 0x8C;
+const PUBDEF: u8 = 0x90;
+const LNAMES: u8 = 0x96;
+const SEGDEF: u8 = 0x98;
+const GRPDEF: u8 = 0x9A;
+const FIXUPP: u8 = 0x9C;
+const LEDATA: u8 = 0xA0;
+const LIDATA: u8 = 0xA2;
+const COMDEF: u8 = 0xB0;
+const LEXTDEF: u8 = 0xB4;
+const LPUBDEF: u8 = 0xB6;
+
+/// Intel/Microsoft OMF-86 object module with its segments' contents assembled from the
+/// data records.
+#[derive(Debug, Default)]
+pub struct Module {
+    pub name: String,
+    pub segments: Vec<Segment>,
+    pub groups: Vec<Group>,
+    pub externals: Vec<String>,
+    pub publics: Vec<Public>,
+    pub fixups: Vec<Fixup>,
+    /// Start address given by `MODEND`, for the module holding the program's entry point.
+    pub start: Option<(Target, u32)>,
+}
+
+#[derive(Debug)]
+pub struct Segment {
+    pub name: String,
+    pub class: String,
+    pub data: Vec<u8>,
+    /// Whether any data record fills the segment, which uninitialised ones like `_BSS` lack.
+    pub initialized: bool,
+}
+
+#[derive(Debug)]
+pub struct Group {
+    pub name: String,
+    /// Indices into [`Module::segments`].
+    pub segments: Vec<usize>,
+}
+
+#[derive(Debug)]
+pub struct Public {
+    pub name: String,
+    /// Index into [`Module::segments`], or `None` for an absolute symbol.
+    pub segment: Option<usize>,
+    pub offset: u32,
+}
+
+/// What a fixup refers to. Indices are into the module's segments, groups and externals.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Target {
+    Segment(usize),
+    Group(usize),
+    External(usize),
+    Frame(u16),
+}
+
+/// Part of the target address a fixup stores.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Location {
+    LowByte,
+    HighByte,
+    Offset,
+    /// Segment base of the target.
+    Base,
+    /// Offset followed by segment base, as in a far `call`.
+    Pointer,
+}
+
+#[derive(Debug)]
+pub struct Fixup {
+    /// Index into [`Module::segments`] of the segment holding the fixed-up bytes.
+    pub segment: usize,
+    pub offset: u32,
+    pub location: Location,
+    /// Relative to the end of the fixed-up field, as in a near `call`, instead of absolute.
+    pub self_relative: bool,
+    pub target: Target,
+    pub displacement: u32,
+}
+
+/// Target of a `THREAD` subrecord, which later fixups refer to by number.
+#[derive(Copy, Clone)]
+struct Thread {
+    method: u8,
+    datum: u16,
+}
+
+impl Module {
+    /// Parses the first module in `bytes`, which may be followed by others as in a library.
+    pub fn parse(bytes: &[u8]) -> Result<Module, Error> {
+        Ok(Module::parse_at(bytes, 0)?.0)
+    }
+
+    /// Parses the module starting at `position` and returns it with the position just past
+    /// its `MODEND` record.
+    pub fn parse_at(bytes: &[u8], mut position: usize) -> Result<(Module, usize), Error> {
+        let mut module = Module::default();
+        let mut names = Vec::new();
+        let mut target_threads = [None; 4];
+        // Segment and start of the last data record, which fixups are relative to.
+        let mut data_record = None;
+
+        if bytes.get(position) != Some(&THEADR) {
+            return Err(Error::InvalidObject(position, "missing THEADR record"));
+        }
+
+        loop {
+            let start = position;
+            let header = bytes
+                .get(start..start + 3)
+                .ok_or(Error::InvalidObject(start, "truncated record header"))?;
+            let kind = header[0];
+            let length = u16::from_le_bytes([header[1], header[2]]) as usize;
+            let end = start + 3 + length;
+            if length == 0 || end > bytes.len() {
+                return Err(Error::InvalidObject(start, "truncated record"));
+            }
+            let checksum = bytes[end - 1];
+            let sum = bytes[start..end]
+                .iter()
+                .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+            if checksum != 0 && sum != 0 {
+                return Err(Error::InvalidObject(start, "checksum mismatch"));
+            }
+            position = end;
+
+            // Odd record types are the 32-bit forms, with wider offsets.
+            let wide = kind & 1 == 1;
+            let mut record = Reader {
+                bytes: &bytes[start + 3..end - 1],
+                position: 0,
+                record: start,
+                wide,
+            };
+            match kind & !1 {
+                THEADR => module.name = record.name()?,
+                LNAMES => {
+                    while !record.is_empty() {
+                        names.push(record.name()?);
+                    }
+                }
+                SEGDEF => {
+                    let acbp = record.byte()?;
+                    if acbp >> 5 == 0 {
+                        // Absolute segment: frame number and offset.
+                        record.word()?;
+                        record.byte()?;
+                    }
+                    let mut length = record.offset()? as usize;
+                    if acbp & 0x02 != 0 {
+                        length = if wide { 1 << 32 } else { 0x10000 };
+                    }
+                    let name = record.name_index(&names)?;
+                    let class = record.name_index(&names)?;
+                    module.segments.push(Segment {
+                        name,
+                        class,
+                        data: vec![0; length.min(0x10000)],
+                        initialized: false,
+                    });
+                }
+                GRPDEF => {
+                    let name = record.name_index(&names)?;
+                    let mut segments = Vec::new();
+                    while !record.is_empty() {
+                        record.byte()?;
+                        segments.push(record.segment_index(&module)?);
+                    }
+                    module.groups.push(Group { name, segments });
+                }
+                PUBDEF | LPUBDEF => {
+                    record.index()?;
+                    let segment = match record.index()? {
+                        0 => {
+                            record.word()?;
+                            None
+                        }
+                        index => Some(record.resolve_segment(&module, index)?),
+                    };
+                    while !record.is_empty() {
+                        let name = record.name()?;
+                        let offset = record.offset()?;
+                        record.index()?;
+                        module.publics.push(Public {
+                            name,
+                            segment,
+                            offset,
+                        });
+                    }
+                }
+                EXTDEF | LEXTDEF => {
+                    while !record.is_empty() {
+                        module.externals.push(record.name()?);
+                        record.index()?;
+                    }
+                }
+                COMDEF => {
+                    while !record.is_empty() {
+                        module.externals.push(record.name()?);
+                        record.index()?;
+                        let lengths = match record.byte()? {
+                            0x61 => 2,
+                            _ => 1,
+                        };
+                        for _ in 0..lengths {
+                            record.communal_length()?;
+                        }
+                    }
+                }
+                LEDATA | LIDATA => {
+                    let segment = record.segment_index(&module)?;
+                    let offset = record.offset()? as usize;
+                    let data = match kind & !1 {
+                        LEDATA => record.rest().to_vec(),
+                        _ => {
+                            let mut data = Vec::new();
+                            while !record.is_empty() {
+                                data.extend(record.iterated_block()?);
+                            }
+                            data
+                        }
+                    };
+                    let contents = &mut module.segments[segment];
+                    if offset + data.len() > contents.data.len() {
+                        return Err(Error::InvalidObject(
+                            start,
+                            "data past the end of its segment",
+                        ));
+                    }
+                    contents.data[offset..offset + data.len()].copy_from_slice(&data);
+                    contents.initialized = true;
+                    data_record = Some((segment, offset as u32));
+                }
+                FIXUPP => {
+                    while !record.is_empty() {
+                        let first = record.byte()?;
+                        if first & 0x80 == 0 {
+                            let method = (first >> 2) & 0x07;
+                            let datum = match method & 0x03 {
+                                3 => record.word()?,
+                                _ if method < 4 => record.index()?,
+                                _ => 0,
+                            };
+                            if first & 0x40 == 0 {
+                                target_threads[(first & 0x03) as usize] =
+                                    Some(Thread { method, datum });
+                            }
+                            continue;
+                        }
+
+                        let (segment, record_offset) = data_record
+                            .ok_or(Error::InvalidObject(start, "fixup without a data record"))?;
+                        let data_offset = (((first & 0x03) as u32) << 8) | record.byte()? as u32;
+                        let location = match (first >> 2) & 0x0F {
+                            0 => Location::LowByte,
+                            1 | 5 | 9 | 13 => Location::Offset,
+                            2 => Location::Base,
+                            3 | 11 => Location::Pointer,
+                            4 => Location::HighByte,
+                            _ => return Err(Error::InvalidObject(start, "unknown fixup location")),
+                        };
+
+                        let fix_data = record.byte()?;
+                        if fix_data & 0x80 == 0 {
+                            match (fix_data >> 4) & 0x07 {
+                                0..=2 => {
+                                    record.index()?;
+                                }
+                                3 => {
+                                    record.word()?;
+                                }
+                                _ => {}
+                            }
+                        }
+                        let no_displacement = fix_data & 0x04 != 0;
+                        let (method, datum) = match fix_data & 0x08 {
+                            0 => {
+                                let method = fix_data & 0x03;
+                                let datum = match method {
+                                    3 => record.word()?,
+                                    _ => record.index()?,
+                                };
+                                (method, datum)
+                            }
+                            _ => {
+                                let thread = target_threads[(fix_data & 0x03) as usize].ok_or(
+                                    Error::InvalidObject(start, "undefined target thread"),
+                                )?;
+                                (thread.method & 0x03, thread.datum)
+                            }
+                        };
+                        let target = match method {
+                            0 => Target::Segment(record.resolve_segment(&module, datum)?),
+                            1 => Target::Group(resolve(datum, module.groups.len(), start)?),
+                            2 => Target::External(resolve(datum, module.externals.len(), start)?),
+                            _ => Target::Frame(datum),
+                        };
+                        let displacement = if no_displacement { 0 } else { record.offset()? };
+
+                        module.fixups.push(Fixup {
+                            segment,
+                            offset: record_offset + data_offset,
+                            location,
+                            self_relative: first & 0x40 == 0,
+                            target,
+                            displacement,
+                        });
+                    }
+                }
+                MODEND => {
+                    let module_type = record.byte()?;
+                    if module_type & 0x40 != 0 {
+                        let end_data = record.byte()?;
+                        if end_data & 0x80 == 0 && (end_data >> 4) & 0x07 < 4 {
+                            record.index()?;
+                        }
+                        let datum = record.index()?;
+                        let target = match end_data & 0x03 {
+                            0 => Target::Segment(record.resolve_segment(&module, datum)?),
+                            1 => Target::Group(resolve(datum, module.groups.len(), start)?),
+                            2 => Target::External(resolve(datum, module.externals.len(), start)?),
+                            _ => Target::Frame(datum),
+                        };
+                        let displacement = match end_data & 0x04 {
+                            0 => record.offset()?,
+                            _ => 0,
+                        };
+                        module.start = Some((target, displacement));
+                    }
+                    return Ok((module, position));
+                }
+                // Comments, line numbers and the other records do not affect the code.
+                _ => {}
+            }
+        }
+    }
+
+    /// Name of the fixup's target as NASM writes it for the fixup's location: the segment
+    /// base of a symbol is `seg name`, its offset the bare name, plus any displacement.
+    pub fn target_name(&self, fixup: &Fixup) -> String {
+        let stored = self.segments[fixup.segment]
+            .data
+            .get(fixup.offset as usize..fixup.offset as usize + 2)
+            .map_or(0, |word| u16::from_le_bytes([word[0], word[1]]) as u32);
+        // Without a displacement in the record, the assembler leaves it in the bytes.
+        let offset = match fixup.location {
+            Location::Offset | Location::Pointer
+                if fixup.displacement == 0 && !fixup.self_relative =>
+            {
+                stored
+            }
+            _ => fixup.displacement,
+        };
+        self.name_of(fixup.target, fixup.location, offset)
+    }
+
+    /// Name of `offset` bytes into `target`, naming the target's segment base for
+    /// [`Location::Base`].
+    fn name_of(&self, target: Target, location: Location, offset: u32) -> String {
+        match (target, location) {
+            (Target::Group(group), _) => self.groups[group].name.clone(),
+            (Target::Frame(frame), _) => format!("{frame:#06x}"),
+            (Target::Segment(segment), Location::Base) => self.segments[segment].name.clone(),
+            (Target::External(external), Location::Base) => {
+                format!("seg {}", self.externals[external])
+            }
+            (Target::External(external), _) => with_offset(&self.externals[external], offset),
+            (Target::Segment(segment), _) => {
+                let public = self
+                    .publics
+                    .iter()
+                    .find(|public| public.segment == Some(segment) && public.offset == offset);
+                match public {
+                    Some(public) => public.name.clone(),
+                    None => with_offset(&self.segments[segment].name, offset),
+                }
+            }
+        }
+    }
+
+    /// Module header and the symbols it imports, as assembly comments.
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        let _ = writeln!(text, "; OMF module {}", self.name);
+        for group in &self.groups {
+            let segments: Vec<&str> = group
+                .segments
+                .iter()
+                .map(|&segment| self.segments[segment].name.as_str())
+                .collect();
+            let _ = writeln!(text, "; group {}: {}", group.name, segments.join(", "));
+        }
+        if !self.externals.is_empty() {
+            let _ = writeln!(text, "; extern {}", self.externals.join(", "));
+        }
+        if let Some((target, offset)) = self.start {
+            let _ = writeln!(
+                text,
+                "; start address {}",
+                self.name_of(target, Location::Offset, offset)
+            );
+        }
+        text
+    }
+}
+
+impl Segment {
+    /// Whether the segment holds code, judging by its class name, or its name when it has
+    /// no class.
+    pub fn is_code(&self) -> bool {
+        let class = self.class.to_ascii_uppercase();
+        if class.is_empty() {
+            self.name.to_ascii_uppercase().ends_with("TEXT")
+        } else {
+            class.ends_with("CODE")
+        }
+    }
+}
+
+/// Fields of `segment` the linker fills in with an address outside it, such as the
+/// operand of a `call` to an external.
+pub fn linked_fields(module: &Module, segment: usize) -> Vec<Range<Address>> {
+    module
+        .fixups
+        .iter()
+        .filter(|fixup| fixup.segment == segment && fixup.target != Target::Segment(segment))
+        .map(|fixup| {
+            let width = match fixup.location {
+                Location::LowByte | Location::HighByte => 1,
+                Location::Offset | Location::Base => 2,
+                Location::Pointer => 4,
+            };
+            let start = fixup.offset.min(0xFFFF) as u16;
+            Address(start)..Address(start.saturating_add(width))
+        })
+        .collect()
+}
+
+/// Names the relocated operands of the instructions in `segment` after their targets.
+pub fn name_operands(module: &Module, segment: usize, program: &Program, symbols: &mut Symbols) {
+    for fixup in module
+        .fixups
+        .iter()
+        .filter(|fixup| fixup.segment == segment)
+    {
+        let Some((&address, item)) = program
+            .items
+            .range(..=Address(fixup.offset as u16))
+            .next_back()
+        else {
+            continue;
+        };
+        let crate::flow::Item::Instruction(instruction) = item else {
+            continue;
+        };
+        let within = fixup.offset as usize - address.0 as usize;
+        if within >= instruction.get_size() as usize {
+            continue;
+        }
+        if let Some(field) = field_at(instruction, within, fixup.self_relative) {
+            symbols.name_operand(address, field, module.target_name(fixup));
+        }
+    }
+}
+
+/// Field of `instruction` holding the byte `within` bytes from its start, which a
+/// self-relative fixup fills in only for a near branch.
+fn field_at(instruction: &Instruction, within: usize, self_relative: bool) -> Option<OperandField> {
+    match instruction {
+        Instruction::CallFar { .. } | Instruction::JmpFar { .. } => {
+            return Some(OperandField::Pointer)
+        }
+        Instruction::Call { .. } | Instruction::Jmp { .. } if self_relative => {
+            return Some(OperandField::Branch)
+        }
+        _ => {}
+    }
+
+    let size = instruction.get_size() as usize;
+    let operands = instruction.operands();
+    // The encoded immediate, so the implied 1 of `shl r/m, 1` doesn't count
+    let immediate = instruction
+        .encoding()
+        .immediate
+        .map(|immediate| match immediate {
+            Immediate::Byte(_) | Immediate::SignExtended(_) => 1,
+            Immediate::Word(_) => 2,
+        });
+    match immediate {
+        Some(width) if within + width >= size => Some(OperandField::Immediate),
+        _ if operands
+            .iter()
+            .any(|operand| matches!(operand, Operand::Memory(_))) =>
+        {
+            Some(OperandField::Displacement)
+        }
+        _ => None,
+    }
+}
+
+fn with_offset(name: &str, offset: u32) -> String {
+    match offset {
+        0 => name.to_string(),
+        _ => format!("{name}+{offset:#x}"),
+    }
+}
+
+/// Converts a 1-based index into one of the module's tables to a 0-based one.
+fn resolve(index: u16, count: usize, record: usize) -> Result<usize, Error> {
+    match (index as usize).checked_sub(1) {
+        Some(index) if index < count => Ok(index),
+        _ => Err(Error::InvalidObject(record, "index out of range")),
+    }
+}
+
+/// Reads the fields of one record.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Offset of the record in the file, for errors.
+    record: usize,
+    /// Whether offsets are 32-bit.
+    wide: bool,
+}
+
+impl Reader<'_> {
+    fn is_empty(&self) -> bool {
+        self.position >= self.bytes.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&[u8], Error> {
+        let bytes =
+            self.bytes
+                .get(self.position..self.position + count)
+                .ok_or(Error::InvalidObject(
+                    self.record,
+                    "record ends inside a field",
+                ))?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn rest(&mut self) -> &[u8] {
+        let rest = &self.bytes[self.position.min(self.bytes.len())..];
+        self.position = self.bytes.len();
+        rest
+    }
+
+    fn byte(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn word(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn offset(&mut self) -> Result<u32, Error> {
+        if self.wide {
+            let bytes = self.take(4)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        } else {
+            Ok(self.word()? as u32)
+        }
+    }
+
+    /// Index into one of the module's tables: one byte, or two with the high bit set.
+    fn index(&mut self) -> Result<u16, Error> {
+        let first = self.byte()?;
+        match first & 0x80 {
+            0 => Ok(first as u16),
+            _ => Ok((((first & 0x7F) as u16) << 8) | self.byte()? as u16),
+        }
+    }
+
+    /// Length-prefixed string.
+    fn name(&mut self) -> Result<String, Error> {
+        let length = self.byte()? as usize;
+        Ok(String::from_utf8_lossy(self.take(length)?).into_owned())
+    }
+
+    fn name_index(&mut self, names: &[String]) -> Result<String, Error> {
+        match self.index()? {
+            0 => Ok(String::new()),
+            index => Ok(names[resolve(index, names.len(), self.record)?].clone()),
+        }
+    }
+
+    fn segment_index(&mut self, module: &Module) -> Result<usize, Error> {
+        let index = self.index()?;
+        self.resolve_segment(module, index)
+    }
+
+    fn resolve_segment(&self, module: &Module, index: u16) -> Result<usize, Error> {
+        resolve(index, module.segments.len(), self.record)
+    }
+
+    /// Length of a communal variable: the byte itself up to 0x80, otherwise a marker
+    /// giving the number of bytes that follow.
+    fn communal_length(&mut self) -> Result<u32, Error> {
+        let first = self.byte()?;
+        let size = match first {
+            0..=0x80 => return Ok(first as u32),
+            0x81 => 2,
+            0x84 => 3,
+            0x88 => 4,
+            _ => return Err(Error::InvalidObject(self.record, "invalid communal length")),
+        };
+        let bytes = self.take(size)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |length, &byte| (length << 8) | byte as u32))
+    }
+
+    /// Expands one block of an `LIDATA` record: a repeat count and either nested blocks or
+    /// the bytes to repeat.
+    fn iterated_block(&mut self) -> Result<Vec<u8>, Error> {
+        let repeat = self.offset()? as usize;
+        let blocks = self.word()?;
+        let mut contents = Vec::new();
+        if blocks == 0 {
+            let length = self.byte()? as usize;
+            contents.extend_from_slice(self.take(length)?);
+        } else {
+            for _ in 0..blocks {
+                contents.extend(self.iterated_block()?);
+            }
+        }
+        if contents.len() * repeat > 0x10000 {
+            return Err(Error::InvalidObject(self.record, "iterated data too large"));
+        }
+        Ok(contents.repeat(repeat))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Layout};
+    use crate::functions::discover;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::omf::{linked_fields, Location, Module, Target};
+
+    /// Appends a record of type `kind` with its length and checksum.
+    fn record(bytes: &mut Vec<u8>, kind: u8, contents: &[u8]) {
+        let start = bytes.len();
+        bytes.push(kind);
+        bytes.extend(((contents.len() + 1) as u16).to_le_bytes());
+        bytes.extend(contents);
+        let sum = bytes[start..]
+            .iter()
+            .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+        bytes.push(sum.wrapping_neg());
+    }
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let mut bytes = Vec::new();
+        record(&mut bytes, 0x80, b"\x07hello.c");
+        record(&mut bytes, 0x96, b"\x00\x05_TEXT\x04CODE");
+        // Relocatable, byte aligned, public, 9 bytes, named _TEXT of class CODE
+        record(&mut bytes, 0x98, &[0x28, 0x09, 0x00, 0x02, 0x03, 0x01]);
+        record(&mut bytes, 0x90, b"\x00\x01\x05_main\x00\x00\x00");
+        record(&mut bytes, 0x8C, b"\x07_printf\x00");
+        // mov ax, 1; call far 0:0; ret
+        record(
+            &mut bytes,
+            0xA0,
+            &[0x01, 0x00, 0x00, 0xB8, 0x01, 0x00, 0x9A, 0, 0, 0, 0, 0xCB],
+        );
+        // Segment-relative pointer at offset 4 to external 1, frame of the target
+        record(&mut bytes, 0x9C, &[0xCC, 0x04, 0x56, 0x01]);
+        record(&mut bytes, 0x8A, &[0x00]);
+
+        let module = Module::parse(&bytes)?;
+        assert_eq!(module.name, "hello.c");
+        assert_eq!(module.segments[0].name, "_TEXT");
+        assert!(module.segments[0].is_code());
+        assert_eq!(module.segments[0].data[3], 0x9A);
+        assert_eq!(module.publics[0].name, "_main");
+        assert_eq!(module.externals, vec!["_printf"]);
+
+        let fixup = &module.fixups[0];
+        assert_eq!(
+            (fixup.offset, fixup.location, fixup.target),
+            (4, Location::Pointer, Target::External(0))
+        );
+        assert_eq!(module.target_name(fixup), "_printf");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_linked_fields() -> anyhow::Result<()> {
+        let mut bytes = Vec::new();
+        record(&mut bytes, 0x80, b"\x06call.c");
+        record(&mut bytes, 0x96, b"\x00\x05_TEXT\x04CODE");
+        record(&mut bytes, 0x98, &[0x28, 0x04, 0x00, 0x02, 0x03, 0x01]);
+        record(&mut bytes, 0x8C, b"\x04_put\x00");
+        // call _put; ret
+        record(&mut bytes, 0xA0, &[0x01, 0x00, 0x00, 0xE8, 0, 0, 0xC3]);
+        // Self-relative offset at offset 1 to external 1
+        record(&mut bytes, 0x9C, &[0x84, 0x01, 0x56, 0x01]);
+        record(&mut bytes, 0x8A, &[0x00]);
+
+        let module = Module::parse(&bytes)?;
+        let linked = linked_fields(&module, 0);
+        assert_eq!(linked, vec![Address(1)..Address(3)]);
+
+        // The call's operand is 0 until linked, which is not a call to the `ret`.
+        let layout = Layout {
+            linked,
+            ..Layout::default()
+        };
+        let program = trace(
+            &Image::flat(&module.segments[0].data),
+            &[Address(0)],
+            &layout,
+        );
+        assert!(program.branch_targets().is_empty());
+        assert_eq!(discover(&program).len(), 1);
+
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 use crate::decode::{AddressByteIteratorExt, CountingPeekable};
+use crate::encoding::{Immediate, Reg};
+use crate::format::{FormatContext, Plain, Style};
 use crate::memory::Displacement::{Disp16, Disp8};
 use crate::memory::{Address, Displacement, Memory};
 use crate::register::Register;
@@ -12,23 +14,44 @@ pub enum Operand {
 }
 
 impl Operand {
+    /// Width of the operand in bytes, or `None` for memory whose width comes from the instruction.
+    pub fn width(&self) -> Option<u8> {
+        match self {
+            Operand::Register(reg) => Some(reg.width()),
+            Operand::Memory(_) => None,
+            Operand::Immediate8(_) => Some(1),
+            Operand::Immediate16(_) => Some(2),
+        }
+    }
+
+    /// Address of a memory operand that names a fixed location, like `[1000]`.
+    pub fn direct_address(&self) -> Option<Address> {
+        match self {
+            Operand::Memory(Memory {
+                displacement: Disp16(disp),
+                registers: [None, None],
+            }) => Some(Address(*disp)),
+            _ => None,
+        }
+    }
+
     pub fn immediate<T>(w: u8, bytes: &mut CountingPeekable<T>) -> anyhow::Result<[Operand; 2]>
     where
         T: Iterator<Item = (Address, u8)>,
     {
-        let (_mod_rm_address, mod_rm) = bytes.try_next()?;
+        let mod_rm = bytes.next_mod_rm(Reg::Extension)?;
         let op_rm = Operand::from_mod_rm(w, mod_rm, bytes)?;
 
         if w == 0 {
             let (_address, data) = bytes.try_next()?;
+            bytes.fields.immediate = Some(Immediate::Byte(data));
             Ok([op_rm, Operand::Immediate8(data)])
         } else {
             let (_address, data_lo) = bytes.try_next()?;
             let (_address, data_hi) = bytes.try_next()?;
-            Ok([
-                op_rm,
-                Operand::Immediate16(((data_hi as u16) << 8) | data_lo as u16),
-            ])
+            let data = ((data_hi as u16) << 8) | data_lo as u16;
+            bytes.fields.immediate = Some(Immediate::Word(data));
+            Ok([op_rm, Operand::Immediate16(data)])
         }
     }
 
@@ -37,6 +60,17 @@ impl Operand {
         mod_rm: u8,
         bytes: &mut CountingPeekable<T>,
     ) -> anyhow::Result<Operand>
+    where
+        T: Iterator<Item = (Address, u8)>,
+    {
+        let operand = Operand::decode_rm(w, mod_rm, bytes)?;
+        if let Operand::Memory(memory) = &operand {
+            bytes.fields.displacement = memory.displacement;
+        }
+        Ok(operand)
+    }
+
+    fn decode_rm<T>(w: u8, mod_rm: u8, bytes: &mut CountingPeekable<T>) -> anyhow::Result<Operand>
     where
         T: Iterator<Item = (Address, u8)>,
     {
@@ -91,7 +125,7 @@ impl Operand {
     where
         T: Iterator<Item = (Address, u8)>,
     {
-        let (_mod_reg_rm_address, mod_reg_rm) = bytes.try_next()?;
+        let mod_reg_rm = bytes.next_mod_rm(Reg::Register)?;
         let op_reg = Operand::Register(Register::decode_reg((mod_reg_rm & 0b0011_1000) >> 3, w));
         let op_rm = Operand::from_mod_rm(w, mod_reg_rm, bytes)?;
 
@@ -105,18 +139,25 @@ impl Operand {
 
 impl std::fmt::Display for Operand {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.format(&Plain))
+    }
+}
+
+impl Operand {
+    pub fn format(&self, context: &dyn FormatContext) -> String {
+        let palette = context.palette();
         match self {
-            Operand::Register(reg) => {
-                write!(f, "{reg}")
-            }
-            Operand::Memory(mem) => {
-                write!(f, "{mem}")
-            }
+            Operand::Register(reg) => palette.paint(Style::Register, &reg.to_string()),
+            Operand::Memory(mem) => mem.format(context),
             Operand::Immediate8(imm) => {
-                write!(f, "byte {imm}")
+                let text = context.immediate(*imm as u16);
+                let text = text.unwrap_or_else(|| imm.to_string());
+                palette.paint(Style::Immediate, &format!("byte {text}"))
             }
             Operand::Immediate16(imm) => {
-                write!(f, "word {imm}")
+                let text = context.immediate(*imm);
+                let text = text.unwrap_or_else(|| imm.to_string());
+                palette.paint(Style::Immediate, &format!("word {text}"))
             }
         }
     }
@@ -0,0 +1,165 @@
+use crate::flow::Layout;
+use crate::memory::Address;
+use crate::symbol_file::{parse_address, ImportedSymbol};
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+
+/// How the immediate operand of an instruction is printed.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum OperandDisplay {
+    Hex,
+    Decimal,
+    /// The immediate is an address and is printed as the name of what it points at.
+    Offset,
+    /// The immediate is a plain number, even if it happens to match an address.
+    Constant,
+}
+
+/// Address written as hexadecimal text in the project file, like `"0x0120"`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, PartialOrd, Ord)]
+pub struct HexAddress(pub Address);
+
+impl Serialize for HexAddress {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("0x{:04x}", self.0 .0))
+    }
+}
+
+impl<'de> Deserialize<'de> for HexAddress {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        parse_address(&text)
+            .map(HexAddress)
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid address {text:?}")))
+    }
+}
+
+/// Half-open range of addresses, `start` included and `end` excluded.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Range {
+    pub start: HexAddress,
+    pub end: HexAddress,
+}
+
+/// Annotations made while reverse engineering a binary, kept in a JSON file next to it
+/// and applied on every run.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    pub entry_points: Vec<HexAddress>,
+    pub names: BTreeMap<HexAddress, String>,
+    pub comments: BTreeMap<HexAddress, String>,
+    /// Ranges decoded as code even where control flow does not reach them.
+    pub code: Vec<Range>,
+    /// Ranges kept as data even where control flow runs into them.
+    pub data: Vec<Range>,
+    /// Display of the immediate operand of the instruction at each address.
+    pub operands: BTreeMap<HexAddress, OperandDisplay>,
+}
+
+impl Project {
+    /// Project file belonging to the binary at `path`.
+    pub fn path_for(path: &str) -> String {
+        format!("{path}.project.json")
+    }
+
+    /// Reads the project at `path`, or returns an empty one if there is none yet.
+    pub fn load(path: &str) -> anyhow::Result<Project> {
+        match fs::read_to_string(path) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Project::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entry_points.is_empty()
+            && self.names.is_empty()
+            && self.comments.is_empty()
+            && self.code.is_empty()
+            && self.data.is_empty()
+            && self.operands.is_empty()
+    }
+
+    pub fn entry_points(&self) -> impl Iterator<Item = Address> + '_ {
+        self.entry_points.iter().map(|address| address.0)
+    }
+
+    pub fn layout(&self) -> Layout {
+        let ranges = |ranges: &[Range]| {
+            ranges
+                .iter()
+                .map(|range| range.start.0..range.end.0)
+                .collect()
+        };
+
+        Layout {
+            code: ranges(&self.code),
+            data: ranges(&self.data),
+            linked: Vec::new(),
+        }
+    }
+
+    /// User-assigned names together with the comment at the same address.
+    pub fn symbols(&self) -> Vec<ImportedSymbol> {
+        self.names
+            .iter()
+            .map(|(address, name)| ImportedSymbol {
+                address: address.0,
+                name: name.clone(),
+                comment: None,
+            })
+            .collect()
+    }
+
+    pub fn comments(&self) -> impl Iterator<Item = (Address, &str)> {
+        self.comments
+            .iter()
+            .map(|(address, comment)| (address.0, comment.as_str()))
+    }
+
+    pub fn operands(&self) -> impl Iterator<Item = (Address, OperandDisplay)> + '_ {
+        self.operands
+            .iter()
+            .map(|(address, display)| (address.0, *display))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::Address;
+    use crate::project::{HexAddress, OperandDisplay, Project};
+
+    #[test]
+    fn test_project_round_trip() -> anyhow::Result<()> {
+        let json = r#"{
+            "names": { "0x0120": "draw_line" },
+            "data": [{ "start": "0x0200", "end": "0x0210" }],
+            "operands": { "0x0005": "offset" }
+        }"#;
+
+        let project: Project = serde_json::from_str(json)?;
+        assert_eq!(project.names[&HexAddress(Address(0x120))], "draw_line");
+        assert_eq!(project.layout().data, vec![Address(0x200)..Address(0x210)]);
+        assert!(project.entry_points.is_empty());
+
+        let saved = serde_json::to_string(&project)?;
+        assert!(saved.contains(r#""0x0005":"offset""#));
+        let reloaded: Project = serde_json::from_str(&saved)?;
+        assert_eq!(
+            reloaded.operands().collect::<Vec<_>>(),
+            vec![(Address(5), OperandDisplay::Offset)]
+        );
+
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use std::fmt::Formatter;
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum Register {
     Al = 0,
     Cl = 1,
@@ -43,6 +44,21 @@ impl std::fmt::Display for Register {
 }
 
 impl Register {
+    /// Width of the register in bytes.
+    pub fn width(&self) -> u8 {
+        match self {
+            Register::Al
+            | Register::Cl
+            | Register::Dl
+            | Register::Bl
+            | Register::Ah
+            | Register::Ch
+            | Register::Dh
+            | Register::Bh => 1,
+            _ => 2,
+        }
+    }
+
     pub fn decode_reg(reg: u8, w: u8) -> Register {
         match (reg, w) {
             (0b0000, 0) => Register::Al,
@@ -80,3 +96,35 @@ impl Register {
         }
     }
 }
+
+/// Segment register, numbered like the `sreg` field of `mov` to and from segment registers.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SegmentRegister {
+    Es = 0,
+    Cs = 1,
+    Ss = 2,
+    Ds = 3,
+}
+
+impl std::fmt::Display for SegmentRegister {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SegmentRegister::Es => write!(f, "es"),
+            SegmentRegister::Cs => write!(f, "cs"),
+            SegmentRegister::Ss => write!(f, "ss"),
+            SegmentRegister::Ds => write!(f, "ds"),
+        }
+    }
+}
+
+impl SegmentRegister {
+    /// The 8086 ignores the top bit of the three-bit field.
+    pub fn decode_sreg(sreg: u8) -> SegmentRegister {
+        match sreg & 0b11 {
+            0b00 => SegmentRegister::Es,
+            0b01 => SegmentRegister::Cs,
+            0b10 => SegmentRegister::Ss,
+            _ => SegmentRegister::Ds,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use crate::error::Error;
+use crate::image::Image;
+use std::fmt::Write;
+
+/// Where the 8086 starts after a reset, as `segment:offset`.
+pub const RESET_VECTOR: (u16, u16) = (0xFFFF, 0x0000);
+
+/// Option ROMs start with this signature, a length in 512-byte blocks and their
+/// initialization entry point.
+const OPTION_ROM_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const OPTION_ROM_ENTRY: u16 = 3;
+const BLOCK_SIZE: usize = 512;
+
+/// First segment the BIOS scans for option ROMs, where video ROMs sit.
+const OPTION_ROM_SEGMENT: u16 = 0xC000;
+
+/// System or option ROM placed at its segment.
+pub struct Rom<'a> {
+    bytes: &'a [u8],
+    pub segment: u16,
+    option: bool,
+    /// Where execution starts: the reset vector for a system ROM and the initialization
+    /// entry point for an option ROM.
+    pub entry: (u16, u16),
+}
+
+impl<'a> Rom<'a> {
+    /// Places `bytes` at `segment`, by default at `C000` for an option ROM and so that it
+    /// ends at the top of memory for a system ROM. A system ROM must cover the reset vector.
+    pub fn parse(bytes: &'a [u8], segment: Option<u16>) -> Result<Rom<'a>, Error> {
+        if bytes.is_empty() || bytes.len() > 0x10000 {
+            return Err(Error::InvalidRom("not between 1 byte and 64 KiB long"));
+        }
+        let option = bytes.len() >= 3 && bytes[..2] == OPTION_ROM_SIGNATURE;
+        let segment = segment.unwrap_or(if option {
+            OPTION_ROM_SEGMENT
+        } else {
+            ((0x10_0000 - bytes.len().next_multiple_of(16)) >> 4) as u16
+        });
+        let start = segment as usize * 16;
+        if start + bytes.len() > 0x10_0000 {
+            return Err(Error::InvalidRom("extends past the end of memory"));
+        }
+
+        let entry = if option {
+            (segment, OPTION_ROM_ENTRY)
+        } else {
+            let reset = linear(RESET_VECTOR);
+            if !(start..start + bytes.len()).contains(&reset) {
+                return Err(Error::InvalidRom(
+                    "does not cover the reset vector at FFFF:0000",
+                ));
+            }
+            RESET_VECTOR
+        };
+
+        Ok(Rom {
+            bytes,
+            segment,
+            option,
+            entry,
+        })
+    }
+
+    pub fn is_option_rom(&self) -> bool {
+        self.option
+    }
+
+    /// Bytes the checksum covers: the length in the header for an option ROM, everything
+    /// for a system ROM.
+    fn checked(&self) -> &[u8] {
+        if self.is_option_rom() {
+            let length = self.bytes[2] as usize * BLOCK_SIZE;
+            &self.bytes[..length.min(self.bytes.len())]
+        } else {
+            self.bytes
+        }
+    }
+
+    /// Sum of the checked bytes, which is 0 for a valid ROM.
+    pub fn checksum(&self) -> u8 {
+        self.checked()
+            .iter()
+            .fold(0, |sum, &byte| sum.wrapping_add(byte))
+    }
+
+    /// Linear addresses to trace from: the entry point, and where the far jump a system ROM
+    /// usually has at the reset vector leads if it stays in the ROM.
+    pub fn entry_points(&self) -> Vec<u32> {
+        let entry = linear(self.entry);
+        let mut entry_points = vec![entry as u32];
+        let at_entry = &self.bytes[entry - self.segment as usize * 16..];
+        if let [0xEA, offset_low, offset_high, segment_low, segment_high, ..] = *at_entry {
+            let target = linear((
+                u16::from_le_bytes([segment_low, segment_high]),
+                u16::from_le_bytes([offset_low, offset_high]),
+            ));
+            let start = self.segment as usize * 16;
+            if (start..start + self.bytes.len()).contains(&target) {
+                entry_points.push(target as u32);
+            }
+        }
+        entry_points
+    }
+
+    /// The ROM at its linear address, starting at its entry point.
+    pub fn image(&self) -> Image {
+        let mut image = Image::default();
+        image.insert(self.segment as u32 * 16, self.bytes);
+        image.entry = Some(linear(self.entry) as u32);
+        image
+    }
+
+    /// Kind, placement, entry point and checksum as assembly comments, for the top of a
+    /// listing.
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        let kind = if self.is_option_rom() {
+            "option ROM"
+        } else {
+            "system ROM"
+        };
+        let _ = writeln!(
+            text,
+            "; {kind} of {:#x} bytes at {:04x}:0000, entry {:04x}:{:04x}",
+            self.bytes.len(),
+            self.segment,
+            self.entry.0,
+            self.entry.1
+        );
+        if self.is_option_rom() && self.checked().len() < self.bytes[2] as usize * BLOCK_SIZE {
+            let _ = writeln!(
+                text,
+                "; header gives {:#x} bytes, more than the file holds",
+                self.bytes[2] as usize * BLOCK_SIZE
+            );
+        }
+        match self.checksum() {
+            0 => {
+                let _ = writeln!(text, "; checksum ok");
+            }
+            sum => {
+                let _ = writeln!(text, "; checksum bad: bytes sum to {sum:#04x}");
+            }
+        }
+        text
+    }
+}
+
+fn linear((segment, offset): (u16, u16)) -> usize {
+    segment as usize * 16 + offset as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::rom::{Rom, RESET_VECTOR};
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        let mut system = vec![0; 0x2000];
+        // jmp f000:e05b at the reset vector
+        system[0x1FF0..0x1FF5].copy_from_slice(&[0xEA, 0x5B, 0xE0, 0x00, 0xF0]);
+        let rom = Rom::parse(&system, None)?;
+        assert_eq!(rom.segment, 0xFE00);
+        assert_eq!(rom.entry, RESET_VECTOR);
+        assert_eq!(rom.image().entry, Some(0xFFFF0));
+        assert_eq!(rom.entry_points(), vec![0xFFFF0, 0xFE05B]);
+        // 0xEA + 0x5B + 0xE0 + 0xF0 = 0x315
+        assert_eq!(rom.checksum(), 0x15);
+        assert!(Rom::parse(&system, Some(0xF000)).is_err());
+
+        let mut option = vec![0; 0x400];
+        option[..5].copy_from_slice(&[0x55, 0xAA, 0x02, 0xCB, 0x00]);
+        // 0x55 + 0xAA + 0x02 + 0xCB = 0x1CC
+        option[0x3FF] = 0x34;
+        let rom = Rom::parse(&option, None)?;
+        assert!(rom.is_option_rom());
+        assert_eq!(rom.entry, (0xC000, 3));
+        assert_eq!(rom.checksum(), 0);
+
+        Ok(())
+    }
+}
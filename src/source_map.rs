@@ -0,0 +1,322 @@
+use crate::image::Image;
+use crate::memory::Address;
+use std::collections::BTreeMap;
+use std::fs;
+
+/// Column NASM starts the source text at in a `-l` listing, after the line number, the
+/// offset, the bytes and the macro level.
+const NASM_SOURCE_COLUMN: usize = 40;
+
+/// Column MASM starts the source text at in a `.LST` file, once tabs are expanded.
+const MASM_SOURCE_COLUMN: usize = 32;
+
+/// Most matches of the longest listed instruction tried as the listing's origin.
+const MAX_CANDIDATES: usize = 16;
+
+/// Line of the original source, numbered when the listing gives the numbers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceLine {
+    pub number: Option<usize>,
+    pub text: String,
+}
+
+impl std::fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.number {
+            Some(number) => write!(f, "{number}: {}", self.text),
+            None => write!(f, "{}", self.text),
+        }
+    }
+}
+
+/// Offset, listed bytes and source text of a line of a listing.
+type ListingLine = (Option<u32>, Vec<Option<u8>>, Option<SourceLine>);
+
+/// Source lines that assembled to bytes, with the lines without bytes before them.
+struct Entry {
+    offset: u32,
+    /// Bytes as listed; relocated fields are unknown.
+    bytes: Vec<Option<u8>>,
+    /// The lines leading up to the one that emitted the bytes, which comes last.
+    lines: Vec<SourceLine>,
+}
+
+/// Source lines of a NASM `-l` or MASM `.LST` listing by the offset their code was
+/// assembled at.
+#[derive(Default)]
+pub struct SourceMap {
+    entries: Vec<Entry>,
+}
+
+impl SourceMap {
+    pub fn load(path: &str) -> anyhow::Result<SourceMap> {
+        Ok(SourceMap::parse(&fs::read_to_string(path)?))
+    }
+
+    /// Parses a listing as NASM or MASM, whichever format more of its lines are in. Page
+    /// headers, symbol tables and other lines of neither format are skipped.
+    pub fn parse(text: &str) -> SourceMap {
+        let nasm = text.lines().filter(|line| is_nasm(line)).count();
+        let masm = text
+            .lines()
+            .filter(|line| is_masm(&expand_tabs(line)))
+            .count();
+
+        let mut map = SourceMap::default();
+        let mut pending = Vec::new();
+        for line in text.lines() {
+            let parsed = if nasm >= masm {
+                parse_nasm(line)
+            } else {
+                parse_masm(&expand_tabs(line))
+            };
+            let Some((offset, bytes, source)) = parsed else {
+                continue;
+            };
+            if bytes.is_empty() {
+                pending.extend(source);
+            } else if source.is_none() && map.continues(offset) {
+                // A continuation line of NASM, which holds the rest of the bytes.
+                if let Some(entry) = map.entries.last_mut() {
+                    entry.bytes.extend(bytes);
+                }
+            } else {
+                let mut lines = std::mem::take(&mut pending);
+                lines.extend(source);
+                map.entries.push(Entry {
+                    offset: offset.expect("lines with bytes have an offset"),
+                    bytes,
+                    lines,
+                });
+            }
+        }
+        map
+    }
+
+    fn continues(&self, offset: Option<u32>) -> bool {
+        self.entries
+            .last()
+            .is_some_and(|entry| Some(entry.offset + entry.bytes.len() as u32) == offset)
+    }
+
+    /// Source lines by the address of the code they assembled to in `image`.
+    ///
+    /// Listings count from the start of their section, not from the `org`, so the listing is
+    /// moved to where its bytes match the image best: by nothing, or to one of the places
+    /// the longest listed instruction occurs.
+    pub fn place(&self, image: &Image) -> BTreeMap<Address, Vec<SourceLine>> {
+        let mut candidates = vec![0i64];
+        if let Some(longest) = self.entries.iter().max_by_key(|entry| entry.bytes.len()) {
+            for (start, data) in image.regions() {
+                let matches = (0..data.len())
+                    .filter(|&index| matches_at(&longest.bytes, &data[index..]))
+                    .take(MAX_CANDIDATES);
+                candidates.extend(
+                    matches.map(|index| start as i64 + index as i64 - longest.offset as i64),
+                );
+            }
+        }
+
+        let score = |delta: i64| {
+            self.entries
+                .iter()
+                .filter(|entry| {
+                    let address = entry.offset as i64 + delta;
+                    image.regions().any(|(start, data)| {
+                        let index = address - start as i64;
+                        (0..data.len() as i64).contains(&index)
+                            && matches_at(&entry.bytes, &data[index as usize..])
+                    })
+                })
+                .count()
+        };
+        let mut best = (0, score(0));
+        for &delta in &candidates[1..] {
+            let score = score(delta);
+            if score > best.1 {
+                best = (delta, score);
+            }
+        }
+
+        self.entries
+            .iter()
+            .filter_map(|entry| {
+                let address = u16::try_from(entry.offset as i64 + best.0).ok()?;
+                Some((Address(address), entry.lines.clone()))
+            })
+            .collect()
+    }
+}
+
+fn matches_at(listed: &[Option<u8>], data: &[u8]) -> bool {
+    listed.len() <= data.len()
+        && listed
+            .iter()
+            .zip(data)
+            .all(|(listed, byte)| listed.is_none_or(|listed| listed == *byte))
+}
+
+/// `     2 00000000 B80100                  mov ax, 1`
+fn is_nasm(line: &str) -> bool {
+    let number = line.get(..6).map(str::trim_start);
+    number.is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()))
+        && line.get(6..7) == Some(" ")
+        && line.get(7..15).is_some_and(|offset| {
+            offset.bytes().all(|b| b.is_ascii_hexdigit()) || offset.trim().is_empty()
+        })
+}
+
+/// Offset, bytes and source of a line of a NASM listing. Relocated fields are listed in
+/// brackets or parentheses, repeated data as `<rep N>` and reserved space as `<res N>`.
+fn parse_nasm(line: &str) -> Option<ListingLine> {
+    if !is_nasm(line) {
+        return None;
+    }
+    let number = line[..6].trim().parse().ok()?;
+    let offset = u32::from_str_radix(line[7..15].trim(), 16).ok();
+
+    let field = line.get(16..35).unwrap_or(line.get(16..).unwrap_or(""));
+    let mut bytes = Vec::new();
+    let mut relocated = false;
+    let mut digits = field.trim_end().trim_end_matches('-').chars();
+    while let Some(c) = digits.next() {
+        match c {
+            '[' | '(' => relocated = true,
+            ']' | ')' => relocated = false,
+            '<' => break,
+            c if c.is_ascii_hexdigit() => {
+                let low = digits.next()?;
+                let byte = u8::from_str_radix(&format!("{c}{low}"), 16).ok()?;
+                bytes.push((!relocated).then_some(byte));
+            }
+            _ => {}
+        }
+    }
+
+    let source = line
+        .get(NASM_SOURCE_COLUMN..)
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(|text| SourceLine {
+            number: Some(number),
+            text: text.to_string(),
+        });
+    Some((offset, bytes, source))
+}
+
+/// ` 0000  B8 0001               start:  mov ax, 1`, or only the source for lines without
+/// code.
+fn is_masm(line: &str) -> bool {
+    let prefix = line.get(..MASM_SOURCE_COLUMN).unwrap_or(line);
+    if prefix.trim().is_empty() {
+        return line.len() > MASM_SOURCE_COLUMN;
+    }
+    line.starts_with(' ')
+        && line
+            .get(1..5)
+            .is_some_and(|offset| offset.bytes().all(|b| b.is_ascii_hexdigit()))
+        && matches!(line.get(5..6), None | Some(" "))
+}
+
+/// Offset, bytes and source of a line of a MASM listing. Words are listed by their value,
+/// and segments and externals the linker fills in as `----` or with an `R` or `E` after
+/// them.
+fn parse_masm(line: &str) -> Option<ListingLine> {
+    if !is_masm(line) {
+        return None;
+    }
+    let offset = line
+        .get(1..5)
+        .and_then(|offset| u32::from_str_radix(offset, 16).ok());
+
+    let field = line
+        .get(5..MASM_SOURCE_COLUMN)
+        .unwrap_or(line.get(5..).unwrap_or(""));
+    let mut bytes: Vec<Option<u8>> = Vec::new();
+    if offset.is_some() {
+        for token in field.split_whitespace() {
+            let hex = token.bytes().all(|b| b.is_ascii_hexdigit());
+            match token.len() {
+                _ if token.contains('[') => break,
+                2 if hex => bytes.push(u8::from_str_radix(token, 16).ok()),
+                4 if hex => {
+                    let word = u16::from_str_radix(token, 16).ok()?;
+                    bytes.extend(word.to_le_bytes().map(Some));
+                }
+                4 if token == "----" => bytes.extend([None, None]),
+                _ => {}
+            }
+        }
+    }
+
+    let source = line
+        .get(MASM_SOURCE_COLUMN..)
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(|text| SourceLine {
+            number: None,
+            text: text.to_string(),
+        });
+    Some((offset, bytes, source))
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::new();
+    for c in line.chars() {
+        if c == '\t' {
+            let width = 8 - expanded.chars().count() % 8;
+            expanded.extend(std::iter::repeat_n(' ', width));
+        } else {
+            expanded.push(c);
+        }
+    }
+    expanded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::source_map::SourceMap;
+
+    #[test]
+    fn test_parse() {
+        let nasm = "     1                                  org 0x7c00
+     2                                  ; print a greeting
+     3 00000000 B80100                  start: mov ax, 1
+     4 00000003 E8(FDFF)                call start
+     5 00000006 48656C6C6F2C20776F-     db \"Hello, world\"
+     5 0000000F 726C64
+";
+        let mut image = Image::default();
+        let mut bytes = vec![0xB8, 0x01, 0x00, 0xE8, 0xFA, 0xFF];
+        bytes.extend(b"Hello, world");
+        image.insert(0x7C00, &bytes);
+
+        let lines = SourceMap::parse(nasm).place(&image);
+        let addresses: Vec<u16> = lines.keys().map(|address| address.0).collect();
+        assert_eq!(addresses, vec![0x7C00, 0x7C03, 0x7C06]);
+        let first: Vec<String> = lines[&Address(0x7C00)]
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            first,
+            vec![
+                "1: org 0x7c00",
+                "2: ; print a greeting",
+                "3: start: mov ax, 1"
+            ]
+        );
+
+        let masm = "Microsoft (R) Macro Assembler Version 6.11\t\t    01/01/24 12:00:00
+ 0000\t\t\t\t_TEXT\tSEGMENT
+ 0000  B8 ---- R\t\tstart:\tmov\tax, @data
+ 0003  8E D8\t\t\t\tmov\tds, ax
+";
+        let image = Image::flat(&[0xB8, 0x34, 0x12, 0x8E, 0xD8]);
+        let lines = SourceMap::parse(masm).place(&image);
+        assert_eq!(lines[&Address(0)].len(), 2);
+        assert_eq!(lines[&Address(3)][0].to_string(), "mov     ds, ax");
+    }
+}
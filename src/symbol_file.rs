@@ -0,0 +1,153 @@
+use crate::error::Error;
+use crate::memory::Address;
+use std::fs;
+
+pub struct ImportedSymbol {
+    pub address: Address,
+    pub name: String,
+    pub comment: Option<String>,
+}
+
+/// Loads symbols from a MASM/TLINK `.MAP` file or from a list of `address name [comment]` lines.
+pub fn load(path: &str) -> anyhow::Result<Vec<ImportedSymbol>> {
+    let contents = fs::read_to_string(path)?;
+
+    if contents.contains("Publics by") {
+        Ok(parse_map(&contents))
+    } else {
+        Ok(parse_list(&contents)?)
+    }
+}
+
+/// Reads the public symbols of a linker map. Symbols are listed once by name and once
+/// by value, so only the first occurrence of each address is kept.
+fn parse_map(contents: &str) -> Vec<ImportedSymbol> {
+    let mut symbols: Vec<ImportedSymbol> = Vec::new();
+    let mut in_publics = false;
+
+    for line in contents.lines() {
+        if line.contains("Publics by") {
+            in_publics = true;
+            continue;
+        }
+        if !in_publics {
+            continue;
+        }
+
+        let mut tokens = line.split_whitespace();
+        let Some(address) = tokens.next().and_then(parse_segmented_address) else {
+            continue;
+        };
+        // `Abs` and `Imp` mark absolute and imported symbols.
+        let name = match tokens.next() {
+            Some("Abs") | Some("Imp") => tokens.next(),
+            name => name,
+        };
+
+        if let Some(name) = name {
+            if !symbols.iter().any(|symbol| symbol.address == address) {
+                symbols.push(ImportedSymbol {
+                    address,
+                    name: name.to_string(),
+                    comment: None,
+                });
+            }
+        }
+    }
+
+    symbols
+}
+
+fn parse_list(contents: &str) -> Result<Vec<ImportedSymbol>, Error> {
+    let mut symbols = Vec::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        let mut parts = line.splitn(3, char::is_whitespace);
+        let address = parts.next().and_then(parse_address);
+        let name = parts.next();
+        let (Some(address), Some(name)) = (address, name) else {
+            return Err(Error::InvalidSymbolLine(index + 1, line.to_string()));
+        };
+        let comment = parts
+            .next()
+            .map(|comment| comment.trim().trim_start_matches(';').trim().to_string())
+            .filter(|comment| !comment.is_empty());
+
+        symbols.push(ImportedSymbol {
+            address,
+            name: name.to_string(),
+            comment,
+        });
+    }
+
+    Ok(symbols)
+}
+
+pub fn parse_address(token: &str) -> Option<Address> {
+    if token.contains(':') {
+        return parse_segmented_address(token);
+    }
+
+    let digits = token.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits.trim_end_matches(['h', 'H']), 16)
+        .ok()
+        .map(Address)
+}
+
+/// Parses a `segment:offset` pair into the address it refers to.
+fn parse_segmented_address(token: &str) -> Option<Address> {
+    let (segment, offset) = token.split_once(':')?;
+    let segment = u16::from_str_radix(segment, 16).ok()?;
+    let offset = u16::from_str_radix(offset, 16).ok()?;
+
+    Some(Address(((segment as u32) * 16 + offset as u32) as u16))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::symbol_file::{parse_list, parse_map};
+
+    #[test]
+    fn test_parse_map() {
+        let map = " Start  Stop   Length Name   Class\n\
+                   \x20000000H 0001FH 00020H _TEXT  CODE\n\
+                   \n\
+                   \x20 Address         Publics by Name\n\
+                   \n\
+                   \x200000:0010       _main\n\
+                   \x200000:0000  Abs  _start\n\
+                   \n\
+                   \x20 Address         Publics by Value\n\
+                   \n\
+                   \x200000:0000  Abs  _start\n\
+                   \x200000:0010       _main\n\
+                   \n\
+                   Program entry point at 0000:0000\n";
+
+        let symbols = parse_map(map);
+        let names: Vec<(u16, &str)> = symbols
+            .iter()
+            .map(|symbol| (symbol.address.0, symbol.name.as_str()))
+            .collect();
+        assert_eq!(names, vec![(0x10, "_main"), (0, "_start")]);
+    }
+
+    #[test]
+    fn test_parse_list() -> anyhow::Result<()> {
+        let list = "# name list\n0x0120 draw_line ; Bresenham\n1000:0004 counter\n";
+
+        let symbols = parse_list(list)?;
+        assert_eq!(symbols[0].address.0, 0x120);
+        assert_eq!(symbols[0].comment.as_deref(), Some("Bresenham"));
+        assert_eq!(symbols[1].address.0, 0x4);
+        assert_eq!(symbols[1].comment, None);
+        assert!(parse_list("nonsense").is_err());
+
+        Ok(())
+    }
+}
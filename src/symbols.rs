@@ -0,0 +1,449 @@
+use crate::flow::{Item, Program};
+use crate::format::FormatContext;
+use crate::functions::Function;
+use crate::memory::Address;
+use crate::project::OperandDisplay;
+use crate::source_map::SourceLine;
+use crate::symbol_file::ImportedSymbol;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum SymbolKind {
+    Function,
+    /// Branch target inside a function, declared as a NASM local label.
+    Local,
+    /// Branch target outside of any function.
+    Code,
+    /// Fixed memory location accessed with the given width in bytes.
+    Data {
+        width: u8,
+    },
+}
+
+struct Symbol {
+    name: String,
+    kind: SymbolKind,
+}
+
+/// Field of an instruction that a relocation fills in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OperandField {
+    /// Displacement of a memory operand.
+    Displacement,
+    Immediate,
+    /// `segment:offset` of a far `call` or `jmp`.
+    Pointer,
+    /// Relative target of a near `call` or `jmp`.
+    Branch,
+}
+
+/// Names given to addresses in the listing.
+///
+/// Functions are named `sub_XXXX`. Other branch targets become NASM local labels
+/// (`.loc_XXXX`) scoped under the function they fall in, and directly addressed
+/// memory is named after its width (`byte_XXXX`, `word_XXXX`).
+#[derive(Default)]
+pub struct Symbols {
+    symbols: BTreeMap<Address, Symbol>,
+    comments: BTreeMap<Address, String>,
+    /// Lines of the original source, the last one being the line of the code at the address.
+    source: BTreeMap<Address, Vec<SourceLine>>,
+    operand_displays: BTreeMap<Address, OperandDisplay>,
+    /// Segment words the loader relocates, with their value in the file.
+    fixups: BTreeMap<Address, u16>,
+    /// Names printed instead of the placeholder values of relocated operands.
+    operand_names: BTreeMap<(Address, OperandField), String>,
+    /// Symbols at the start of an item, which are declared as labels. The others are `equ`s.
+    placed: BTreeSet<Address>,
+    functions: BTreeSet<Address>,
+    function_ends: BTreeMap<Address, Address>,
+}
+
+impl Symbols {
+    pub fn new(program: &Program, functions: &[Function]) -> Symbols {
+        let mut symbols = Symbols {
+            symbols: BTreeMap::new(),
+            comments: BTreeMap::new(),
+            source: BTreeMap::new(),
+            operand_displays: BTreeMap::new(),
+            fixups: BTreeMap::new(),
+            operand_names: BTreeMap::new(),
+            placed: BTreeSet::new(),
+            functions: functions.iter().map(|function| function.start).collect(),
+            function_ends: functions
+                .iter()
+                .filter_map(|function| function.end.map(|end| (end, function.start)))
+                .collect(),
+        };
+
+        for function in functions {
+            symbols.insert(
+                function.start,
+                format!("sub_{:04X}", function.start.0),
+                SymbolKind::Function,
+            );
+        }
+
+        for target in program.branch_targets() {
+            match symbols.scope_of(target) {
+                Some(_) => {
+                    symbols.insert(target, format!(".loc_{:04X}", target.0), SymbolKind::Local)
+                }
+                None => symbols.insert(target, format!("loc_{:04X}", target.0), SymbolKind::Code),
+            }
+        }
+
+        for (_, instruction) in program.instructions() {
+            let width = instruction.operand_width().unwrap_or(1);
+            for address in instruction.direct_addresses() {
+                let prefix = match width {
+                    1 => "byte",
+                    2 => "word",
+                    _ => "dword",
+                };
+                symbols.insert(
+                    address,
+                    format!("{prefix}_{:04X}", address.0),
+                    SymbolKind::Data { width },
+                );
+            }
+        }
+
+        symbols.placed = symbols
+            .symbols
+            .keys()
+            .copied()
+            .filter(|address| program.items.contains_key(address))
+            .collect();
+
+        symbols
+    }
+
+    /// Replaces generated names with imported ones. Imported names at instructions are
+    /// treated as functions; without a program every imported name is.
+    pub fn import(&mut self, program: Option<&Program>, imported: &[ImportedSymbol]) {
+        for symbol in imported {
+            let is_code = program.is_none_or(|program| program.is_instruction(symbol.address));
+            let kind = match self.symbols.get(&symbol.address) {
+                Some(existing) if existing.kind != SymbolKind::Local => existing.kind,
+                _ if is_code => SymbolKind::Function,
+                _ => SymbolKind::Data { width: 1 },
+            };
+            if kind == SymbolKind::Function {
+                self.functions.insert(symbol.address);
+            }
+            self.symbols.insert(
+                symbol.address,
+                Symbol {
+                    name: symbol.name.clone(),
+                    kind,
+                },
+            );
+
+            if let Some(comment) = &symbol.comment {
+                self.comments.insert(symbol.address, comment.clone());
+            }
+            if program.is_some_and(|program| program.items.contains_key(&symbol.address)) {
+                self.placed.insert(symbol.address);
+            }
+        }
+    }
+
+    /// Sets the width data at `address` is printed with, if it is named as data.
+    pub fn set_data_width(&mut self, address: Address, width: u8) {
+        if let Some(symbol) = self.symbols.get_mut(&address) {
+            if let SymbolKind::Data { .. } = symbol.kind {
+                symbol.kind = SymbolKind::Data { width };
+            }
+        }
+    }
+
+    pub fn add_fixup(&mut self, location: Address, segment: u16) {
+        self.fixups.insert(location, segment);
+    }
+
+    /// Relocated segment word within the `size` bytes at `address`.
+    pub fn fixup_in(&self, address: Address, size: u16) -> Option<u16> {
+        let end = address.0.checked_add(size).map(Address);
+        let mut fixups = match end {
+            Some(end) => self.fixups.range(address..end),
+            None => self.fixups.range(address..),
+        };
+        fixups.next().map(|(_, segment)| *segment)
+    }
+
+    /// Prints `name` in place of `field` of the instruction at `address`.
+    pub fn name_operand(&mut self, address: Address, field: OperandField, name: String) {
+        self.operand_names.insert((address, field), name);
+    }
+
+    pub fn operand_name(&self, address: Address, field: OperandField) -> Option<&str> {
+        self.operand_names
+            .get(&(address, field))
+            .map(String::as_str)
+    }
+
+    pub fn add_comment(&mut self, address: Address, comment: &str) {
+        self.comments.insert(address, comment.to_string());
+    }
+
+    pub fn comment(&self, address: Address) -> Option<&str> {
+        self.comments.get(&address).map(String::as_str)
+    }
+
+    pub fn add_source(&mut self, address: Address, lines: Vec<SourceLine>) {
+        self.source.insert(address, lines);
+    }
+
+    pub fn source(&self, address: Address) -> &[SourceLine] {
+        self.source.get(&address).map_or(&[], Vec::as_slice)
+    }
+
+    /// Sets how the immediate of the instruction at `address` is printed. Immediates shown
+    /// as offsets get a name for the address they point at if it has none yet.
+    pub fn set_operand_display(
+        &mut self,
+        program: Option<&Program>,
+        address: Address,
+        display: OperandDisplay,
+    ) {
+        self.operand_displays.insert(address, display);
+        if display != OperandDisplay::Offset {
+            return;
+        }
+
+        let Some(program) = program else {
+            return;
+        };
+        let target = match program.items.get(&address) {
+            Some(Item::Instruction(instruction)) => instruction.immediate().map(Address),
+            _ => None,
+        };
+        if let Some(target) = target {
+            let prefix = if program.is_instruction(target) {
+                "loc"
+            } else {
+                "unk"
+            };
+            self.insert(
+                target,
+                format!("{prefix}_{:04X}", target.0),
+                SymbolKind::Code,
+            );
+            if program.items.contains_key(&target) {
+                self.placed.insert(target);
+            }
+        }
+    }
+
+    pub fn operand_display(&self, address: Address) -> Option<OperandDisplay> {
+        self.operand_displays.get(&address).copied()
+    }
+
+    /// Formatting context for the instruction at `address`, which also applies its
+    /// operand display.
+    pub fn at(&self, address: Address) -> At<'_> {
+        At {
+            symbols: self,
+            address,
+        }
+    }
+
+    /// Adds a symbol unless the address already has a name.
+    fn insert(&mut self, address: Address, name: String, kind: SymbolKind) {
+        self.symbols.entry(address).or_insert(Symbol { name, kind });
+    }
+
+    /// Label declared in front of the item at `address`.
+    pub fn label(&self, address: Address) -> Option<&str> {
+        if !self.placed.contains(&address) {
+            return None;
+        }
+
+        self.symbols
+            .get(&address)
+            .map(|symbol| symbol.name.as_str())
+    }
+
+    /// Symbols that do not start an item and are declared with `equ` instead.
+    pub fn equates(&self) -> impl Iterator<Item = (&str, Address)> {
+        self.symbols
+            .iter()
+            .filter(|(address, _)| !self.placed.contains(address))
+            .map(|(address, symbol)| (symbol.name.as_str(), *address))
+    }
+
+    /// Name to use for `target` in an instruction at `from`. Local labels outside the
+    /// current NASM scope are qualified with the label that scopes them.
+    pub fn reference(&self, from: Address, target: Address) -> Option<String> {
+        let symbol = self.symbols.get(&target)?;
+        if symbol.kind != SymbolKind::Local {
+            return Some(symbol.name.clone());
+        }
+
+        let scope = self.nasm_scope(target);
+        match scope {
+            Some(scope) if self.nasm_scope(from) != Some(scope) => {
+                Some(format!("{}{}", self.symbols[&scope].name, symbol.name))
+            }
+            _ => Some(symbol.name.clone()),
+        }
+    }
+
+    /// Width in bytes of the data accessed at `address`, if it is directly addressed.
+    pub fn data_width(&self, address: Address) -> Option<u8> {
+        match self.symbols.get(&address)?.kind {
+            SymbolKind::Data { width } => Some(width),
+            _ => None,
+        }
+    }
+
+    pub fn is_function(&self, address: Address) -> bool {
+        self.functions.contains(&address)
+    }
+
+    /// Start and name of every function, in address order.
+    pub fn functions(&self) -> impl Iterator<Item = (Address, &str)> {
+        self.functions
+            .iter()
+            .filter_map(|address| Some((*address, self.symbols.get(address)?.name.as_str())))
+    }
+
+    /// Function whose last `ret` or `jmp` ends just before `address`.
+    pub fn function_ending_at(&self, address: Address) -> Option<&str> {
+        self.function_ends
+            .get(&address)
+            .and_then(|start| self.label(*start))
+    }
+
+    /// Describes `address` relative to the function containing it, like `sub_0120+0x14`.
+    pub fn describe(&self, address: Address) -> String {
+        self.locate(address)
+            .unwrap_or_else(|| format!("{:04X}", address.0))
+    }
+
+    /// Like [`Symbols::describe`], but `None` when no function contains `address`.
+    pub fn locate(&self, address: Address) -> Option<String> {
+        let scope = self.scope_of(address)?;
+        let name = &self.symbols[&scope].name;
+
+        if scope == address {
+            Some(name.clone())
+        } else {
+            Some(format!("{name}+{:#x}", address.0.wrapping_sub(scope.0)))
+        }
+    }
+
+    fn scope_of(&self, address: Address) -> Option<Address> {
+        self.functions.range(..=address).next_back().copied()
+    }
+
+    /// Closest label at or before `address` that NASM attaches local labels to.
+    fn nasm_scope(&self, address: Address) -> Option<Address> {
+        self.symbols
+            .range(..=address)
+            .rev()
+            .find(|(a, symbol)| symbol.kind != SymbolKind::Local && self.placed.contains(a))
+            .map(|(a, _)| *a)
+    }
+}
+
+impl FormatContext for Symbols {
+    fn memory_name(&self, address: Address) -> Option<String> {
+        let symbol = self.symbols.get(&address)?;
+        (symbol.kind != SymbolKind::Local).then(|| symbol.name.clone())
+    }
+}
+
+pub struct At<'a> {
+    symbols: &'a Symbols,
+    address: Address,
+}
+
+impl At<'_> {
+    fn operand_name(&self, field: OperandField) -> Option<String> {
+        self.symbols
+            .operand_name(self.address, field)
+            .map(str::to_string)
+    }
+}
+
+impl FormatContext for At<'_> {
+    fn memory_name(&self, address: Address) -> Option<String> {
+        self.operand_name(OperandField::Displacement)
+            .or_else(|| self.symbols.memory_name(address))
+    }
+
+    fn displacement(&self, _value: u16) -> Option<String> {
+        self.operand_name(OperandField::Displacement)
+    }
+
+    fn far_target(&self, _segment: u16, _offset: u16) -> Option<String> {
+        self.operand_name(OperandField::Pointer)
+    }
+
+    fn immediate(&self, value: u16) -> Option<String> {
+        if let Some(name) = self.operand_name(OperandField::Immediate) {
+            return Some(name);
+        }
+        match self.symbols.operand_display(self.address)? {
+            OperandDisplay::Hex => Some(format!("0x{value:x}")),
+            OperandDisplay::Decimal | OperandDisplay::Constant => None,
+            OperandDisplay::Offset => Some(
+                self.symbols
+                    .reference(self.address, Address(value))
+                    .unwrap_or_else(|| format!("0x{value:04x}")),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Layout};
+    use crate::functions::discover;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::symbol_file::ImportedSymbol;
+    use crate::symbols::Symbols;
+
+    #[test]
+    fn test_data_labels() {
+        let bin = [
+            0xA0, 0x07, 0x00, // mov al, [0x07]
+            0xA3, 0x00, 0x10, // mov [0x1000], ax
+            0xC3, // ret
+            0x41, 0x42, // data
+        ];
+        let program = trace(&Image::flat(&bin), &[Address(0)], &Layout::default());
+        let mut symbols = Symbols::new(&program, &discover(&program));
+
+        assert_eq!(symbols.label(Address(7)), Some("byte_0007"));
+        assert_eq!(symbols.data_width(Address(7)), Some(1));
+        assert_eq!(symbols.label(Address(0x1000)), None);
+        assert_eq!(
+            symbols.reference(Address(3), Address(0x1000)).as_deref(),
+            Some("word_1000")
+        );
+        assert_eq!(symbols.data_width(Address(0x1000)), Some(2));
+        assert_eq!(symbols.data_width(Address(0)), None);
+
+        // Only the symbol outside the image needs an `equ`.
+        let equates: Vec<(&str, Address)> = symbols.equates().collect();
+        assert_eq!(equates, vec![("word_1000", Address(0x1000))]);
+
+        // Imported names replace generated ones, keeping the width.
+        symbols.import(
+            Some(&program),
+            &[ImportedSymbol {
+                address: Address(7),
+                name: "letters".to_string(),
+                comment: None,
+            }],
+        );
+        assert_eq!(symbols.label(Address(7)), Some("letters"));
+        assert_eq!(symbols.data_width(Address(7)), Some(1));
+        symbols.set_data_width(Address(7), 2);
+        assert_eq!(symbols.data_width(Address(7)), Some(2));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::flow::{Item, Program};
+use crate::instruction::Instruction;
+use crate::memory::Address;
+use crate::operand::Operand;
+use crate::project::OperandDisplay;
+use crate::symbols::Symbols;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Formatter;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+    Execute,
+    /// The address is loaded as an immediate, e.g. `mov si, table`.
+    Offset,
+}
+
+impl std::fmt::Display for Access {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Access::Read => write!(f, "r"),
+            Access::Write => write!(f, "w"),
+            Access::ReadWrite => write!(f, "rw"),
+            Access::Execute => write!(f, "x"),
+            Access::Offset => write!(f, "o"),
+        }
+    }
+}
+
+pub struct Xref {
+    pub from: Address,
+    pub access: Access,
+}
+
+/// Every instruction referencing an address, keyed by the referenced address.
+pub struct Xrefs {
+    references: BTreeMap<Address, Vec<Xref>>,
+}
+
+impl Xrefs {
+    /// Collects references from the traced code. Immediates the user marked as offsets
+    /// always count and those marked as constants never do.
+    pub fn collect(program: &Program, symbols: &Symbols) -> Xrefs {
+        let mut references: BTreeMap<Address, Vec<Xref>> = BTreeMap::new();
+
+        for (from, instruction) in program.instructions() {
+            if let Some(target) = program.branch_target(from, instruction) {
+                references.entry(target).or_default().push(Xref {
+                    from,
+                    access: Access::Execute,
+                });
+            }
+
+            for (operand, access) in operand_accesses(instruction) {
+                if let Some(target) = operand.direct_address() {
+                    references
+                        .entry(target)
+                        .or_default()
+                        .push(Xref { from, access });
+                }
+            }
+        }
+
+        // Otherwise immediates only count as offsets when they point at something already
+        // known to be referenced or at data, since most small constants would match.
+        let known: BTreeSet<Address> = references.keys().copied().collect();
+        for (from, instruction) in program.instructions() {
+            let target = match symbols.operand_display(from) {
+                Some(OperandDisplay::Offset) => instruction.immediate().map(Address),
+                Some(OperandDisplay::Constant) => None,
+                _ => offset_immediate(instruction).filter(|target| {
+                    matches!(program.items.get(target), Some(Item::Data(_)))
+                        || known.contains(target)
+                }),
+            };
+            if let Some(target) = target {
+                references.entry(target).or_default().push(Xref {
+                    from,
+                    access: Access::Offset,
+                });
+            }
+        }
+
+        Xrefs { references }
+    }
+
+    pub fn to(&self, address: Address) -> &[Xref] {
+        self.references
+            .get(&address)
+            .map(Vec::as_slice)
+            .unwrap_or(&[])
+    }
+
+    /// Comment listing the references to `address`, like `; xref: sub_0120+0x14 (r)`.
+    pub fn comment(&self, address: Address, symbols: &Symbols) -> Option<String> {
+        let xrefs = self.to(address);
+        if xrefs.is_empty() {
+            return None;
+        }
+
+        let descriptions: Vec<String> = xrefs
+            .iter()
+            .map(|xref| format!("{} ({})", symbols.describe(xref.from), xref.access))
+            .collect();
+        Some(format!("; xref: {}", descriptions.join(", ")))
+    }
+
+    pub fn report(&self, symbols: &Symbols) -> String {
+        let mut report = String::new();
+
+        for (address, xrefs) in &self.references {
+            match symbols.label(*address) {
+                Some(name) => report.push_str(&format!("{:04X} {name}\n", address.0)),
+                None => report.push_str(&format!("{:04X}\n", address.0)),
+            }
+            for xref in xrefs {
+                report.push_str(&format!(
+                    "    {:04X} {} ({})\n",
+                    xref.from.0,
+                    symbols.describe(xref.from),
+                    xref.access
+                ));
+            }
+        }
+
+        report
+    }
+}
+
+fn operand_accesses(instruction: &Instruction) -> Vec<(&Operand, Access)> {
+    match instruction {
+        Instruction::Mov { dst, src, .. } => vec![(dst, Access::Write), (src, Access::Read)],
+        Instruction::Add { dst, src, .. }
+        | Instruction::Sub { dst, src, .. }
+        | Instruction::Or { dst, src, .. }
+        | Instruction::Adc { dst, src, .. }
+        | Instruction::Sbb { dst, src, .. }
+        | Instruction::Xor { dst, src, .. } => {
+            vec![(dst, Access::ReadWrite), (src, Access::Read)]
+        }
+        Instruction::MovSegment {
+            to_segment: true,
+            operand,
+            ..
+        } => vec![(operand, Access::Read)],
+        Instruction::MovSegment { operand, .. } => vec![(operand, Access::Write)],
+        Instruction::Idiv { src, .. } => vec![(src, Access::Read)],
+        Instruction::Shl { dst, .. } => vec![(dst, Access::ReadWrite)],
+        Instruction::Cmp { dst, src, .. } => vec![(dst, Access::Read), (src, Access::Read)],
+        Instruction::Push { src, .. } => vec![(src, Access::Read)],
+        Instruction::Pop { dst, .. } => vec![(dst, Access::Write)],
+        Instruction::CallIndirect { target, .. } | Instruction::JmpIndirect { target, .. } => {
+            vec![(target, Access::Read)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Immediate loaded into a 16-bit register, the usual way of taking an address.
+fn offset_immediate(instruction: &Instruction) -> Option<Address> {
+    match instruction {
+        Instruction::Mov {
+            dst: Operand::Register(reg),
+            src: Operand::Immediate16(value),
+            ..
+        } if reg.width() == 2 => Some(Address(*value)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::flow::{trace, Layout};
+    use crate::functions::discover;
+    use crate::image::Image;
+    use crate::memory::Address;
+    use crate::symbols::Symbols;
+    use crate::xref::{Access, Xrefs};
+
+    #[test]
+    fn test_collect() {
+        let mut image = Image::default();
+        image.insert(
+            0,
+            &[
+                0xA1, 0x20, 0x00, // mov ax, [0x20]
+                0x01, 0x06, 0x22, 0x00, // add [0x22], ax
+                0xA3, 0x24, 0x00, // mov [0x24], ax
+                0xBE, 0x20, 0x00, // mov si, 0x20
+                0xBF, 0x03, 0x00, // mov di, 3
+                0xEB, 0xFE, // jmp $
+            ],
+        );
+        image.insert(0x20, &[1, 0, 2, 0, 3, 0]);
+        let program = trace(&image, &[Address(0)], &Layout::default());
+        let symbols = Symbols::new(&program, &discover(&program));
+        let xrefs = Xrefs::collect(&program, &symbols);
+
+        let to = |address: u16| -> Vec<(u16, Access)> {
+            xrefs
+                .to(Address(address))
+                .iter()
+                .map(|xref| (xref.from.0, xref.access))
+                .collect()
+        };
+        assert_eq!(to(0x20), vec![(0, Access::Read), (0xA, Access::Offset)]);
+        assert_eq!(to(0x22), vec![(3, Access::ReadWrite)]);
+        assert_eq!(to(0x24), vec![(7, Access::Write)]);
+        assert_eq!(to(0x10), vec![(0x10, Access::Execute)]);
+        // 3 is only a constant: it is neither data nor referenced otherwise.
+        assert_eq!(to(3), vec![]);
+
+        assert_eq!(
+            xrefs.comment(Address(0x20), &symbols).as_deref(),
+            Some("; xref: sub_0000 (r), sub_0000+0xa (o)")
+        );
+    }
+}